@@ -0,0 +1,188 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use sha2::{Digest, Sha256};
+
+pub const ASSET_STORE_DIR: &str = "image_cache";
+
+/// target encoding of stored images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImageFormatChoice {
+    /// keep whatever the source gave us
+    Original,
+    Png,
+    Jpeg,
+    Webp,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    pub format: ImageFormatChoice,
+    /// 1..=100, used by jpeg and webp
+    pub quality: u8,
+    /// downscale so neither side exceeds this many pixels
+    pub max_dimension: Option<u32>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            format: ImageFormatChoice::Original,
+            quality: 85,
+            max_dimension: None,
+        }
+    }
+}
+
+/// Content-addressed store for images and other binary assets, shared by every document.
+///
+/// Files live at `{root}/{hash[0..2]}/{hash[0..16]}.{ext}`, the hash is taken over the
+/// stored (already converted) bytes, so the same picture used in many documents or many
+/// times in one document is stored once.
+pub struct AssetStore {
+    root: PathBuf,
+    options: ImageOptions,
+}
+
+impl AssetStore {
+    pub fn new(root: impl Into<PathBuf>, options: ImageOptions) -> Self {
+        Self {
+            root: root.into(),
+            options,
+        }
+    }
+
+    /// store an image, converting and downscaling it according to the options first
+    /// ext is the extension matching `data` as given (png, jpg, gif, svg...)
+    pub fn store_image(&self, data: &[u8], ext: &str) -> Option<PathBuf> {
+        match self.process_image(data, ext) {
+            Some((processed, processed_ext)) => self.store(&processed, processed_ext),
+            None => self.store(data, ext),
+        }
+    }
+
+    /// store bytes as they are
+    pub fn store(&self, data: &[u8], ext: &str) -> Option<PathBuf> {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let hash = format!("{:x}", hasher.finalize());
+
+        let dir = self.root.join(&hash[0..2]);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            println!("Failed to create directory: {:?}", e);
+            return None;
+        }
+
+        let path = dir.join(format!("{}.{}", &hash[0..16], ext));
+        if path.exists() {
+            println!("Reused asset: {:?}", path);
+            return Some(path);
+        }
+        if let Err(e) = std::fs::write(&path, data) {
+            println!("Failed to write asset: {:?}", e);
+            return None;
+        }
+
+        println!("Saved asset to: {:?}", path);
+        Some(path)
+    }
+
+    /// None means the bytes should be stored untouched
+    fn process_image(&self, data: &[u8], ext: &str) -> Option<(Vec<u8>, &'static str)> {
+        // vector drawings don't need it, and re-encoding a gif would drop its animation
+        if matches!(ext, "svg" | "gif") {
+            return None;
+        }
+        if self.options.format == ImageFormatChoice::Original
+            && self.options.max_dimension.is_none()
+        {
+            return None;
+        }
+
+        let mut img = match decode_oriented(data) {
+            Ok(img) => img,
+            Err(e) => {
+                println!("Failed to decode image, storing as is: {:?}", e);
+                return None;
+            }
+        };
+
+        let mut resized = false;
+        if let Some(max) = self.options.max_dimension
+            && (img.width() > max || img.height() > max)
+        {
+            img = img.resize(max, max, FilterType::Lanczos3);
+            resized = true;
+        }
+
+        let format = match self.options.format {
+            ImageFormatChoice::Original if !resized => return None,
+            ImageFormatChoice::Original => match image::guess_format(data) {
+                Ok(ImageFormat::Jpeg) => ImageFormatChoice::Jpeg,
+                Ok(ImageFormat::WebP) => ImageFormatChoice::Webp,
+                _ => ImageFormatChoice::Png,
+            },
+            format => format,
+        };
+
+        match encode(&img, format, self.options.quality) {
+            Ok(encoded) => Some(encoded),
+            Err(e) => {
+                println!("Failed to encode image, storing as is: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// decode and apply the exif orientation, re-encoded output carries no exif anymore
+fn decode_oriented(data: &[u8]) -> Result<DynamicImage, Box<dyn std::error::Error>> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+    Ok(img)
+}
+
+fn encode(
+    img: &DynamicImage,
+    format: ImageFormatChoice,
+    quality: u8,
+) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error>> {
+    let quality = quality.clamp(1, 100);
+    let mut out = Vec::new();
+    match format {
+        ImageFormatChoice::Original | ImageFormatChoice::Png => {
+            img.write_to(&mut Cursor::new(&mut out), ImageFormat::Png)?;
+            Ok((out, "png"))
+        }
+        ImageFormatChoice::Jpeg => {
+            // jpeg has no alpha channel
+            let rgb = img.to_rgb8();
+            JpegEncoder::new_with_quality(&mut out, quality).encode_image(&rgb)?;
+            Ok((out, "jpg"))
+        }
+        ImageFormatChoice::Webp => {
+            let rgba = img.to_rgba8();
+            let encoded =
+                webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(quality as f32);
+            Ok((encoded.to_vec(), "webp"))
+        }
+    }
+}
+
+/// put one copy of `source` at `dest`, hard linking into the store when the filesystem allows it
+pub fn link_or_copy(source: &Path, dest: &Path) -> std::io::Result<()> {
+    if dest.exists() {
+        return Ok(());
+    }
+    if std::fs::hard_link(source, dest).is_err() {
+        std::fs::copy(source, dest)?;
+    }
+    Ok(())
+}
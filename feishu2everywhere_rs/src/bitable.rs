@@ -0,0 +1,383 @@
+use std::path::Path;
+
+use chrono::{TimeZone, Utc};
+use serde_json::{Map, Value, json};
+
+use crate::feishu_api::{self, FeishuClient};
+use crate::sheet;
+use crate::spreadsheet;
+
+// field types, see
+// https://open.feishu.cn/document/server-docs/docs/bitable-v1/bitable-structure
+pub const FIELD_TEXT: u32 = 1;
+pub const FIELD_NUMBER: u32 = 2;
+pub const FIELD_SINGLE_SELECT: u32 = 3;
+pub const FIELD_MULTI_SELECT: u32 = 4;
+pub const FIELD_DATETIME: u32 = 5;
+pub const FIELD_CHECKBOX: u32 = 7;
+pub const FIELD_USER: u32 = 11;
+pub const FIELD_PHONE: u32 = 13;
+pub const FIELD_URL: u32 = 15;
+pub const FIELD_ATTACHMENT: u32 = 17;
+pub const FIELD_SINGLE_LINK: u32 = 18;
+pub const FIELD_LOOKUP: u32 = 19;
+pub const FIELD_FORMULA: u32 = 20;
+pub const FIELD_DUPLEX_LINK: u32 = 21;
+pub const FIELD_LOCATION: u32 = 22;
+pub const FIELD_GROUP_CHAT: u32 = 23;
+pub const FIELD_CREATED_TIME: u32 = 1001;
+pub const FIELD_MODIFIED_TIME: u32 = 1002;
+pub const FIELD_CREATED_USER: u32 = 1003;
+pub const FIELD_MODIFIED_USER: u32 = 1004;
+pub const FIELD_AUTO_NUMBER: u32 = 1005;
+
+/// what a typed value of the field looks like once normalized by `typed_value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Text,
+    Number,
+    Bool,
+    /// RFC 3339 string
+    DateTime,
+    /// array of strings
+    List,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitableField {
+    pub field_id: String,
+    pub name: String,
+    pub field_type: u32,
+    /// type specific settings as given by the api (select options, number format...)
+    pub property: Value,
+}
+
+impl BitableField {
+    pub fn type_name(&self) -> &'static str {
+        match self.field_type {
+            FIELD_TEXT => "text",
+            FIELD_NUMBER => "number",
+            FIELD_SINGLE_SELECT => "single_select",
+            FIELD_MULTI_SELECT => "multi_select",
+            FIELD_DATETIME => "datetime",
+            FIELD_CHECKBOX => "checkbox",
+            FIELD_USER => "user",
+            FIELD_PHONE => "phone",
+            FIELD_URL => "url",
+            FIELD_ATTACHMENT => "attachment",
+            FIELD_SINGLE_LINK => "single_link",
+            FIELD_LOOKUP => "lookup",
+            FIELD_FORMULA => "formula",
+            FIELD_DUPLEX_LINK => "duplex_link",
+            FIELD_LOCATION => "location",
+            FIELD_GROUP_CHAT => "group_chat",
+            FIELD_CREATED_TIME => "created_time",
+            FIELD_MODIFIED_TIME => "modified_time",
+            FIELD_CREATED_USER => "created_user",
+            FIELD_MODIFIED_USER => "modified_user",
+            FIELD_AUTO_NUMBER => "auto_number",
+            _ => "unknown",
+        }
+    }
+
+    pub fn value_kind(&self) -> ValueKind {
+        match self.field_type {
+            FIELD_NUMBER => ValueKind::Number,
+            FIELD_CHECKBOX => ValueKind::Bool,
+            FIELD_DATETIME | FIELD_CREATED_TIME | FIELD_MODIFIED_TIME => ValueKind::DateTime,
+            FIELD_MULTI_SELECT | FIELD_USER | FIELD_ATTACHMENT | FIELD_SINGLE_LINK
+            | FIELD_DUPLEX_LINK | FIELD_GROUP_CHAT | FIELD_CREATED_USER | FIELD_MODIFIED_USER => {
+                ValueKind::List
+            }
+            // formula and lookup results can be anything, keep their text
+            _ => ValueKind::Text,
+        }
+    }
+
+    /// names of the select options, empty for other types
+    pub fn options(&self) -> Vec<String> {
+        self.property
+            .get("options")
+            .and_then(|o| o.as_array())
+            .map(|options| {
+                options
+                    .iter()
+                    .filter_map(|o| o.get("name").and_then(|n| n.as_str()))
+                    .map(|n| n.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BitableRecord {
+    pub record_id: String,
+    /// one typed value per field, in field order, Null when empty
+    pub values: Vec<Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitableTable {
+    pub table_id: String,
+    pub name: String,
+    pub fields: Vec<BitableField>,
+    pub records: Vec<BitableRecord>,
+}
+
+/// whether the url alone says it is a base, wiki nodes need `--mode bitable`
+pub fn is_bitable_url(url: &str) -> bool {
+    feishu_api::parse_doc_url(url).is_some_and(|(kind, _)| kind == "base")
+}
+
+/// app token of a /base/<token> url, or of the bitable behind a /wiki/<token> node
+async fn resolve_app_token(
+    client: &FeishuClient,
+    url: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (kind, token) =
+        feishu_api::parse_doc_url(url).ok_or(format!("not a feishu url: {}", url))?;
+    match kind.as_str() {
+        "base" => Ok(token),
+        "wiki" => {
+            let (obj_type, obj_token) = client.resolve_wiki_node(&token).await?;
+            if obj_type != "bitable" {
+                return Err(format!("wiki node is a {}, not a bitable", obj_type).into());
+            }
+            Ok(obj_token)
+        }
+        _ => Err(format!("{} is not a bitable url", kind).into()),
+    }
+}
+
+/// Fetch every table of the base behind `url`: field schema and all records
+pub async fn fetch_bitable(
+    client: &FeishuClient,
+    url: &str,
+) -> Result<Vec<BitableTable>, Box<dyn std::error::Error>> {
+    let app_token = resolve_app_token(client, url).await?;
+    println!("api: fetching tables of base {}", app_token);
+
+    let table_items = client
+        .get_all_items(
+            &format!("/open-apis/bitable/v1/apps/{}/tables", app_token),
+            &[],
+        )
+        .await?;
+
+    let mut tables = vec![];
+    for table_item in table_items {
+        let str_of = |v: &Value, name: &str| {
+            v.get(name)
+                .and_then(|s| s.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let table_id = str_of(&table_item, "table_id");
+        let name = str_of(&table_item, "name");
+        let table_path = format!(
+            "/open-apis/bitable/v1/apps/{}/tables/{}",
+            app_token, table_id
+        );
+
+        let fields = client
+            .get_all_items(&format!("{}/fields", table_path), &[])
+            .await?
+            .iter()
+            .map(|f| BitableField {
+                field_id: str_of(f, "field_id"),
+                name: str_of(f, "field_name"),
+                field_type: f.get("type").and_then(|t| t.as_u64()).unwrap_or(0) as u32,
+                property: f.get("property").cloned().unwrap_or(Value::Null),
+            })
+            .collect::<Vec<_>>();
+
+        // records are keyed by field name by default
+        let records = client
+            .get_all_items(&format!("{}/records", table_path), &[])
+            .await?
+            .iter()
+            .map(|r| {
+                let raw = r.get("fields").cloned().unwrap_or(Value::Null);
+                BitableRecord {
+                    record_id: str_of(r, "record_id"),
+                    values: fields
+                        .iter()
+                        .map(|f| {
+                            raw.get(&f.name)
+                                .map(|v| typed_value(f, v))
+                                .unwrap_or(Value::Null)
+                        })
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        println!(
+            "api: table {} has {} fields, {} records",
+            name,
+            fields.len(),
+            records.len()
+        );
+        tables.push(BitableTable {
+            table_id,
+            name,
+            fields,
+            records,
+        });
+    }
+    Ok(tables)
+}
+
+/// plain text of the loosely typed api values (segments, objects with text/name...)
+fn raw_text(raw: &Value) -> String {
+    match raw {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Array(items) => items.iter().map(raw_text).collect::<Vec<_>>().join(""),
+        Value::Object(obj) => ["text", "name", "full_address", "link", "value"]
+            .iter()
+            .find_map(|key| obj.get(*key))
+            .map(raw_text)
+            .unwrap_or_default(),
+    }
+}
+
+fn raw_list(raw: &Value) -> Vec<String> {
+    match raw {
+        Value::Array(items) => items.iter().flat_map(raw_list).collect(),
+        // links: {"link_record_ids": [...]}
+        Value::Object(obj) if obj.contains_key("link_record_ids") => {
+            raw_list(&obj["link_record_ids"])
+        }
+        Value::Object(obj) if obj.contains_key("en_name") && !obj.contains_key("name") => {
+            vec![raw_text(&obj["en_name"])]
+        }
+        Value::Null => vec![],
+        other => vec![raw_text(other)],
+    }
+}
+
+/// normalize an api cell value to the shape given by the field's `ValueKind`
+pub fn typed_value(field: &BitableField, raw: &Value) -> Value {
+    // formula and lookup wrap their result as {"type": .., "value": [..]}
+    let raw = match raw.get("value") {
+        Some(inner) if matches!(field.field_type, FIELD_FORMULA | FIELD_LOOKUP) => inner,
+        _ => raw,
+    };
+    if raw.is_null() {
+        return Value::Null;
+    }
+    match field.value_kind() {
+        ValueKind::Number => match raw {
+            Value::Number(_) => raw.clone(),
+            other => raw_text(other)
+                .parse::<f64>()
+                .map(|n| json!(n))
+                .unwrap_or(Value::Null),
+        },
+        ValueKind::Bool => Value::Bool(raw.as_bool().unwrap_or(false)),
+        ValueKind::DateTime => raw
+            .as_i64()
+            .and_then(|ms| Utc.timestamp_millis_opt(ms).single())
+            .map(|t| Value::String(t.to_rfc3339()))
+            .unwrap_or(Value::Null),
+        ValueKind::List => Value::Array(raw_list(raw).into_iter().map(Value::String).collect()),
+        ValueKind::Text => Value::String(raw_text(raw)),
+    }
+}
+
+/// text shown in csv cells, lists are joined with ", "
+pub fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(value_to_text)
+            .collect::<Vec<_>>()
+            .join(", "),
+        other => raw_text(other),
+    }
+}
+
+fn tables_to_json(tables: &[BitableTable]) -> Value {
+    let tables = tables
+        .iter()
+        .map(|table| {
+            let fields = table
+                .fields
+                .iter()
+                .map(|f| {
+                    json!({
+                        "field_id": f.field_id,
+                        "name": f.name,
+                        "type": f.field_type,
+                        "type_name": f.type_name(),
+                        "options": f.options(),
+                        "property": f.property,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let records = table
+                .records
+                .iter()
+                .map(|r| {
+                    let values = table
+                        .fields
+                        .iter()
+                        .zip(&r.values)
+                        .map(|(f, v)| (f.name.clone(), v.clone()))
+                        .collect::<Map<_, _>>();
+                    json!({ "record_id": r.record_id, "fields": values })
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "table_id": table.table_id,
+                "name": table.name,
+                "fields": fields,
+                "records": records,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "tables": tables })
+}
+
+/// Write `{n}_{table}.csv` per table into `out_dir` and the typed dump to `json_path`,
+/// plus a sqlite database when `sqlite_path` is set
+pub fn export_bitable(
+    tables: &[BitableTable],
+    out_dir: &Path,
+    json_path: &Path,
+    sqlite_path: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(out_dir)?;
+    for (index, table) in tables.iter().enumerate() {
+        let mut rows = vec![table.fields.iter().map(|f| f.name.clone()).collect()];
+        rows.extend(
+            table
+                .records
+                .iter()
+                .map(|r| r.values.iter().map(value_to_text).collect::<Vec<_>>()),
+        );
+        let csv_path = out_dir.join(format!(
+            "{}_{}.csv",
+            index,
+            spreadsheet::file_stem(&table.name)
+        ));
+        std::fs::write(&csv_path, sheet::rows_to_csv(&rows))?;
+        println!("Saved table to: {:?}", csv_path);
+    }
+
+    std::fs::write(
+        json_path,
+        serde_json::to_string_pretty(&tables_to_json(tables))?,
+    )?;
+    println!("Saved typed dump to: {:?}", json_path);
+
+    if let Some(sqlite_path) = sqlite_path {
+        crate::to_sqlite::export_tables_to_sqlite(tables, sqlite_path)?;
+        println!("Saved database to: {:?}", sqlite_path);
+    }
+    Ok(())
+}
@@ -1,14 +1,16 @@
 use std::path::PathBuf;
 
-use base64;
-use sha2::{Digest, Sha256};
+use base64::{Engine as _, engine::general_purpose};
+use serde::{Deserialize, Serialize};
 use thirtyfour::{By, WebDriver, WebElement};
 
-use crate::{BlockId, webelement_ext::WebElementExt};
+use crate::assets::AssetStore;
+use crate::diagram::{self, DiagramKind};
+use crate::outline::OutlineNode;
+use crate::{BlockId, sheet, webelement_ext::WebElementExt};
 
-const IMAGE_CACHE_DIR: &str = "image_cache";
-
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TextSlice {
     pub text: String,
     pub is_bold: bool,
@@ -23,14 +25,16 @@ pub struct TextSlice {
 //     shown_name: String,
 // }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListOne {
     pub done: Option<bool>,
     pub headline: Vec<TextSlice>,
+    #[serde(default)]
     pub following: Vec<Block>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum HeadLevel {
     H1,
     H2,
@@ -73,14 +77,24 @@ impl HeadLevel {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ListType {
     Ordered,
     Unordered,
     Task,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Block {
     /// markdown format
     Text(Vec<TextSlice>),
@@ -94,11 +108,34 @@ pub enum Block {
     },
     Image {
         cached_path: PathBuf,
+        /// alt text set on the image, if any
+        alt: Option<String>,
+        /// caption shown under the image
+        caption: Option<String>,
+        /// display width in css pixels, None when the image is shown at full width
+        width: Option<u32>,
+        align: Option<ImageAlign>,
     },
     Code {
         language: String,
         code: String,
     },
+    /// embedded sheet or bitable view, row-major cell text, first row is the header
+    Sheet {
+        cells: Vec<Vec<String>>,
+    },
+    /// whiteboard, mind map, flowchart or diagram rendered to an svg or png
+    Diagram {
+        kind: DiagramKind,
+        cached_path: PathBuf,
+        /// node tree, only for mind maps
+        outline: Option<OutlineNode>,
+    },
+    /// highlighted box holding other blocks, led by an emoji
+    Callout {
+        emoji: Option<String>,
+        children: Vec<Block>,
+    },
 }
 
 async fn try_new_heading(e: &WebElement) -> Option<Block> {
@@ -277,6 +314,54 @@ async fn try_new_code(e: &WebElement) -> Option<Block> {
     return ret;
 }
 
+async fn try_new_sheet(driver: &WebDriver, e: &WebElement) -> Option<Block> {
+    // embedded sheet: .docx-sheet-block
+    // embedded bitable view: .docx-bitable-block
+    let class_name = e.class_name().await.ok()??;
+    if !class_name.contains("docx-sheet-block") && !class_name.contains("docx-bitable-block") {
+        return None;
+    }
+
+    let Some(cells) = sheet::scrape_virtual_grid(driver, e).await else {
+        println!("sheet block has no readable cells");
+        return None;
+    };
+
+    let ret = Block::Sheet { cells };
+    println!("extracted sheet: {:?}", ret);
+    Some(ret)
+}
+
+async fn try_new_callout(e: &WebElement) -> Option<Block> {
+    // .docx-callout-block, the content blocks are collected as its children
+    let class_name = e.class_name().await.ok()??;
+    if !class_name.contains("docx-callout-block") {
+        return None;
+    }
+    let mut emoji = None;
+    if let Ok(found) = e
+        .find_all(By::Css(".callout-block-emoji, [class*=callout-emoji]"))
+        .await
+        && let Some(emoji_elem) = found.first()
+    {
+        // emojis render either as text or as an image with the emoji as alt
+        let mut text = emoji_elem.text().await.unwrap_or_default();
+        if text.trim().is_empty()
+            && let Ok(img) = emoji_elem.find(By::Css("img")).await
+        {
+            text = img.attr("alt").await.ok().flatten().unwrap_or_default();
+        }
+        emoji = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+    }
+
+    let ret = Block::Callout {
+        emoji,
+        children: vec![],
+    };
+    println!("extracted callout: {:?}", ret);
+    Some(ret)
+}
+
 #[derive(Debug)]
 pub enum OneOf<A, B> {
     A(A),
@@ -399,20 +484,20 @@ async fn try_new_list(e: &WebElement) -> Option<OneOf<Block, (ListType, ListOne)
     None
 }
 
-async fn try_new_image(driver: &WebDriver, ctx_str: &str, e: &WebElement) -> Option<Block> {
+async fn try_new_image(driver: &WebDriver, assets: &AssetStore, e: &WebElement) -> Option<Block> {
     // direct: .block-comment > .docx-block-loading-container
     let container = e
         .get_direct_children(".block-comment > .docx-block-loading-container")
         .await;
 
-    let canvas_png = if !container.is_empty() {
+    let (image_data, ext, rendered) = if !container.is_empty() {
         // find_all: canvas
         let canvas_result = e.find_all(By::Css("canvas")).await;
         if canvas_result.is_err() || canvas_result.as_ref().unwrap().is_empty() {
             return None;
         }
 
-        let canvas = &canvas_result.unwrap()[0];
+        let canvas = canvas_result.unwrap()[0].clone();
 
         //  # get the canvas as a PNG base64 string
         //  canvas_base64 = driver.execute_script("return arguments[0].toDataURL('image/png').substring(21);", canvas)
@@ -431,7 +516,7 @@ async fn try_new_image(driver: &WebDriver, ctx_str: &str, e: &WebElement) -> Opt
         let canvas_base64 = canvas_base64.json().as_str().unwrap();
         println!("canvas_base64: {}", canvas_base64);
         let canvas_png = base64::decode(canvas_base64).unwrap();
-        canvas_png
+        (canvas_png, "png", canvas)
     } else {
         if e.class_name()
             .await
@@ -444,18 +529,14 @@ async fn try_new_image(driver: &WebDriver, ctx_str: &str, e: &WebElement) -> Opt
             if img_result.is_err() || img_result.as_ref().unwrap().is_empty() {
                 return None;
             }
-            let img = &img_result.unwrap()[0];
-
-            // execute script
-            //  const img = arguments[0];
-            //  const canvas = document.createElement('canvas');
-            //  canvas.width = img.naturalWidth;
-            //  canvas.height = img.naturalHeight;
-            //  const ctx = canvas.getContext('2d');
-            //  ctx.drawImage(img, 0, 0);
-            //  return canvas.toDataURL();
-            let canvas_base64 = unsafe {
-                driver
+            let img = img_result.unwrap()[0].clone();
+
+            // the original file keeps gif animation, jpeg encoding and exif orientation,
+            // re-rendering through a canvas is only the fallback (tainted or revoked blob urls)
+            if let Some((original, ext)) = fetch_original_image(driver, &img).await {
+                (original, ext, img)
+            } else {
+                let canvas_base64 = driver
                     .execute(
                         "
                         const img = arguments[0];
@@ -469,50 +550,162 @@ async fn try_new_image(driver: &WebDriver, ctx_str: &str, e: &WebElement) -> Opt
                         vec![img.to_json().unwrap()],
                     )
                     .await
-                    .unwrap()
-            };
-            let canvas_base64 = canvas_base64.json().as_str().unwrap();
-            let canvas_png = base64::decode(canvas_base64).unwrap();
-            canvas_png
-            // let img_url = img.get_attribute("src").await.unwrap().unwrap();
-            // let img_data = reqwest::get(img_url).await.unwrap().bytes().await.unwrap();
-            // img_data.to_vec()
+                    .ok()?;
+                let canvas_base64 = canvas_base64.json().as_str()?;
+                let canvas_png = general_purpose::STANDARD.decode(canvas_base64).ok()?;
+                (canvas_png, "png", img)
+            }
         } else {
             return None;
         }
     };
 
-    // Create a hash from the image data for a unique filename
-    let mut hasher = Sha256::new();
-    hasher.update(&canvas_png);
-    let hash = format!("{:x}", unsafe { hasher.finalize() });
+    let image_path = assets.store_image(&image_data, ext)?;
+    let (alt, width, align) = read_image_layout(driver, e, &rendered).await;
+    let caption = read_image_caption(e).await;
+
+    let ret = Block::Image {
+        cached_path: image_path,
+        alt,
+        caption,
+        width,
+        align,
+    };
+    println!("extracted image: {:?}", ret);
+    Some(ret)
+}
 
-    // save to {IMAGE_CACHE_DIR}/{ctx_str}/{SUMMARY_HASH}.png
-    let cache_dir = std::path::Path::new(IMAGE_CACHE_DIR).join(ctx_str);
-    if !cache_dir.exists() {
-        if let Err(e) = std::fs::create_dir_all(&cache_dir) {
-            println!("Failed to create directory: {:?}", e);
+/// download arguments[0].src inside the page, so the document session cookies apply
+/// calls back with [base64, content-type] or null
+const FETCH_IMAGE_JS: &str = r#"
+const done = arguments[arguments.length - 1];
+const img = arguments[0];
+fetch(img.currentSrc || img.src, { credentials: 'include' })
+    .then((resp) => {
+        if (!resp.ok) throw new Error(resp.status);
+        const type = resp.headers.get('content-type') || '';
+        return resp.blob().then((blob) => {
+            const reader = new FileReader();
+            reader.onload = () => done([reader.result.split(',')[1] || '', type]);
+            reader.onerror = () => done(null);
+            reader.readAsDataURL(blob);
+        });
+    })
+    .catch(() => done(null));
+"#;
+
+/// download the full resolution bytes behind an img through the page session
+/// returns (bytes, extension), None when only the rendered pixels are reachable
+pub async fn fetch_original_image(
+    driver: &WebDriver,
+    img: &WebElement,
+) -> Option<(Vec<u8>, &'static str)> {
+    let ret = match driver
+        .execute_async(FETCH_IMAGE_JS, vec![img.to_json().unwrap()])
+        .await
+    {
+        Ok(ret) => ret,
+        Err(err) => {
+            println!("err fetch original image: {:?}", err);
             return None;
         }
-    }
-
-    let image_path = cache_dir.join(format!("{}.png", &hash[0..16]));
-    if let Err(e) = std::fs::write(&image_path, canvas_png) {
-        println!("Failed to write image: {:?}", e);
+    };
+    let ret = ret.json();
+    let data = general_purpose::STANDARD
+        .decode(ret.get(0)?.as_str()?)
+        .ok()?;
+    if data.is_empty() {
         return None;
     }
 
-    println!("Saved image to: {:?}", image_path);
+    // trust the bytes over the header, cdn often serves images as octet-stream
+    let ext = match image::guess_format(&data) {
+        Ok(image::ImageFormat::Jpeg) => "jpg",
+        Ok(image::ImageFormat::Png) => "png",
+        Ok(image::ImageFormat::Gif) => "gif",
+        Ok(image::ImageFormat::WebP) => "webp",
+        Ok(image::ImageFormat::Bmp) => "bmp",
+        _ if ret.get(1)?.as_str()?.starts_with("image/svg") => "svg",
+        _ => {
+            println!("fetched image has unknown format, fall back to canvas");
+            return None;
+        }
+    };
+    Some((data, ext))
+}
 
-    Some(Block::Image {
-        cached_path: image_path,
-    })
+/// [alt, width, align] of the rendered img/canvas arguments[1] inside block arguments[0],
+/// width and align are null when the image spans the whole block
+const IMAGE_LAYOUT_JS: &str = r#"
+const block = arguments[0].getBoundingClientRect();
+const shown = arguments[1].getBoundingClientRect();
+const alt = (arguments[1].getAttribute('alt') || '').trim();
+if (shown.width <= 0 || shown.width >= block.width * 0.95) return [alt, null, null];
+const leftGap = shown.left - block.left, rightGap = block.right - shown.right;
+const align = Math.abs(leftGap - rightGap) < 8 ? 'center' : (leftGap < rightGap ? 'left' : 'right');
+return [alt, Math.round(shown.width), align];
+"#;
+
+/// whether `alt` is an image file name like `IMG_0012.PNG`
+fn is_image_file_name(alt: &str) -> bool {
+    let Some((name, extension)) = alt.rsplit_once('.') else {
+        return false;
+    };
+    !name.is_empty()
+        && matches!(
+            extension.to_ascii_lowercase().as_str(),
+            "png" | "jpg" | "jpeg" | "gif" | "webp" | "svg" | "bmp" | "heic"
+        )
+}
+
+pub async fn read_image_layout(
+    driver: &WebDriver,
+    e: &WebElement,
+    rendered: &WebElement,
+) -> (Option<String>, Option<u32>, Option<ImageAlign>) {
+    let Ok(ret) = driver
+        .execute(
+            IMAGE_LAYOUT_JS,
+            vec![e.to_json().unwrap(), rendered.to_json().unwrap()],
+        )
+        .await
+    else {
+        return (None, None, None);
+    };
+    let ret = ret.json();
+    // feishu fills alt with the uploaded file name when the user gave none, it's no description
+    let alt = ret
+        .get(0)
+        .and_then(|v| v.as_str())
+        .filter(|alt| !alt.is_empty() && !is_image_file_name(alt))
+        .map(|alt| alt.to_string());
+    let width = ret.get(1).and_then(|v| v.as_u64()).map(|w| w as u32);
+    let align = match ret.get(2).and_then(|v| v.as_str()) {
+        Some("left") => Some(ImageAlign::Left),
+        Some("center") => Some(ImageAlign::Center),
+        Some("right") => Some(ImageAlign::Right),
+        _ => None,
+    };
+    (alt, width, align)
+}
+
+async fn read_image_caption(e: &WebElement) -> Option<String> {
+    // caption is an editable line under the image: .image-caption / .docx-image-caption
+    let captions = e
+        .find_all(By::Css(
+            ".image-caption, .docx-image-caption, .image-block-caption",
+        ))
+        .await
+        .ok()?;
+    let caption = captions.first()?.text().await.ok()?;
+    let caption = caption.trim();
+    (!caption.is_empty()).then(|| caption.to_string())
 }
 
 impl Block {
     pub async fn new_by_element(
         driver: &WebDriver,
-        ctx_str: &str,
+        assets: &AssetStore,
         e: &WebElement,
     ) -> Option<OneOf<Block, (ListType, ListOne)>> {
         // head case
@@ -520,6 +713,11 @@ impl Block {
             return Some(OneOf::A(block));
         }
 
+        // callout case, its content comes as child blocks
+        if let Some(block) = try_new_callout(e).await {
+            return Some(OneOf::A(block));
+        }
+
         // text case
         if let Some(block) = try_new_text(e).await {
             return Some(OneOf::A(block));
@@ -530,8 +728,18 @@ impl Block {
             return Some(OneOf::A(block));
         }
 
+        // sheet case, before image since sheets may also render into a canvas
+        if let Some(block) = try_new_sheet(driver, e).await {
+            return Some(OneOf::A(block));
+        }
+
+        // whiteboard, mind map and diagram case, they also render into canvases
+        if let Some(block) = diagram::try_new_diagram(driver, assets, e).await {
+            return Some(OneOf::A(block));
+        }
+
         // image case
-        if let Some(block) = try_new_image(driver, ctx_str, e).await {
+        if let Some(block) = try_new_image(driver, assets, e).await {
             return Some(OneOf::A(block));
         }
 
@@ -0,0 +1,198 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thirtyfour::{By, WebDriver};
+
+use crate::block::Block;
+
+/// how comment threads show up in markdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CommentStyle {
+    /// comments are not collected
+    #[default]
+    None,
+    /// a footnote reference on the commented block, the thread in the footnote
+    Footnotes,
+    /// every thread in a trailing "Discussion" section
+    Discussion,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Comment {
+    pub author: String,
+    /// as shown by feishu, rfc 3339 when it comes from the api
+    pub time: Option<String>,
+    pub text: String,
+}
+
+/// One comment thread, the first comment opened it and the rest are replies
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommentThread {
+    /// the commented text, empty for comments on the whole document
+    pub anchor: String,
+    pub resolved: bool,
+    pub comments: Vec<Comment>,
+    /// index of the top level block the anchor was found in
+    pub block_index: Option<usize>,
+}
+
+/// open the comment side panel, returns whether a toggle was found
+const OPEN_COMMENT_PANEL_JS: &str = r#"
+const toggle = document.querySelector(
+    '.docx-comment-entry, [class*=comment-entry], [data-e2e*=comment] button, .suite-comment-btn');
+if (!toggle) return false;
+toggle.click();
+return true;
+"#;
+
+/// read every thread card of the side panel
+/// returns [{anchor, resolved, comments: [{author, time, text}]}]
+const READ_COMMENT_PANEL_JS: &str = r#"
+const text = (root, sel) => {
+    const e = root.querySelector(sel);
+    return e ? (e.innerText || '').trim() : '';
+};
+const cards = document.querySelectorAll('.comment-card, [class*=comment-card]:not([class*=comment-card] *)');
+return Array.from(cards).map((card) => {
+    const cls = typeof card.className === 'string' ? card.className : '';
+    const items = card.querySelectorAll('.comment-item, [class*=reply-item], [class*=comment-item]');
+    return {
+        anchor: text(card, '.comment-quote, [class*=quote]'),
+        resolved: /resolved|solved/.test(cls) || !!card.querySelector('[class*=resolved-tag], [class*=solved]'),
+        comments: Array.from(items).map((item) => ({
+            author: text(item, '[class*=user-name], [class*=author], [class*=name]'),
+            time: text(item, 'time, [class*=time]') || null,
+            text: text(item, '[class*=content], [class*=text]'),
+        })).filter((c) => c.text.length > 0),
+    };
+}).filter((t) => t.comments.length > 0);
+"#;
+
+/// Open the side panel and read every comment thread of the document in `driver`.
+/// Threads are not attached to blocks yet, see `attach_to_blocks`.
+pub async fn collect_page_comments(driver: &WebDriver) -> Vec<CommentThread> {
+    match driver.execute(OPEN_COMMENT_PANEL_JS, vec![]).await {
+        Ok(ret) if ret.json().as_bool() == Some(true) => {}
+        _ => {
+            println!("comment panel toggle not found, no comments collected");
+            return vec![];
+        }
+    }
+    // the panel loads its threads after opening
+    let start = std::time::Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        if let Ok(cards) = driver.find_all(By::Css("[class*=comment-card]")).await
+            && !cards.is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    let ret = match driver.execute(READ_COMMENT_PANEL_JS, vec![]).await {
+        Ok(ret) => ret,
+        Err(err) => {
+            println!("err read comments: {:?}", err);
+            return vec![];
+        }
+    };
+    let str_of = |v: &serde_json::Value, name: &str| {
+        v.get(name)
+            .and_then(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let threads = ret
+        .json()
+        .as_array()
+        .map(|threads| {
+            threads
+                .iter()
+                .map(|t| CommentThread {
+                    anchor: str_of(t, "anchor"),
+                    resolved: t.get("resolved").and_then(|r| r.as_bool()).unwrap_or(false),
+                    comments: t
+                        .get("comments")
+                        .and_then(|c| c.as_array())
+                        .into_iter()
+                        .flatten()
+                        .map(|c| Comment {
+                            author: str_of(c, "author"),
+                            time: c
+                                .get("time")
+                                .and_then(|s| s.as_str())
+                                .map(|s| s.to_string()),
+                            text: str_of(c, "text"),
+                        })
+                        .collect(),
+                    block_index: None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    println!("collected {} comment threads", threads.len());
+    threads
+}
+
+/// all text of a block, nested items included
+pub fn block_plain_text(block: &Block) -> String {
+    match block {
+        Block::Text(slices) => slices.iter().map(|s| s.text.as_str()).collect(),
+        Block::Title { text, .. } => text.clone(),
+        Block::List { items, .. } => items
+            .iter()
+            .map(|item| {
+                let mut text = item
+                    .headline
+                    .iter()
+                    .map(|s| s.text.as_str())
+                    .collect::<String>();
+                for following in &item.following {
+                    text.push('\n');
+                    text.push_str(&block_plain_text(following));
+                }
+                text
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Image { alt, caption, .. } => [alt.as_deref(), caption.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Code { code, .. } => code.clone(),
+        Block::Sheet { cells } => cells
+            .iter()
+            .map(|row| row.join("\t"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Diagram { outline, .. } => outline
+            .as_ref()
+            .map(|o| block_plain_text(&o.to_list_block()))
+            .unwrap_or_default(),
+        Block::Callout { children, .. } => children
+            .iter()
+            .map(block_plain_text)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// set `block_index` to the first top level block containing the anchor text
+pub fn attach_to_blocks(threads: &mut [CommentThread], blocks: &[Block]) {
+    let texts = blocks
+        .iter()
+        .map(|b| normalize(&block_plain_text(b)))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        let anchor = normalize(&thread.anchor);
+        if anchor.is_empty() {
+            continue;
+        }
+        thread.block_index = texts.iter().position(|t| t.contains(&anchor));
+    }
+}
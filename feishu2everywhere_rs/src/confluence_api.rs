@@ -0,0 +1,462 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use reqwest::{Method, RequestBuilder, Response};
+use serde_json::{Value, json};
+
+use crate::document::Document;
+use crate::to_confluence::render_storage;
+
+/// how requests are authorized
+pub enum ConfluenceAuth {
+    /// cloud: account email and api token
+    Basic { user: String, token: String },
+    /// data center: personal access token
+    Bearer(String),
+}
+
+/// where pages go: a space, optionally under a parent page
+pub struct PublishTarget {
+    pub space: String,
+    pub parent_id: Option<String>,
+}
+
+/// an existing page and the version to bump on update
+struct PageRef {
+    id: String,
+    version: u64,
+    body: String,
+}
+
+/// Client for the content rest api (`/rest/api/content`) of cloud and data center
+pub struct ConfluenceClient {
+    http: reqwest::Client,
+    /// site url including the context path, eg. https://example.atlassian.net/wiki
+    base: String,
+    auth: ConfluenceAuth,
+}
+
+/// the response body as json, the status and the error body otherwise
+async fn json_response(what: &str, resp: Response) -> Result<Value, Box<dyn std::error::Error>> {
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(format!("{} failed: {} {}", what, status, text).into());
+    }
+    Ok(resp.json().await?)
+}
+
+impl ConfluenceClient {
+    /// `base` is the site url, point it to a local mock server to run without confluence
+    pub fn new(base: &str, auth: ConfluenceAuth) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base: base.trim_end_matches('/').to_string(),
+            auth,
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}/rest/api{}", self.base, path));
+        match &self.auth {
+            ConfluenceAuth::Basic { user, token } => request.basic_auth(user, Some(token)),
+            ConfluenceAuth::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+
+    /// page titles are unique within a space
+    async fn find_page(
+        &self,
+        space: &str,
+        title: &str,
+    ) -> Result<Option<PageRef>, Box<dyn std::error::Error>> {
+        let resp = self
+            .request(Method::GET, "/content")
+            .query(&[
+                ("spaceKey", space),
+                ("title", title),
+                ("type", "page"),
+                ("expand", "version,body.storage"),
+            ])
+            .send()
+            .await?;
+        let data = json_response("find page", resp).await?;
+        let Some(page) = data
+            .get("results")
+            .and_then(|r| r.as_array())
+            .and_then(|r| r.first())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(PageRef {
+            id: page
+                .get("id")
+                .and_then(|i| i.as_str())
+                .ok_or("page without id")?
+                .to_string(),
+            version: page
+                .pointer("/version/number")
+                .and_then(|n| n.as_u64())
+                .unwrap_or(1),
+            body: page
+                .pointer("/body/storage/value")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+        }))
+    }
+
+    async fn create_page(
+        &self,
+        space: &str,
+        title: &str,
+        parent_id: Option<&str>,
+        body: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut page = json!({
+            "type": "page",
+            "title": title,
+            "space": { "key": space },
+            "body": { "storage": { "value": body, "representation": "storage" } },
+        });
+        if let Some(parent_id) = parent_id {
+            page["ancestors"] = json!([{ "id": parent_id }]);
+        }
+        let resp = self
+            .request(Method::POST, "/content")
+            .json(&page)
+            .send()
+            .await?;
+        let data = json_response("create page", resp).await?;
+        Ok(data
+            .get("id")
+            .and_then(|i| i.as_str())
+            .ok_or("created page without id")?
+            .to_string())
+    }
+
+    async fn update_page(
+        &self,
+        page: &PageRef,
+        space: &str,
+        title: &str,
+        body: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let resp = self
+            .request(Method::PUT, &format!("/content/{}", page.id))
+            .json(&json!({
+                "id": page.id,
+                "type": "page",
+                "title": title,
+                "space": { "key": space },
+                "version": { "number": page.version + 1, "minorEdit": true },
+                "body": { "storage": { "value": body, "representation": "storage" } },
+            }))
+            .send()
+            .await?;
+        json_response("update page", resp).await?;
+        Ok(())
+    }
+
+    async fn attachment_names(
+        &self,
+        page_id: &str,
+    ) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+        let mut names = HashSet::new();
+        let limit = 200;
+        let mut start = 0;
+        loop {
+            let resp = self
+                .request(
+                    Method::GET,
+                    &format!("/content/{}/child/attachment", page_id),
+                )
+                .query(&[("start", start), ("limit", limit)])
+                .send()
+                .await?;
+            let data = json_response("list attachments", resp).await?;
+            let results = data
+                .get("results")
+                .and_then(|r| r.as_array())
+                .cloned()
+                .unwrap_or_default();
+            names.extend(
+                results
+                    .iter()
+                    .filter_map(|a| a.get("title").and_then(|t| t.as_str()))
+                    .map(str::to_string),
+            );
+            if results.len() < limit {
+                return Ok(names);
+            }
+            start += limit;
+        }
+    }
+
+    async fn upload_attachment(
+        &self,
+        page_id: &str,
+        path: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file_name = path
+            .file_name()
+            .ok_or("attachment without file name")?
+            .to_string_lossy()
+            .to_string();
+        let form = reqwest::multipart::Form::new()
+            .part(
+                "file",
+                reqwest::multipart::Part::bytes(fs::read(path)?).file_name(file_name),
+            )
+            .text("minorEdit", "true");
+        let resp = self
+            .request(
+                Method::POST,
+                &format!("/content/{}/child/attachment", page_id),
+            )
+            // uploads are refused without it, as a csrf check
+            .header("X-Atlassian-Token", "no-check")
+            .multipart(form)
+            .send()
+            .await?;
+        json_response("upload attachment", resp).await?;
+        Ok(())
+    }
+
+    /// the page titled `title`, created empty under `parent_id` when missing
+    async fn ensure_page(
+        &self,
+        space: &str,
+        title: &str,
+        parent_id: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        match self.find_page(space, title).await? {
+            Some(page) => Ok(page.id),
+            None => self.create_page(space, title, parent_id, "").await,
+        }
+    }
+}
+
+/// Create or update the page of `doc` in the target space, under pages named after its
+/// wiki ancestors (created empty when missing), and upload the images it does not have yet.
+/// Returns the page id.
+pub async fn publish_document(
+    client: &ConfluenceClient,
+    target: &PublishTarget,
+    doc: &Document,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut parent_id = target.parent_id.clone();
+    for ancestor in &doc.meta.wiki_path {
+        parent_id = Some(
+            client
+                .ensure_page(&target.space, ancestor, parent_id.as_deref())
+                .await?,
+        );
+    }
+
+    let page = render_storage(doc);
+    let (page_id, existing) = match client.find_page(&target.space, title).await? {
+        Some(existing) => (existing.id.clone(), Some(existing)),
+        // created first so the images can be attached to it
+        None => (
+            client
+                .create_page(&target.space, title, parent_id.as_deref(), "")
+                .await?,
+            None,
+        ),
+    };
+
+    let attached = client.attachment_names(&page_id).await?;
+    for path in &page.attachments {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // names are content hashes, an attached name is the same image
+        if attached.contains(name.as_ref()) {
+            continue;
+        }
+        if !path.is_file() {
+            println!("image {:?} not found, not attached", path);
+            continue;
+        }
+        client.upload_attachment(&page_id, path).await?;
+    }
+
+    let current = match existing {
+        Some(existing) => existing,
+        None => client
+            .find_page(&target.space, title)
+            .await?
+            .ok_or("created page not found")?,
+    };
+    if current.body == page.body {
+        println!("confluence page {:?} is unchanged", title);
+    } else {
+        client
+            .update_page(&current, &target.space, title, &page.body)
+            .await?;
+        println!("published {:?} to confluence page {}", title, page_id);
+    }
+    Ok(page_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::golden::fixture;
+    use crate::mock_http::{MockServer, Request, Response};
+
+    /// a confluence holding at most one page, `page` is (id, version, body)
+    fn confluence(page: Option<(&str, u64, &str)>, attached: &[&str]) -> MockServer {
+        let page =
+            Arc::new(Mutex::new(page.map(|(id, version, body)| {
+                (id.to_string(), version, body.to_string())
+            })));
+        let attached = attached
+            .iter()
+            .map(|a| json!({ "title": a }))
+            .collect::<Vec<_>>();
+        MockServer::start(move |request: &Request| {
+            let mut page = page.lock().unwrap();
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/wiki/rest/api/content") => {
+                    let results = page
+                        .iter()
+                        .map(|(id, version, body)| {
+                            json!({ "id": id, "version": { "number": version },
+                                "body": { "storage": { "value": body } } })
+                        })
+                        .collect::<Vec<_>>();
+                    Response::json(json!({ "results": results }))
+                }
+                ("POST", "/wiki/rest/api/content") => {
+                    *page = Some(("101".to_string(), 1, String::new()));
+                    Response::json(json!({ "id": "101" }))
+                }
+                ("PUT", path) => {
+                    let body = request.json();
+                    let (_, version, stored) = page.as_mut().unwrap();
+                    *version = body["version"]["number"].as_u64().unwrap();
+                    *stored = body["body"]["storage"]["value"]
+                        .as_str()
+                        .unwrap()
+                        .to_string();
+                    Response::json(json!({ "id": path.rsplit('/').next() }))
+                }
+                ("GET", path) if path.ends_with("/child/attachment") => {
+                    Response::json(json!({ "results": attached }))
+                }
+                ("POST", path) if path.ends_with("/child/attachment") => {
+                    Response::json(json!({ "results": [{ "id": "att1" }] }))
+                }
+                _ => Response::json(json!({ "message": "not found" })).with_status(404),
+            }
+        })
+    }
+
+    fn client(server: &MockServer) -> ConfluenceClient {
+        ConfluenceClient::new(
+            &format!("{}/wiki/", server.base),
+            ConfluenceAuth::Basic {
+                user: "ann@example.com".to_string(),
+                token: "secret".to_string(),
+            },
+        )
+    }
+
+    fn target() -> PublishTarget {
+        PublishTarget {
+            space: "DOC".to_string(),
+            parent_id: Some("7".to_string()),
+        }
+    }
+
+    fn requests_to<'a>(requests: &'a [Request], method: &str) -> Vec<&'a Request> {
+        requests.iter().filter(|r| r.method == method).collect()
+    }
+
+    #[tokio::test]
+    async fn test_publish_creates_page_and_uploads_images() {
+        let server = confluence(None, &[]);
+        let page_id = publish_document(&client(&server), &target(), &fixture(), "Golden")
+            .await
+            .unwrap();
+        assert_eq!(page_id, "101");
+
+        let requests = server.requests();
+        let find = &requests[0];
+        assert_eq!(find.query("spaceKey"), Some("DOC"));
+        assert_eq!(find.query("title"), Some("Golden"));
+        // basic auth of "ann@example.com:secret"
+        assert_eq!(
+            find.header("authorization"),
+            Some("Basic YW5uQGV4YW1wbGUuY29tOnNlY3JldA==")
+        );
+
+        let posts = requests_to(&requests, "POST");
+        assert_eq!(posts.len(), 2);
+        let created = posts[0].json();
+        assert_eq!(created["title"], "Golden");
+        assert_eq!(created["space"]["key"], "DOC");
+        assert_eq!(created["ancestors"], json!([{ "id": "7" }]));
+
+        let upload = posts[1];
+        assert_eq!(upload.path, "/wiki/rest/api/content/101/child/attachment");
+        assert_eq!(upload.header("x-atlassian-token"), Some("no-check"));
+        assert!(
+            upload
+                .header("content-type")
+                .is_some_and(|t| t.starts_with("multipart/form-data"))
+        );
+        let form = String::from_utf8_lossy(&upload.body);
+        assert!(
+            form.contains("name=\"file\"; filename=\"image.png\""),
+            "{}",
+            form
+        );
+        assert!(form.contains("name=\"minorEdit\""), "{}", form);
+
+        // the body goes in once the images are attached
+        let puts = requests_to(&requests, "PUT");
+        assert_eq!(puts.len(), 1);
+        let update = puts[0].json();
+        assert_eq!(puts[0].path, "/wiki/rest/api/content/101");
+        assert_eq!(update["version"]["number"], 2);
+        assert_eq!(update["body"]["storage"]["representation"], "storage");
+        assert_eq!(
+            update["body"]["storage"]["value"],
+            render_storage(&fixture()).body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_publish_updates_existing_page() {
+        let server = confluence(Some(("55", 7, "<p>old</p>")), &["image.png"]);
+        publish_document(&client(&server), &target(), &fixture(), "Golden")
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        // nothing created, the attached image is not uploaded again
+        assert!(requests_to(&requests, "POST").is_empty());
+        let puts = requests_to(&requests, "PUT");
+        assert_eq!(puts.len(), 1);
+        assert_eq!(puts[0].path, "/wiki/rest/api/content/55");
+        let update = puts[0].json();
+        assert_eq!(update["id"], "55");
+        assert_eq!(update["version"]["number"], 8);
+        assert_eq!(update["version"]["minorEdit"], true);
+
+        // publishing the same body again leaves the page alone
+        let body = render_storage(&fixture()).body;
+        let server = confluence(Some(("55", 8, &body)), &["image.png"]);
+        publish_document(&client(&server), &target(), &fixture(), "Golden")
+            .await
+            .unwrap();
+        assert!(requests_to(&server.requests(), "PUT").is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose};
+use image::{GenericImageView, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use thirtyfour::{WebDriver, WebElement};
+
+use crate::assets::AssetStore;
+use crate::block::Block;
+use crate::outline::OutlineNode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagramKind {
+    Whiteboard,
+    MindMap,
+    Flowchart,
+    Diagram,
+}
+
+impl DiagramKind {
+    /// e is the .block element, its class name tells the block type
+    fn from_class_name(class_name: &str) -> Option<DiagramKind> {
+        if class_name.contains("docx-whiteboard-block") {
+            Some(DiagramKind::Whiteboard)
+        } else if class_name.contains("docx-mindnote-block") {
+            Some(DiagramKind::MindMap)
+        } else if class_name.contains("docx-flowchart-block") {
+            Some(DiagramKind::Flowchart)
+        } else if class_name.contains("docx-diagram-block") {
+            Some(DiagramKind::Diagram)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagramKind::Whiteboard => "whiteboard",
+            DiagramKind::MindMap => "mindmap",
+            DiagramKind::Flowchart => "flowchart",
+            DiagramKind::Diagram => "diagram",
+        }
+    }
+}
+
+/// the largest svg under arguments[0], serialized standalone, or null
+const SERIALIZE_SVG_JS: &str = r#"
+const svgs = Array.from(arguments[0].querySelectorAll('svg'));
+let best = null, bestArea = 0;
+for (const svg of svgs) {
+    const r = svg.getBoundingClientRect();
+    if (r.width * r.height > bestArea) { best = svg; bestArea = r.width * r.height; }
+}
+// small svgs are toolbar icons, not the drawing
+if (!best || bestArea < 100 * 100) return null;
+const clone = best.cloneNode(true);
+clone.setAttribute('xmlns', 'http://www.w3.org/2000/svg');
+clone.setAttribute('xmlns:xlink', 'http://www.w3.org/1999/xlink');
+if (!clone.getAttribute('viewBox')) {
+    const b = best.getBBox();
+    clone.setAttribute('viewBox', `${b.x} ${b.y} ${b.width} ${b.height}`);
+}
+return new XMLSerializer().serializeToString(clone);
+"#;
+
+/// compose every canvas under arguments[0] at its on-page position, at backing store
+/// resolution, returns png base64 or null (no canvas / tainted canvas)
+const COMPOSE_CANVAS_JS: &str = r#"
+const root = arguments[0];
+const canvases = Array.from(root.querySelectorAll('canvas'))
+    .filter((c) => c.width > 0 && c.height > 0);
+if (canvases.length === 0) return null;
+const base = root.getBoundingClientRect();
+let scale = 1;
+for (const c of canvases) {
+    const r = c.getBoundingClientRect();
+    if (r.width > 0) scale = Math.max(scale, c.width / r.width);
+}
+const out = document.createElement('canvas');
+out.width = Math.ceil(base.width * scale);
+out.height = Math.ceil(base.height * scale);
+const ctx = out.getContext('2d');
+for (const c of canvases) {
+    const r = c.getBoundingClientRect();
+    ctx.drawImage(c, (r.left - base.left) * scale, (r.top - base.top) * scale,
+                  r.width * scale, r.height * scale);
+}
+try {
+    return out.toDataURL('image/png').substring(22);
+} catch (e) {
+    return null;
+}
+"#;
+
+/// mind map nodes as a {text, children} tree, or null when no node is found
+const MINDMAP_TREE_JS: &str = r#"
+const root = arguments[0];
+const nodes = Array.from(root.querySelectorAll('[data-node-id]'));
+if (nodes.length === 0) return null;
+const byId = new Map();
+for (const n of nodes) {
+    const textElem = n.querySelector('.node-text, .mind-node-text, [class*=text]') || n;
+    const text = (textElem.innerText || '').split('\n')[0];
+    byId.set(n.getAttribute('data-node-id'), { elem: n, text: text, children: [] });
+}
+const roots = [];
+for (const [id, node] of byId) {
+    let parentId = node.elem.getAttribute('data-parent-id');
+    if (!parentId) {
+        const parentElem = node.elem.parentElement && node.elem.parentElement.closest('[data-node-id]');
+        parentId = parentElem ? parentElem.getAttribute('data-node-id') : null;
+    }
+    const parent = parentId ? byId.get(parentId) : null;
+    (parent ? parent.children : roots).push(node);
+}
+const strip = (n) => ({ text: n.text, children: n.children.map(strip) });
+if (roots.length === 1) return strip(roots[0]);
+return { text: '', children: roots.map(strip) };
+"#;
+
+/// [width, height] of arguments[0] and the viewport height, in css pixels
+const ELEMENT_SIZE_JS: &str = r#"
+const r = arguments[0].getBoundingClientRect();
+return [r.width, r.height, window.innerHeight];
+"#;
+
+/// scroll the nearest scrollable ancestor of arguments[0] (the document body in docx is
+/// not the window) so that the element's y offset arguments[1] lands at the top
+const SCROLL_TO_OFFSET_JS: &str = r#"
+const e = arguments[0];
+let p = e.parentElement;
+while (p && !(p.scrollHeight > p.clientHeight + 1 && /(auto|scroll)/.test(getComputedStyle(p).overflowY))) {
+    p = p.parentElement;
+}
+const box = p || document.scrollingElement;
+const boxTop = p ? p.getBoundingClientRect().top : 0;
+box.scrollTop += e.getBoundingClientRect().top - boxTop + arguments[1];
+"#;
+
+/// [left, top, right, bottom] of arguments[0] clipped to the viewport, relative to the element,
+/// plus devicePixelRatio
+const VISIBLE_PART_JS: &str = r#"
+const r = arguments[0].getBoundingClientRect();
+const left = Math.max(r.left, 0), top = Math.max(r.top, 0);
+const right = Math.min(r.right, window.innerWidth), bottom = Math.min(r.bottom, window.innerHeight);
+return [left - r.left, top - r.top, right - r.left, bottom - r.top, left, top, window.devicePixelRatio];
+"#;
+
+async fn run_js_f64s(driver: &WebDriver, script: &str, e: &WebElement) -> Option<Vec<f64>> {
+    let ret = driver
+        .execute(script, vec![e.to_json().unwrap()])
+        .await
+        .ok()?;
+    ret.json()
+        .as_array()?
+        .iter()
+        .map(|v| v.as_f64())
+        .collect::<Option<Vec<_>>>()
+}
+
+async fn try_serialize_svg(driver: &WebDriver, e: &WebElement) -> Option<Vec<u8>> {
+    let ret = driver
+        .execute(SERIALIZE_SVG_JS, vec![e.to_json().unwrap()])
+        .await
+        .ok()?;
+    Some(ret.json().as_str()?.as_bytes().to_vec())
+}
+
+async fn try_compose_canvas(driver: &WebDriver, e: &WebElement) -> Option<Vec<u8>> {
+    let ret = driver
+        .execute(COMPOSE_CANVAS_JS, vec![e.to_json().unwrap()])
+        .await
+        .ok()?;
+    general_purpose::STANDARD.decode(ret.json().as_str()?).ok()
+}
+
+async fn scroll_to_offset(driver: &WebDriver, e: &WebElement, y: f64) -> bool {
+    let ret = driver
+        .execute(SCROLL_TO_OFFSET_JS, vec![e.to_json().unwrap(), y.into()])
+        .await;
+    // wait for the drawing to render the new viewport
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    ret.is_ok()
+}
+
+/// scroll over the element one viewport at a time, so lazily drawn tiles get rendered
+pub async fn walk_through_element(driver: &WebDriver, e: &WebElement) {
+    let Some(size) = run_js_f64s(driver, ELEMENT_SIZE_JS, e).await else {
+        return;
+    };
+    let (height, step) = (size[1], size[2].max(100.0));
+
+    let mut y = 0.0;
+    while y < height && scroll_to_offset(driver, e, y).await {
+        y += step;
+    }
+    let _ = e.scroll_into_view().await;
+}
+
+/// screenshot the element viewport by viewport and stitch the visible parts together,
+/// used when the drawing is neither svg nor a readable canvas
+///
+/// only scrolls vertically, the part of a drawing wider than the viewport stays transparent
+async fn try_stitch_screenshots(driver: &WebDriver, e: &WebElement) -> Option<Vec<u8>> {
+    let size = run_js_f64s(driver, ELEMENT_SIZE_JS, e).await?;
+    let (width, height) = (size[0], size[1]);
+    if width < 1.0 || height < 1.0 {
+        return None;
+    }
+
+    let mut stitched: Option<RgbaImage> = None;
+    let mut covered = 0.0;
+    while covered < height {
+        if !scroll_to_offset(driver, e, covered).await {
+            return None;
+        }
+
+        let part = run_js_f64s(driver, VISIBLE_PART_JS, e).await?;
+        let (rel_left, rel_top, rel_right, rel_bottom, view_left, view_top, dpr) = (
+            part[0], part[1], part[2], part[3], part[4], part[5], part[6],
+        );
+        if rel_bottom <= covered {
+            // no progress, eg. the element is taller than the scrollable page
+            break;
+        }
+
+        let shot = image::load_from_memory(&driver.screenshot_as_png().await.ok()?).ok()?;
+        let canvas = stitched.get_or_insert_with(|| {
+            RgbaImage::new((width * dpr).ceil() as u32, (height * dpr).ceil() as u32)
+        });
+        let px = |v: f64| (v * dpr).round() as u32;
+        let crop_w = px(rel_right - rel_left).min(shot.width().saturating_sub(px(view_left)));
+        let crop_h = px(rel_bottom - rel_top).min(shot.height().saturating_sub(px(view_top)));
+        let piece = shot
+            .view(px(view_left), px(view_top), crop_w, crop_h)
+            .to_image();
+        image::imageops::replace(canvas, &piece, px(rel_left) as i64, px(rel_top) as i64);
+        covered = rel_bottom;
+    }
+    let _ = e.scroll_into_view().await;
+
+    let mut png = Vec::new();
+    stitched?
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
+
+/// tree of the mind map rendered under `e`, nodes are matched by data-node-id / data-parent-id
+pub async fn try_read_mindmap_tree(driver: &WebDriver, e: &WebElement) -> Option<OutlineNode> {
+    let ret = driver
+        .execute(MINDMAP_TREE_JS, vec![e.to_json().unwrap()])
+        .await
+        .ok()?;
+    OutlineNode::from_json(ret.json())
+}
+
+/// whiteboard, mind map, flowchart and diagram blocks
+///
+/// rendering preference: svg (lossless, keeps text) > composed canvas at backing store
+/// resolution > screenshots stitched over several viewports
+pub async fn try_new_diagram(
+    driver: &WebDriver,
+    assets: &AssetStore,
+    e: &WebElement,
+) -> Option<Block> {
+    let class_name = e.class_name().await.ok()??;
+    let kind = DiagramKind::from_class_name(&class_name)?;
+
+    // drawings are lazily rendered, make sure the whole block has been on screen
+    walk_through_element(driver, e).await;
+
+    let cached_path = if let Some(svg) = try_serialize_svg(driver, e).await {
+        assets.store_image(&svg, "svg")?
+    } else if let Some(png) = try_compose_canvas(driver, e).await {
+        assets.store_image(&png, "png")?
+    } else if let Some(png) = try_stitch_screenshots(driver, e).await {
+        assets.store_image(&png, "png")?
+    } else {
+        println!("{} block has nothing to capture", kind.name());
+        return None;
+    };
+
+    let outline = if kind == DiagramKind::MindMap {
+        try_read_mindmap_tree(driver, e).await
+    } else {
+        None
+    };
+
+    let ret = Block::Diagram {
+        kind,
+        cached_path,
+        outline,
+    };
+    println!("extracted diagram: {:?}", ret);
+    Some(ret)
+}
@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thirtyfour::WebDriver;
+
+use crate::block::Block;
+use crate::comments::CommentThread;
+
+/// What is known about the document itself, every field is best effort
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentMeta {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub created: Option<DateTime<Utc>>,
+    pub modified: Option<DateTime<Utc>>,
+    pub source_url: Option<String>,
+    /// titles from the wiki space down to the parent of this document, empty outside wikis
+    pub wiki_path: Vec<String>,
+}
+
+/// A whole export: metadata plus the top level blocks
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Document {
+    pub meta: DocumentMeta,
+    pub blocks: Vec<Block>,
+    /// empty unless comments were asked for
+    pub comments: Vec<CommentThread>,
+}
+
+/// Version of the sidecar json, described by `json_schema::document_schema`.
+/// Raised on every change older readers would misread.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// sidecars written before the version field have the version 1 layout
+fn first_format_version() -> u32 {
+    1
+}
+
+#[derive(Serialize)]
+struct VersionedRef<'a> {
+    format_version: u32,
+    #[serde(flatten)]
+    doc: &'a Document,
+}
+
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default = "first_format_version")]
+    format_version: u32,
+    #[serde(flatten)]
+    doc: Document,
+}
+
+impl Document {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&VersionedRef {
+            format_version: FORMAT_VERSION,
+            doc: self,
+        })
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let versioned: Versioned = serde_json::from_str(json)?;
+        if versioned.format_version > FORMAT_VERSION {
+            return Err(format!(
+                "format version {} is newer than the supported {}",
+                versioned.format_version, FORMAT_VERSION
+            )
+            .into());
+        }
+        Ok(versioned.doc)
+    }
+
+    /// JSON sidecar next to the markdown, later commands (site...) render from it
+    pub fn save_json(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load_json(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// read the title, owner, times and wiki breadcrumb shown around the document
+const PAGE_META_JS: &str = r#"
+const text = (sel) => {
+    const e = document.querySelector(sel);
+    const t = e ? (e.innerText || '').trim() : '';
+    return t || null;
+};
+const title = text('.page-block-content, .doc-title, .note-title')
+    || document.title.replace(/\s*-\s*(飞书云文档|飞书文档|Feishu Docs|Lark Docs).*$/, '').trim()
+    || null;
+const author = text('.docs-info-avatar-name, .doc-info-owner, [class*=owner-name]');
+const times = Array.from(document.querySelectorAll('.doc-info time[datetime], [class*=doc-info] time[datetime]'))
+    .map((t) => t.getAttribute('datetime'));
+const wikiPath = Array.from(document.querySelectorAll('.wiki-breadcrumb a, [class*=breadcrumb] [class*=item]'))
+    .map((e) => (e.innerText || '').trim())
+    .filter((t) => t.length > 0);
+return { title: title, author: author, created: times[0] || null, modified: times[1] || null, wiki_path: wikiPath };
+"#;
+
+pub fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// metadata of the document opened in `driver`
+pub async fn collect_page_meta(driver: &WebDriver, url: &str) -> DocumentMeta {
+    let mut meta = DocumentMeta {
+        source_url: Some(url.to_string()),
+        ..Default::default()
+    };
+    let ret = match driver.execute(PAGE_META_JS, vec![]).await {
+        Ok(ret) => ret,
+        Err(err) => {
+            println!("err read document meta: {:?}", err);
+            return meta;
+        }
+    };
+    let v = ret.json();
+    let str_of = |name: &str| v.get(name).and_then(|s| s.as_str()).map(|s| s.to_string());
+    meta.title = str_of("title");
+    meta.author = str_of("author");
+    meta.created = str_of("created").as_deref().and_then(parse_time);
+    meta.modified = str_of("modified").as_deref().and_then(parse_time);
+    meta.wiki_path = v
+        .get("wiki_path")
+        .and_then(|p| p.as_array())
+        .map(|p| {
+            p.iter()
+                .filter_map(Value::as_str)
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    // the breadcrumb ends with the document itself
+    if meta.wiki_path.last() == meta.title.as_ref() {
+        meta.wiki_path.pop();
+    }
+    println!("document meta: {:?}", meta);
+    meta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{GOLDEN_DIR, assert_golden};
+
+    #[test]
+    fn test_json_round_trip() {
+        let json = std::fs::read_to_string(Path::new(GOLDEN_DIR).join("document.json")).unwrap();
+        let doc = Document::from_json(&json).unwrap();
+        assert_golden("document.json", &(doc.to_json().unwrap() + "\n"));
+    }
+
+    #[test]
+    fn test_missing_fields_default() {
+        let doc = Document::from_json(
+            r#"{ "meta": { "wiki_path": [] }, "blocks": [
+                { "text": [{ "text": "a" }] },
+                { "list": { "list_type": "ordered", "items": [{ "done": null, "headline": [] }] } }
+            ], "comments": [] }"#,
+        )
+        .unwrap();
+        let Block::Text(slices) = &doc.blocks[0] else {
+            panic!("{:?}", doc.blocks[0]);
+        };
+        assert!(!slices[0].is_bold && slices[0].link.is_none());
+        let Block::List { items, .. } = &doc.blocks[1] else {
+            panic!("{:?}", doc.blocks[1]);
+        };
+        assert!(items[0].following.is_empty());
+    }
+
+    #[test]
+    fn test_newer_format_version_is_refused() {
+        let doc = Document::default();
+        let json = doc.to_json().unwrap().replace(
+            &format!("\"format_version\": {}", FORMAT_VERSION),
+            &format!("\"format_version\": {}", FORMAT_VERSION + 1),
+        );
+        let err = Document::from_json(&json).unwrap_err();
+        assert!(err.to_string().contains("newer"), "{}", err);
+    }
+}
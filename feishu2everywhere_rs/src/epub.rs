@@ -0,0 +1,390 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::document::Document;
+use crate::site::{self, NavNode};
+use crate::to_html::{self, Heading};
+use crate::xml::escape;
+
+/// plain, single column, the reading system picks fonts and colours
+const STYLE_CSS: &str = r#"body { line-height: 1.5; }
+h1.chapter-title { margin-top: 0; }
+.breadcrumb { color: #666; font-size: 0.85em; }
+figure { margin: 1em 0; text-align: center; }
+figure.align-left { text-align: left; }
+figure.align-right { text-align: right; }
+figure img { max-width: 100%; }
+figcaption { color: #666; font-size: 0.9em; }
+pre { white-space: pre-wrap; background: #f3f3f3; padding: 0.5em; font-size: 0.85em; }
+code { font-family: monospace; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #999; padding: 0.2em 0.4em; }
+ul.task-list { list-style: none; }
+.callout { background: #fff5d6; border-left: 4px solid #f0b400; padding: 0.2em 0.8em; margin: 1em 0; }
+.margin-note { border-left: 3px solid #ccc; padding-left: 0.6em; color: #555; font-size: 0.9em; }
+.margin-note.resolved { opacity: 0.6; }
+"#;
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+/// book wide settings from the command line
+pub struct BookOptions<'a> {
+    /// defaults to the title of a single document, else "Documents"
+    pub title: Option<&'a str>,
+    /// defaults to the document authors
+    pub author: Option<&'a str>,
+    pub language: &'a str,
+}
+
+struct Chapter {
+    doc: Document,
+    sidecar: PathBuf,
+    title: String,
+    /// relative to OEBPS
+    href: String,
+    headings: Vec<Heading>,
+}
+
+impl Chapter {
+    /// wiki ancestors then the chapter itself
+    fn nav_path(&self) -> Vec<String> {
+        let mut path = self.doc.meta.wiki_path.clone();
+        path.push(self.title.clone());
+        path
+    }
+}
+
+/// media type of an image epub reading systems must support, None for anything else
+fn image_media_type(extension: &str) -> Option<&'static str> {
+    match extension {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// headings nested by level, each followed by the headings under it
+fn heading_items(headings: &[Heading], href: &str) -> String {
+    let mut html = String::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = &headings[i];
+        let end = headings[i + 1..]
+            .iter()
+            .position(|h| h.level <= heading.level)
+            .map_or(headings.len(), |p| i + 1 + p);
+        html.push_str(&format!(
+            "<li><a href=\"{}#{}\">{}</a>",
+            escape(href),
+            escape(&heading.id),
+            escape(&heading.text)
+        ));
+        let children = heading_items(&headings[i + 1..end], href);
+        if !children.is_empty() {
+            html.push_str(&format!("\n<ol>\n{}</ol>\n", children));
+        }
+        html.push_str("</li>\n");
+        i = end;
+    }
+    html
+}
+
+/// the page hierarchy, each chapter listing its own headings before its sub pages
+fn nav_items(node: &NavNode, headings: &HashMap<&str, &[Heading]>) -> String {
+    let mut html = String::new();
+    for child in &node.children {
+        let mut nested = String::new();
+        match &child.url {
+            Some(href) => {
+                html.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a>",
+                    escape(href),
+                    escape(&child.title)
+                ));
+                nested.push_str(&heading_items(
+                    headings.get(href.as_str()).copied().unwrap_or_default(),
+                    href,
+                ));
+            }
+            // a wiki node that was not exported, only groups its children
+            None => html.push_str(&format!("<li><span>{}</span>", escape(&child.title))),
+        }
+        nested.push_str(&nav_items(child, headings));
+        if !nested.is_empty() {
+            html.push_str(&format!("\n<ol>\n{}</ol>\n", nested));
+        }
+        html.push_str("</li>\n");
+    }
+    html
+}
+
+fn xhtml_page(title: &str, language: &str, css_href: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{lang}" lang="{lang}">
+<head>
+<meta charset="utf-8" />
+<title>{title}</title>
+<link rel="stylesheet" type="text/css" href="{css}" />
+</head>
+<body>
+{body}</body>
+</html>
+"#,
+        lang = escape(language),
+        title = escape(title),
+        css = css_href,
+        body = body
+    )
+}
+
+/// Package every export under `input` (their json sidecars) into one epub 3 book:
+/// a chapter per document in wiki order, images from the asset store or the `.rsc`
+/// folders, and a navigation document following the wiki hierarchy and the headings
+pub fn build_epub(
+    input: &Path,
+    output: &Path,
+    options: &BookOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut found = vec![];
+    site::find_documents(input, output, &mut found);
+    if found.is_empty() {
+        return Err(format!("no exported documents (*.json sidecars) under {:?}", input).into());
+    }
+
+    let mut chapters = found
+        .into_iter()
+        .map(|(sidecar, doc)| {
+            let title = site::page_title(&doc, &sidecar);
+            Chapter {
+                doc,
+                sidecar,
+                title,
+                href: String::new(),
+                headings: vec![],
+            }
+        })
+        .collect::<Vec<_>>();
+    // wiki order: a page comes right before its sub pages
+    chapters.sort_by_key(Chapter::nav_path);
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        chapter.href = format!("text/ch{:03}.xhtml", index + 1);
+    }
+
+    let book_title = match (options.title, chapters.as_slice()) {
+        (Some(title), _) => title.to_string(),
+        (None, [only]) => only.title.clone(),
+        (None, _) => "Documents".to_string(),
+    };
+    let authors = match options.author {
+        Some(author) => vec![author.to_string()],
+        None => {
+            let mut seen = HashSet::new();
+            chapters
+                .iter()
+                .filter_map(|c| c.doc.meta.author.clone())
+                .filter(|a| seen.insert(a.clone()))
+                .collect()
+        }
+    };
+
+    // OEBPS relative path -> (media type, bytes), images shared by chapters are stored once
+    let mut images: Vec<(String, &'static str, Vec<u8>)> = vec![];
+    let mut image_hrefs: HashMap<String, String> = HashMap::new();
+    let mut chapter_files = vec![];
+    for chapter in chapters.iter_mut() {
+        let sidecar = chapter.sidecar.clone();
+        let mut link_image = |cached_path: &Path| -> Result<String, Box<dyn std::error::Error>> {
+            let file_name = cached_path
+                .file_name()
+                .ok_or("image path has no file name")?
+                .to_string_lossy()
+                .to_string();
+            if let Some(href) = image_hrefs.get(&file_name) {
+                return Ok(format!("../{}", href));
+            }
+            let Some(source) = site::find_image(cached_path, &sidecar) else {
+                println!("image {:?} of {:?} not found", cached_path, sidecar);
+                return Ok(format!("../images/{}", file_name));
+            };
+            let mut bytes = fs::read(&source)?;
+            let extension = source
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let (href, media_type) = match image_media_type(&extension) {
+                Some(media_type) => (format!("images/{}", file_name), media_type),
+                // other formats are not guaranteed to show, convert them to png
+                None => {
+                    let decoded = image::load_from_memory(&bytes)?;
+                    let mut png = Cursor::new(vec![]);
+                    decoded.write_to(&mut png, image::ImageFormat::Png)?;
+                    bytes = png.into_inner();
+                    let stem = Path::new(&file_name)
+                        .file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    (format!("images/{}.png", stem), "image/png")
+                }
+            };
+            images.push((href.clone(), media_type, bytes));
+            image_hrefs.insert(file_name, href.clone());
+            Ok(format!("../{}", href))
+        };
+        let html = to_html::render_document(&chapter.doc, &mut link_image)?;
+
+        let mut body = String::from("<section epub:type=\"chapter\">\n");
+        if !chapter.doc.meta.wiki_path.is_empty() {
+            body.push_str(&format!(
+                "<p class=\"breadcrumb\">{}</p>\n",
+                escape(&chapter.doc.meta.wiki_path.join(" / "))
+            ));
+        }
+        body.push_str(&format!(
+            "<h1 class=\"chapter-title\">{}</h1>\n{}</section>\n",
+            escape(&chapter.title),
+            html.body
+        ));
+        chapter_files.push((
+            chapter.href.clone(),
+            xhtml_page(&chapter.title, options.language, "../style.css", &body),
+        ));
+        chapter.headings = html.headings;
+    }
+
+    let mut nav_tree = NavNode::default();
+    for chapter in &chapters {
+        nav_tree.insert(&chapter.nav_path(), &chapter.href);
+    }
+    let headings = chapters
+        .iter()
+        .map(|c| (c.href.as_str(), c.headings.as_slice()))
+        .collect::<HashMap<_, _>>();
+    let nav = xhtml_page(
+        &book_title,
+        options.language,
+        "style.css",
+        &format!(
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{}</ol>\n</nav>\n",
+            escape(&book_title),
+            nav_items(&nav_tree, &headings)
+        ),
+    );
+
+    // same documents, same identifier: readers update the book instead of adding a copy
+    let mut hasher = Sha256::new();
+    for chapter in &chapters {
+        hasher.update(
+            chapter
+                .doc
+                .meta
+                .source_url
+                .as_deref()
+                .unwrap_or(&chapter.title),
+        );
+        hasher.update(b"\n");
+    }
+    let hash = format!("{:x}", hasher.finalize());
+    let identifier = format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hash[0..8],
+        &hash[8..12],
+        &hash[12..16],
+        &hash[16..20],
+        &hash[20..32]
+    );
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n",
+        identifier,
+        escape(&book_title),
+        escape(options.language)
+    );
+    for author in &authors {
+        metadata.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+    }
+    metadata.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{}</meta>\n",
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n<item id=\"css\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"ch{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+            index + 1,
+            chapter.href
+        ));
+        spine.push_str(&format!("<itemref idref=\"ch{}\"/>\n", index + 1));
+    }
+    for (index, (href, media_type, _)) in images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"img{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            index + 1,
+            escape(href),
+            media_type
+        ));
+    }
+    let package = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{}\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n<manifest>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+        escape(options.language),
+        metadata,
+        manifest,
+        spine
+    );
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut zip = ZipWriter::new(File::create(output)?);
+    // the mimetype comes first and uncompressed so the file type can be sniffed
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    let mut files = vec![
+        (
+            "META-INF/container.xml".to_string(),
+            CONTAINER_XML.to_string(),
+        ),
+        ("OEBPS/content.opf".to_string(), package),
+        ("OEBPS/nav.xhtml".to_string(), nav),
+        ("OEBPS/style.css".to_string(), STYLE_CSS.to_string()),
+    ];
+    files.extend(
+        chapter_files
+            .into_iter()
+            .map(|(href, xhtml)| (format!("OEBPS/{}", href), xhtml)),
+    );
+    for (name, content) in files {
+        zip.start_file(name, deflated)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    for (href, _, bytes) in &images {
+        zip.start_file(format!("OEBPS/{}", href), stored)?;
+        zip.write_all(bytes)?;
+    }
+    zip.finish()?;
+    println!("Wrote {} chapters to {:?}", chapters.len(), output);
+    Ok(())
+}
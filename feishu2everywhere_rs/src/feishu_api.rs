@@ -0,0 +1,1131 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use percent_encoding::percent_decode_str;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::assets::AssetStore;
+use crate::block::{Block, HeadLevel, ImageAlign, ListOne, ListType, OneOf, TextSlice};
+use crate::comments::{Comment, CommentThread};
+use crate::diagram::DiagramKind;
+use crate::document::DocumentMeta;
+
+pub const DEFAULT_API_BASE: &str = "https://open.feishu.cn";
+/// response code of a request over the app or tenant rate limit
+const RATE_LIMITED_CODE: i64 = 99991400;
+/// attempts of a rate limited request
+const MAX_ATTEMPTS: u32 = 5;
+
+/// how requests are authorized, see
+/// https://open.feishu.cn/document/server-docs/api-call-guide/calling-process/get-access-token
+pub enum ApiAuth {
+    /// app credentials, exchanged for a tenant_access_token
+    Tenant { app_id: String, app_secret: String },
+    /// a user_access_token obtained elsewhere (oauth)
+    User(String),
+}
+
+/// Every open api response is wrapped as {code, msg, data}, code 0 means success
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    code: i64,
+    #[serde(default)]
+    msg: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// One docx block as listed by the document blocks api,
+/// the type specific payload (text, heading1, image...) stays in `payload`
+#[derive(Debug, Deserialize)]
+struct ApiBlock {
+    block_id: String,
+    block_type: u32,
+    #[serde(default)]
+    children: Vec<String>,
+    #[serde(flatten)]
+    payload: HashMap<String, Value>,
+}
+
+pub struct FeishuClient {
+    http: reqwest::Client,
+    base: String,
+    token: String,
+}
+
+impl FeishuClient {
+    /// `base` is the api origin, point it to a local mock server to run without feishu
+    pub async fn new(base: &str, auth: ApiAuth) -> Result<Self, Box<dyn std::error::Error>> {
+        let http = reqwest::Client::new();
+        let base = base.trim_end_matches('/').to_string();
+        let token = match auth {
+            ApiAuth::User(token) => token,
+            ApiAuth::Tenant { app_id, app_secret } => {
+                let resp: Value = http
+                    .post(format!(
+                        "{}/open-apis/auth/v3/tenant_access_token/internal",
+                        base
+                    ))
+                    .json(&json!({ "app_id": app_id, "app_secret": app_secret }))
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                // this endpoint puts the token at the top level instead of under data
+                if resp.get("code").and_then(|c| c.as_i64()) != Some(0) {
+                    return Err(format!("get tenant_access_token failed: {}", resp).into());
+                }
+                resp.get("tenant_access_token")
+                    .and_then(|t| t.as_str())
+                    .ok_or("tenant_access_token missing in response")?
+                    .to_string()
+            }
+        };
+        Ok(Self { http, base, token })
+    }
+
+    pub async fn get_json(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.send(path, || {
+            self.http
+                .get(format!("{}{}", self.base, path))
+                .bearer_auth(&self.token)
+                .query(query)
+        })
+        .await
+    }
+
+    pub async fn post_json(
+        &self,
+        path: &str,
+        body: &Value,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        self.send(path, || {
+            self.http
+                .post(format!("{}{}", self.base, path))
+                .bearer_auth(&self.token)
+                .json(body)
+        })
+        .await
+    }
+
+    /// `data` of the response envelope, the request is sent again while rate limited
+    async fn send(
+        &self,
+        path: &str,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut attempt = 1;
+        loop {
+            let resp = build().send().await?;
+            let status = resp.status();
+            // seconds until the limit resets, sent along with 429
+            let wait = resp
+                .headers()
+                .get("x-ogw-ratelimit-reset")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(1);
+            let text = resp.text().await?;
+            let envelope = serde_json::from_str::<Envelope>(&text).ok();
+            let rate_limited = status == StatusCode::TOO_MANY_REQUESTS
+                || envelope
+                    .as_ref()
+                    .is_some_and(|e| e.code == RATE_LIMITED_CODE);
+            if rate_limited && attempt < MAX_ATTEMPTS {
+                println!("{} is rate limited, retry in {}s", path, wait);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                attempt += 1;
+                continue;
+            }
+            let Some(envelope) = envelope.filter(|_| status.is_success()) else {
+                return Err(format!("{} failed: {} {}", path, status, text).into());
+            };
+            if envelope.code != 0 {
+                return Err(format!("{} failed: {} {}", path, envelope.code, envelope.msg).into());
+            }
+            return Ok(envelope.data);
+        }
+    }
+
+    /// follow `page_token` until `has_more` is false, collecting `data.items`
+    pub async fn get_all_items(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let mut items = vec![];
+        let mut page_token = String::new();
+        loop {
+            let mut page_query = query.to_vec();
+            page_query.push(("page_size", "500"));
+            if !page_token.is_empty() {
+                page_query.push(("page_token", &page_token));
+            }
+            let data = self.get_json(path, &page_query).await?;
+            if let Some(page_items) = data.get("items").and_then(|i| i.as_array()) {
+                items.extend(page_items.iter().cloned());
+            }
+            let has_more = data
+                .get("has_more")
+                .and_then(|h| h.as_bool())
+                .unwrap_or(false);
+            page_token = data
+                .get("page_token")
+                .and_then(|t| t.as_str())
+                .unwrap_or_default()
+                .to_string();
+            if !has_more || page_token.is_empty() {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    pub async fn get_bytes(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let resp = self
+            .http
+            .get(format!("{}{}", self.base, path))
+            .bearer_auth(&self.token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(resp.bytes().await?.to_vec())
+    }
+
+    /// the wiki node object: obj_type, obj_token, title, space_id, parent_node_token...
+    pub async fn get_wiki_node(
+        &self,
+        wiki_token: &str,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut data = self
+            .get_json(
+                "/open-apis/wiki/v2/spaces/get_node",
+                &[("token", wiki_token)],
+            )
+            .await?;
+        Ok(data
+            .get_mut("node")
+            .ok_or("wiki node missing in response")?
+            .take())
+    }
+
+    /// a wiki node points to the real object, returns (obj_type, obj_token)
+    pub async fn resolve_wiki_node(
+        &self,
+        wiki_token: &str,
+    ) -> Result<(String, String), Box<dyn std::error::Error>> {
+        let node = self.get_wiki_node(wiki_token).await?;
+        let field = |name: &str| {
+            node.get(name)
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())
+                .ok_or(format!("wiki node has no {}", name))
+        };
+        Ok((field("obj_type")?, field("obj_token")?))
+    }
+
+    /// document id of a /docx/<id> or /wiki/<token> url
+    pub async fn resolve_document_id(
+        &self,
+        url: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let (kind, token) = parse_doc_url(url).ok_or(format!("not a feishu doc url: {}", url))?;
+        match kind.as_str() {
+            "docx" => Ok(token),
+            "wiki" => {
+                let (obj_type, obj_token) = self.resolve_wiki_node(&token).await?;
+                if obj_type != "docx" {
+                    return Err(format!("wiki node is a {}, not a docx", obj_type).into());
+                }
+                Ok(obj_token)
+            }
+            _ => Err(format!("{} documents are not supported by the api backend", kind).into()),
+        }
+    }
+}
+
+/// (kind, token) from https://xxx.feishu.cn/{kind}/{token}?...
+pub fn parse_doc_url(url: &str) -> Option<(String, String)> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+    let kind = segments.next()?.to_string();
+    let token = segments.next()?.to_string();
+    Some((kind, token))
+}
+
+/// Title, owner, times and wiki path of the docx behind `url`.
+/// Only the title is required, the rest is left empty when the app lacks the permission.
+pub async fn fetch_document_meta(
+    client: &FeishuClient,
+    url: &str,
+) -> Result<DocumentMeta, Box<dyn std::error::Error>> {
+    let document_id = client.resolve_document_id(url).await?;
+    let mut meta = DocumentMeta {
+        source_url: Some(url.to_string()),
+        ..Default::default()
+    };
+
+    let data = client
+        .get_json(
+            &format!("/open-apis/docx/v1/documents/{}", document_id),
+            &[],
+        )
+        .await?;
+    meta.title = data
+        .pointer("/document/title")
+        .and_then(|t| t.as_str())
+        .map(|t| t.to_string());
+
+    let metas = client
+        .post_json(
+            "/open-apis/drive/v1/metas/batch_query",
+            &json!({ "request_docs": [{ "doc_token": document_id, "doc_type": "docx" }] }),
+        )
+        .await;
+    match metas {
+        Ok(data) => {
+            let doc_meta = data.pointer("/metas/0").cloned().unwrap_or(Value::Null);
+            // times are unix seconds as strings
+            let time_of = |name: &str| {
+                doc_meta
+                    .get(name)
+                    .and_then(|t| t.as_str())
+                    .and_then(|t| t.parse::<i64>().ok())
+                    .and_then(|t| Utc.timestamp_opt(t, 0).single())
+            };
+            meta.created = time_of("create_time");
+            meta.modified = time_of("latest_modify_time");
+            if let Some(owner_id) = doc_meta.get("owner_id").and_then(|o| o.as_str()) {
+                meta.author = Some(user_name(client, owner_id, &mut HashMap::new()).await);
+            }
+        }
+        Err(err) => println!("api: document metas unavailable: {:?}", err),
+    }
+
+    if let Some(("wiki", token)) = parse_doc_url(url)
+        .as_ref()
+        .map(|(kind, token)| (kind.as_str(), token.as_str()))
+    {
+        meta.wiki_path = fetch_wiki_path(client, token).await.unwrap_or_else(|err| {
+            println!("api: wiki path unavailable: {:?}", err);
+            vec![]
+        });
+    }
+    println!("api: document meta: {:?}", meta);
+    Ok(meta)
+}
+
+/// display name of an open_id, the id itself when the contact api is not permitted
+async fn user_name(
+    client: &FeishuClient,
+    user_id: &str,
+    cache: &mut HashMap<String, String>,
+) -> String {
+    if let Some(name) = cache.get(user_id) {
+        return name.clone();
+    }
+    let name = client
+        .get_json(&format!("/open-apis/contact/v3/users/{}", user_id), &[])
+        .await
+        .ok()
+        .and_then(|u| {
+            u.pointer("/user/name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_string())
+        })
+        .unwrap_or_else(|| user_id.to_string());
+    cache.insert(user_id.to_string(), name.clone());
+    name
+}
+
+/// Every comment thread of the docx behind `url`, whole-document comments included.
+/// The first reply of a thread is the comment that opened it.
+pub async fn fetch_document_comments(
+    client: &FeishuClient,
+    url: &str,
+) -> Result<Vec<CommentThread>, Box<dyn std::error::Error>> {
+    let document_id = client.resolve_document_id(url).await?;
+    let items = client
+        .get_all_items(
+            &format!("/open-apis/drive/v1/files/{}/comments", document_id),
+            &[("file_type", "docx"), ("user_id_type", "open_id")],
+        )
+        .await?;
+
+    let mut names = HashMap::new();
+    let mut threads = vec![];
+    for item in items {
+        let mut comments = vec![];
+        for reply in item
+            .pointer("/reply_list/replies")
+            .and_then(|r| r.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let author = match reply.get("user_id").and_then(|u| u.as_str()) {
+                Some(user_id) => user_name(client, user_id, &mut names).await,
+                None => String::new(),
+            };
+            let text = reply
+                .pointer("/content/elements")
+                .and_then(|e| e.as_array())
+                .into_iter()
+                .flatten()
+                .map(|element| {
+                    match element.get("type").and_then(|t| t.as_str()) {
+                        Some("text_run") => element.pointer("/text_run/text"),
+                        Some("docs_link") => element.pointer("/docs_link/url"),
+                        Some("person") => element.pointer("/person/user_id"),
+                        _ => None,
+                    }
+                    .and_then(|t| t.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+                })
+                .collect::<String>();
+            let time = reply
+                .get("create_time")
+                .and_then(|t| t.as_i64())
+                .and_then(|t| Utc.timestamp_opt(t, 0).single())
+                .map(|t| t.to_rfc3339());
+            comments.push(Comment { author, time, text });
+        }
+        threads.push(CommentThread {
+            anchor: item
+                .get("quote")
+                .and_then(|q| q.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            resolved: item
+                .get("is_solved")
+                .and_then(|s| s.as_bool())
+                .unwrap_or(false),
+            comments,
+            block_index: None,
+        });
+    }
+    println!("api: {} comment threads", threads.len());
+    Ok(threads)
+}
+
+/// space name and the titles of every ancestor node, root first
+async fn fetch_wiki_path(
+    client: &FeishuClient,
+    wiki_token: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let str_of = |v: &Value, name: &str| {
+        v.get(name)
+            .and_then(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let node = client.get_wiki_node(wiki_token).await?;
+    let mut path = vec![];
+    let mut parent = str_of(&node, "parent_node_token");
+    while !parent.is_empty() {
+        let parent_node = client.get_wiki_node(&parent).await?;
+        path.push(str_of(&parent_node, "title"));
+        parent = str_of(&parent_node, "parent_node_token");
+    }
+
+    let space_id = str_of(&node, "space_id");
+    if !space_id.is_empty()
+        && let Ok(space) = client
+            .get_json(&format!("/open-apis/wiki/v2/spaces/{}", space_id), &[])
+            .await
+        && let Some(name) = space.pointer("/space/name").and_then(|n| n.as_str())
+    {
+        path.push(name.to_string());
+    }
+    path.reverse();
+    Ok(path)
+}
+
+/// Fetch a docx through the document blocks api and build the same Block tree the
+/// browser backend produces. Images and whiteboards are downloaded into the asset store.
+pub async fn fetch_document_blocks(
+    client: &FeishuClient,
+    url: &str,
+    assets: &AssetStore,
+) -> Result<Vec<Block>, Box<dyn std::error::Error>> {
+    let document_id = client.resolve_document_id(url).await?;
+    println!("api: fetching blocks of document {}", document_id);
+
+    let items = client
+        .get_all_items(
+            &format!("/open-apis/docx/v1/documents/{}/blocks", document_id),
+            &[("document_revision_id", "-1")],
+        )
+        .await?;
+    let mut api_blocks = HashMap::new();
+    for item in items {
+        let block: ApiBlock = serde_json::from_value(item)?;
+        api_blocks.insert(block.block_id.clone(), block);
+    }
+    println!("api: {} blocks listed", api_blocks.len());
+
+    // download binaries first, so the tree conversion below stays synchronous
+    let mut media = HashMap::new();
+    for block in api_blocks.values() {
+        let (path, ext) = match block.block_type {
+            BLOCK_TYPE_IMAGE => {
+                let Some(token) = payload_str(block, "image", "token") else {
+                    continue;
+                };
+                (
+                    format!("/open-apis/drive/v1/medias/{}/download", token),
+                    None,
+                )
+            }
+            BLOCK_TYPE_BOARD => {
+                let Some(token) = payload_str(block, "board", "token") else {
+                    continue;
+                };
+                (
+                    format!(
+                        "/open-apis/board/v1/whiteboards/{}/download_as_image",
+                        token
+                    ),
+                    Some("png"),
+                )
+            }
+            _ => continue,
+        };
+        match client.get_bytes(&path).await {
+            Ok(data) => {
+                let ext = ext.unwrap_or_else(|| guess_image_ext(&data));
+                if let Some(stored) = assets.store_image(&data, ext) {
+                    media.insert(block.block_id.clone(), stored);
+                }
+            }
+            Err(err) => println!("api: download {} failed: {:?}", path, err),
+        }
+    }
+
+    let root = api_blocks
+        .get(&document_id)
+        .or_else(|| {
+            api_blocks
+                .values()
+                .find(|b| b.block_type == BLOCK_TYPE_PAGE)
+        })
+        .ok_or("page block missing in document")?;
+    Ok(convert_children(&root.children, &api_blocks, &media))
+}
+
+// https://open.feishu.cn/document/server-docs/docs/docs/docx-v1/data-structure/block
+const BLOCK_TYPE_PAGE: u32 = 1;
+const BLOCK_TYPE_TEXT: u32 = 2;
+const BLOCK_TYPE_HEADING1: u32 = 3;
+const BLOCK_TYPE_HEADING9: u32 = 11;
+const BLOCK_TYPE_BULLET: u32 = 12;
+const BLOCK_TYPE_ORDERED: u32 = 13;
+const BLOCK_TYPE_CODE: u32 = 14;
+const BLOCK_TYPE_QUOTE: u32 = 15;
+const BLOCK_TYPE_TODO: u32 = 17;
+const BLOCK_TYPE_CALLOUT: u32 = 19;
+const BLOCK_TYPE_GRID: u32 = 24;
+const BLOCK_TYPE_GRID_COLUMN: u32 = 25;
+const BLOCK_TYPE_IMAGE: u32 = 27;
+const BLOCK_TYPE_TABLE: u32 = 31;
+const BLOCK_TYPE_QUOTE_CONTAINER: u32 = 34;
+const BLOCK_TYPE_BOARD: u32 = 43;
+
+fn payload_str<'a>(block: &'a ApiBlock, field: &str, key: &str) -> Option<&'a str> {
+    block.payload.get(field)?.get(key)?.as_str()
+}
+
+fn guess_image_ext(data: &[u8]) -> &'static str {
+    match image::guess_format(data) {
+        Ok(image::ImageFormat::Jpeg) => "jpg",
+        Ok(image::ImageFormat::Gif) => "gif",
+        Ok(image::ImageFormat::WebP) => "webp",
+        Ok(image::ImageFormat::Bmp) => "bmp",
+        _ => "png",
+    }
+}
+
+/// payload field that holds the text elements for a block type
+fn text_field_name(block_type: u32) -> Option<String> {
+    match block_type {
+        BLOCK_TYPE_TEXT => Some("text".to_string()),
+        BLOCK_TYPE_HEADING1..=BLOCK_TYPE_HEADING9 => {
+            Some(format!("heading{}", block_type - BLOCK_TYPE_HEADING1 + 1))
+        }
+        BLOCK_TYPE_BULLET => Some("bullet".to_string()),
+        BLOCK_TYPE_ORDERED => Some("ordered".to_string()),
+        BLOCK_TYPE_CODE => Some("code".to_string()),
+        BLOCK_TYPE_QUOTE => Some("quote".to_string()),
+        BLOCK_TYPE_TODO => Some("todo".to_string()),
+        _ => None,
+    }
+}
+
+/// text elements ({elements: [{text_run}, {mention_doc}, ...]}) to slices
+fn text_slices(text: &Value) -> Vec<TextSlice> {
+    let mut slices = vec![];
+    let Some(elements) = text.get("elements").and_then(|e| e.as_array()) else {
+        return slices;
+    };
+    for element in elements {
+        if let Some(run) = element.get("text_run") {
+            let style = run.get("text_element_style");
+            let flag = |name: &str| {
+                style
+                    .and_then(|s| s.get(name))
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false)
+            };
+            // link urls come percent-encoded
+            let link = style
+                .and_then(|s| s.get("link"))
+                .and_then(|l| l.get("url"))
+                .and_then(|u| u.as_str())
+                .map(|u| percent_decode_str(u).decode_utf8_lossy().into_owned());
+            slices.push(TextSlice {
+                text: run
+                    .get("content")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                is_bold: flag("bold"),
+                is_underline: flag("underline"),
+                is_code: flag("inline_code"),
+                link,
+            });
+        } else if let Some(mention) = element.get("mention_doc") {
+            let field = |name: &str| {
+                mention
+                    .get(name)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            slices.push(TextSlice {
+                text: field("title"),
+                link: Some(
+                    percent_decode_str(&field("url"))
+                        .decode_utf8_lossy()
+                        .into_owned(),
+                ),
+                ..Default::default()
+            });
+        } else if let Some(equation) = element.get("equation") {
+            slices.push(TextSlice {
+                text: equation
+                    .get("content")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or_default()
+                    .trim_end()
+                    .to_string(),
+                is_code: true,
+                ..Default::default()
+            });
+        }
+    }
+    slices
+}
+
+fn plain_text(slices: &[TextSlice]) -> String {
+    slices.iter().map(|s| s.text.as_str()).collect()
+}
+
+/// code block language enum of the docx api
+fn code_language(id: i64) -> &'static str {
+    const LANGUAGES: [&str; 75] = [
+        "",
+        "abap",
+        "ada",
+        "apache",
+        "apex",
+        "assembly",
+        "bash",
+        "csharp",
+        "cpp",
+        "c",
+        "cobol",
+        "css",
+        "coffeescript",
+        "d",
+        "dart",
+        "delphi",
+        "django",
+        "dockerfile",
+        "erlang",
+        "fortran",
+        "foxpro",
+        "go",
+        "groovy",
+        "html",
+        "htmlbars",
+        "http",
+        "haskell",
+        "json",
+        "java",
+        "javascript",
+        "julia",
+        "kotlin",
+        "latex",
+        "lisp",
+        "logo",
+        "lua",
+        "matlab",
+        "makefile",
+        "markdown",
+        "nginx",
+        "objectivec",
+        "openedge-abl",
+        "php",
+        "perl",
+        "postscript",
+        "powershell",
+        "prolog",
+        "protobuf",
+        "python",
+        "r",
+        "rpg",
+        "ruby",
+        "rust",
+        "sas",
+        "scss",
+        "sql",
+        "scala",
+        "scheme",
+        "scratch",
+        "shell",
+        "swift",
+        "thrift",
+        "typescript",
+        "vbscript",
+        "vbnet",
+        "xml",
+        "yaml",
+        "cmake",
+        "diff",
+        "gherkin",
+        "graphql",
+        "glsl",
+        "properties",
+        "solidity",
+        "toml",
+    ];
+    usize::try_from(id - 1)
+        .ok()
+        .and_then(|i| LANGUAGES.get(i))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// the api names callout emojis, the common ones as characters
+fn callout_emoji(id: &str) -> Option<String> {
+    let emoji = match id {
+        "bulb" => "💡",
+        "warning" => "⚠️",
+        "exclamation" | "heavy_exclamation_mark" => "❗",
+        "question" => "❓",
+        "information_source" => "ℹ️",
+        "white_check_mark" | "heavy_check_mark" => "✅",
+        "x" | "no_entry" => "⛔",
+        "pushpin" | "round_pushpin" => "📌",
+        "memo" | "pencil" => "📝",
+        "fire" => "🔥",
+        "star" => "⭐",
+        "gift" => "🎁",
+        _ => return None,
+    };
+    Some(emoji.to_string())
+}
+
+fn head_level(n: u32) -> HeadLevel {
+    match n {
+        1 => HeadLevel::H1,
+        2 => HeadLevel::H2,
+        3 => HeadLevel::H3,
+        4 => HeadLevel::H4,
+        5 => HeadLevel::H5,
+        6 => HeadLevel::H6,
+        7 => HeadLevel::H7,
+        8 => HeadLevel::H8,
+        _ => HeadLevel::H9,
+    }
+}
+
+/// push a list item, joining it to the previous list when the type matches
+fn push_list_item(out: &mut Vec<Block>, list_type: ListType, item: ListOne) {
+    if let Some(Block::List {
+        list_type: last_type,
+        items,
+    }) = out.last_mut()
+        && *last_type == list_type
+    {
+        items.push(item);
+        return;
+    }
+    out.push(Block::List {
+        list_type,
+        items: vec![item],
+    });
+}
+
+fn convert_children(
+    ids: &[String],
+    api_blocks: &HashMap<String, ApiBlock>,
+    media: &HashMap<String, PathBuf>,
+) -> Vec<Block> {
+    let mut out = vec![];
+    for id in ids {
+        let Some(block) = api_blocks.get(id) else {
+            println!("api: child block {} not listed", id);
+            continue;
+        };
+        match block.block_type {
+            // layout containers have no counterpart, keep their content in place
+            BLOCK_TYPE_GRID | BLOCK_TYPE_GRID_COLUMN | BLOCK_TYPE_QUOTE_CONTAINER => {
+                for child in convert_children(&block.children, api_blocks, media) {
+                    match child {
+                        Block::List { list_type, items } => {
+                            for item in items {
+                                push_list_item(&mut out, list_type, item);
+                            }
+                        }
+                        child => out.push(child),
+                    }
+                }
+            }
+            _ => match convert_block(block, api_blocks, media) {
+                Some(OneOf::A(converted)) => out.push(converted),
+                Some(OneOf::B((list_type, item))) => push_list_item(&mut out, list_type, item),
+                // convert_block told why the block is skipped
+                None => {}
+            },
+        }
+    }
+    out
+}
+
+fn convert_block(
+    block: &ApiBlock,
+    api_blocks: &HashMap<String, ApiBlock>,
+    media: &HashMap<String, PathBuf>,
+) -> Option<OneOf<Block, (ListType, ListOne)>> {
+    let slices = text_field_name(block.block_type)
+        .and_then(|field| block.payload.get(&field))
+        .map(text_slices)
+        .unwrap_or_default();
+
+    let list_item = |list_type: ListType, done: Option<bool>| {
+        let following = convert_children(&block.children, api_blocks, media);
+        Some(OneOf::B((
+            list_type,
+            ListOne::new(slices.clone(), done, following),
+        )))
+    };
+
+    match block.block_type {
+        BLOCK_TYPE_TEXT | BLOCK_TYPE_QUOTE => Some(OneOf::A(Block::Text(slices))),
+        BLOCK_TYPE_HEADING1..=BLOCK_TYPE_HEADING9 => Some(OneOf::A(Block::Title {
+            text: plain_text(&slices),
+            head_level: head_level(block.block_type - BLOCK_TYPE_HEADING1 + 1),
+        })),
+        BLOCK_TYPE_BULLET => list_item(ListType::Unordered, None),
+        BLOCK_TYPE_ORDERED => list_item(ListType::Ordered, None),
+        BLOCK_TYPE_TODO => {
+            let done = block
+                .payload
+                .get("todo")
+                .and_then(|t| t.get("style"))
+                .and_then(|s| s.get("done"))
+                .and_then(|d| d.as_bool())
+                .unwrap_or(false);
+            list_item(ListType::Task, Some(done))
+        }
+        BLOCK_TYPE_CODE => {
+            let language = block
+                .payload
+                .get("code")
+                .and_then(|c| c.get("style"))
+                .and_then(|s| s.get("language"))
+                .and_then(|l| l.as_i64())
+                .map(code_language)
+                .unwrap_or_default();
+            let mut code = plain_text(&slices);
+            code.push('\n');
+            Some(OneOf::A(Block::Code {
+                language: language.to_string(),
+                code,
+            }))
+        }
+        BLOCK_TYPE_IMAGE => {
+            let image = block.payload.get("image")?;
+            let caption = image
+                .get("caption")
+                .and_then(|c| c.get("content"))
+                .and_then(|c| c.as_str())
+                .filter(|c| !c.is_empty())
+                .map(|c| c.to_string());
+            let align = match image.get("align").and_then(|a| a.as_i64()) {
+                Some(1) => Some(ImageAlign::Left),
+                Some(2) => Some(ImageAlign::Center),
+                Some(3) => Some(ImageAlign::Right),
+                _ => None,
+            };
+            let Some(cached_path) = media.get(&block.block_id) else {
+                println!("api: skip image {}, its download failed", block.block_id);
+                return None;
+            };
+            Some(OneOf::A(Block::Image {
+                cached_path: cached_path.clone(),
+                alt: None,
+                caption,
+                width: None,
+                align,
+            }))
+        }
+        BLOCK_TYPE_CALLOUT => Some(OneOf::A(Block::Callout {
+            emoji: payload_str(block, "callout", "emoji_id").and_then(callout_emoji),
+            children: convert_children(&block.children, api_blocks, media),
+        })),
+        BLOCK_TYPE_BOARD => {
+            let Some(cached_path) = media.get(&block.block_id) else {
+                println!(
+                    "api: skip whiteboard {}, its download failed",
+                    block.block_id
+                );
+                return None;
+            };
+            Some(OneOf::A(Block::Diagram {
+                kind: DiagramKind::Whiteboard,
+                cached_path: cached_path.clone(),
+                outline: None,
+            }))
+        }
+        BLOCK_TYPE_TABLE => {
+            let table = block.payload.get("table")?;
+            let property = table.get("property")?;
+            let cols = property.get("column_size")?.as_u64()? as usize;
+            let cell_ids = table
+                .get("cells")?
+                .as_array()?
+                .iter()
+                .filter_map(|c| c.as_str())
+                .collect::<Vec<_>>();
+            // each cell is a container of text blocks
+            let cell_text = |cell_id: &str| {
+                let Some(cell) = api_blocks.get(cell_id) else {
+                    return String::new();
+                };
+                cell.children
+                    .iter()
+                    .filter_map(|id| api_blocks.get(id))
+                    .map(|child| {
+                        text_field_name(child.block_type)
+                            .and_then(|field| child.payload.get(&field))
+                            .map(|text| plain_text(&text_slices(text)))
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            let cells = cell_ids
+                .chunks(cols.max(1))
+                .map(|row| row.iter().map(|id| cell_text(id)).collect())
+                .collect();
+            Some(OneOf::A(Block::Sheet { cells }))
+        }
+        _ => {
+            println!("api: skip unsupported block type {}", block.block_type);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::assets::ImageOptions;
+    use crate::mock_http::{MockServer, Response, temp_dir};
+
+    fn run(text: &str, style: Value) -> Value {
+        json!({ "text_run": { "content": text, "text_element_style": style } })
+    }
+
+    fn text(id: &str, block_type: u32, field: &str, content: &str) -> Value {
+        json!({ "block_id": id, "block_type": block_type,
+            field: { "elements": [run(content, json!({}))] } })
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = Vec::new();
+        image::RgbaImage::new(1, 1)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// blocks of doc1 as listed by the api
+    fn recorded_blocks() -> Vec<Value> {
+        let mut blocks = vec![
+            json!({ "block_id": "doc1", "block_type": 1,
+                "children": ["h1", "t1", "b1", "b2", "grid", "code", "img", "tbl", "todo", "co"] }),
+            text("h1", 3, "heading1", "Hello"),
+            json!({ "block_id": "t1", "block_type": 2, "text": { "elements": [
+                run("plain ", json!({})),
+                run("bold", json!({ "bold": true })),
+                run(" link", json!({ "link": { "url": "https%3A%2F%2Fexample.com%2F%3Fa%3D1" } })),
+            ] } }),
+            json!({ "block_id": "b1", "block_type": 12, "children": ["b1a"],
+                "bullet": { "elements": [run("item1", json!({}))] } }),
+            text("b1a", 13, "ordered", "nested"),
+            text("b2", 12, "bullet", "item2"),
+            json!({ "block_id": "grid", "block_type": 24, "children": ["col"] }),
+            json!({ "block_id": "col", "block_type": 25, "children": ["b3"] }),
+            text("b3", 12, "bullet", "item3"),
+            json!({ "block_id": "code", "block_type": 14, "code": {
+                "elements": [run("fn main() {}", json!({}))], "style": { "language": 53 } } }),
+            json!({ "block_id": "img", "block_type": 27, "image": { "token": "imgtok", "align": 2,
+                "caption": { "content": "Fig. 1" } } }),
+            json!({ "block_id": "tbl", "block_type": 31, "children": ["c11", "c12", "c21", "c22"],
+                "table": { "cells": ["c11", "c12", "c21", "c22"],
+                    "property": { "row_size": 2, "column_size": 2 } } }),
+        ];
+        for (cell, content) in [("c11", "A"), ("c12", "B"), ("c21", "1"), ("c22", "2")] {
+            let child = format!("x{}", cell);
+            blocks.push(json!({ "block_id": cell, "block_type": 32, "children": [child] }));
+            blocks.push(text(&child, 2, "text", content));
+        }
+        blocks.extend([
+            json!({ "block_id": "todo", "block_type": 17, "todo": {
+                "elements": [run("task", json!({}))], "style": { "done": true } } }),
+            json!({ "block_id": "co", "block_type": 19, "children": ["co1", "co2"],
+                "callout": { "emoji_id": "bulb" } }),
+            text("co1", 2, "text", "inside"),
+            text("co2", 12, "bullet", "point"),
+        ]);
+        blocks
+    }
+
+    #[tokio::test]
+    async fn test_fetch_document_blocks_from_wiki_url() {
+        let blocks = recorded_blocks();
+        let limited = AtomicBool::new(false);
+        let server = MockServer::start(move |request| {
+            let page = |items: &[Value], has_more: bool, page_token: &str| {
+                Response::json(json!({ "code": 0, "msg": "success", "data": {
+                    "items": items, "has_more": has_more, "page_token": page_token } }))
+            };
+            match request.path.as_str() {
+                "/open-apis/auth/v3/tenant_access_token/internal" => Response::json(
+                    json!({ "code": 0, "tenant_access_token": "t-123", "expire": 7200 }),
+                ),
+                "/open-apis/wiki/v2/spaces/get_node" => {
+                    Response::json(json!({ "code": 0, "data": {
+                    "node": { "obj_type": "docx", "obj_token": "doc1", "title": "My Doc" } } }))
+                }
+                "/open-apis/docx/v1/documents/doc1/blocks" => match request.query("page_token") {
+                    None => page(&blocks[..9], true, "p2"),
+                    // the second page is rate limited once
+                    Some("p2") if !limited.swap(true, Ordering::SeqCst) => {
+                        Response::json(json!({ "code": 99991400, "msg": "frequency limit" }))
+                            .with_status(429)
+                            .with_header("x-ogw-ratelimit-reset", "0")
+                    }
+                    Some("p2") => page(&blocks[9..], false, ""),
+                    Some(_) => Response::json(json!({ "code": 99991663, "msg": "bad token" })),
+                },
+                "/open-apis/drive/v1/medias/imgtok/download" => Response::bytes("image/png", png()),
+                _ => Response::json(json!({ "code": 404, "msg": "not found" })).with_status(404),
+            }
+        });
+
+        let client = FeishuClient::new(
+            &server.base,
+            ApiAuth::Tenant {
+                app_id: "cli_1".to_string(),
+                app_secret: "secret".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        let assets = AssetStore::new(temp_dir("feishu-api"), ImageOptions::default());
+        let blocks = fetch_document_blocks(&client, "https://x.feishu.cn/wiki/wk1?from=a", &assets)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].json()["app_id"], "cli_1");
+        assert_eq!(requests[1].query("token"), Some("wk1"));
+        assert_eq!(requests[1].header("authorization"), Some("Bearer t-123"));
+        let pages = requests
+            .iter()
+            .filter(|r| r.path.ends_with("/blocks"))
+            .collect::<Vec<_>>();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].query("page_token"), None);
+        assert_eq!(pages[1].query("page_token"), Some("p2"));
+        assert_eq!(pages[2].query("page_token"), Some("p2"));
+        assert_eq!(pages[2].query("document_revision_id"), Some("-1"));
+
+        let image_path = assets.store_image(&png(), "png").unwrap();
+        let expected: Vec<Block> = serde_json::from_value(json!([
+            { "title": { "text": "Hello", "head_level": "h1" } },
+            { "text": [
+                { "text": "plain " },
+                { "text": "bold", "is_bold": true },
+                { "text": " link", "link": "https://example.com/?a=1" },
+            ] },
+            { "list": { "list_type": "unordered", "items": [
+                { "headline": [{ "text": "item1" }], "following": [
+                    { "list": { "list_type": "ordered", "items": [{ "headline": [{ "text": "nested" }] }] } },
+                ] },
+                { "headline": [{ "text": "item2" }] },
+                // grid columns are flattened, their list joins the one before
+                { "headline": [{ "text": "item3" }] },
+            ] } },
+            { "code": { "language": "rust", "code": "fn main() {}\n" } },
+            { "image": { "cached_path": image_path, "alt": null, "caption": "Fig. 1",
+                "width": null, "align": "center" } },
+            { "sheet": { "cells": [["A", "B"], ["1", "2"]] } },
+            { "list": { "list_type": "task", "items": [{ "done": true, "headline": [{ "text": "task" }] }] } },
+            { "callout": { "emoji": "💡", "children": [
+                { "text": [{ "text": "inside" }] },
+                { "list": { "list_type": "unordered", "items": [{ "headline": [{ "text": "point" }] }] } },
+            ] } },
+        ]))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&blocks).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_error_keeps_status_and_body() {
+        let server = MockServer::start(|_| {
+            Response::bytes("text/html", b"<html>bad gateway</html>".to_vec()).with_status(502)
+        });
+        let client = FeishuClient::new(&server.base, ApiAuth::User("u-1".to_string()))
+            .await
+            .unwrap();
+        let err = client
+            .get_json("/open-apis/docx/v1/documents/doc1", &[])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("502") && err.contains("bad gateway"),
+            "{}",
+            err
+        );
+    }
+}
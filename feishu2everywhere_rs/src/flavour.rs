@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use thirtyfour::{By, WebDriver};
+
+/// Which editor rendered the page, each one needs its own extractors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorFlavour {
+    /// new docx editor, blocks under .root-render-unit-container
+    Docx,
+    /// legacy doc editor (also used by wiki v1), lines under #innerdocbody
+    LegacyDoc,
+    /// standalone spreadsheet, exported as tables instead of markdown
+    Sheets,
+    /// standalone mind note, exported as an outline
+    Mindnote,
+}
+
+/// what the url alone tells, wiki links can point to any kind so they need the page
+pub fn flavour_from_url(url: &str) -> Option<EditorFlavour> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    if path.contains("/sheets/") {
+        Some(EditorFlavour::Sheets)
+    } else if path.contains("/mindnotes/") {
+        Some(EditorFlavour::Mindnote)
+    } else {
+        None
+    }
+}
+
+const DETECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// wait until one of the editors has rendered and tell which one it is
+pub async fn detect_editor_flavour(driver: &WebDriver) -> Option<EditorFlavour> {
+    let probes = [
+        (".root-render-unit-container", EditorFlavour::Docx),
+        ("#innerdocbody, .innerdocbody", EditorFlavour::LegacyDoc),
+        (
+            ".spreadsheet-container, .sheet-container",
+            EditorFlavour::Sheets,
+        ),
+        (
+            ".mindnote-paper, .mindnote-container",
+            EditorFlavour::Mindnote,
+        ),
+    ];
+
+    let start = std::time::Instant::now();
+    while start.elapsed() < DETECT_TIMEOUT {
+        for (css, flavour) in probes {
+            if let Ok(found) = driver.find_all(By::Css(css)).await
+                && !found.is_empty()
+            {
+                println!("detected editor flavour: {:?}", flavour);
+                return Some(flavour);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    None
+}
@@ -0,0 +1,205 @@
+use crate::document::DocumentMeta;
+use crate::obsidian;
+
+/// front matter block put at the top of markdown exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum FrontMatterFormat {
+    #[default]
+    None,
+    /// `---` fenced, read by hugo, jekyll, docusaurus...
+    Yaml,
+    /// `+++` fenced, read by hugo and zola
+    Toml,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaField {
+    Title,
+    Author,
+    Created,
+    Modified,
+    SourceUrl,
+    WikiPath,
+    /// the wiki path as tags, obsidian style
+    Tags,
+}
+
+impl MetaField {
+    pub const ALL: [MetaField; 7] = [
+        MetaField::Title,
+        MetaField::Author,
+        MetaField::Created,
+        MetaField::Modified,
+        MetaField::SourceUrl,
+        MetaField::WikiPath,
+        MetaField::Tags,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetaField::Title => "title",
+            MetaField::Author => "author",
+            MetaField::Created => "created",
+            MetaField::Modified => "modified",
+            MetaField::SourceUrl => "source_url",
+            MetaField::WikiPath => "wiki_path",
+            MetaField::Tags => "tags",
+        }
+    }
+
+    /// key names most site generators understand without configuration
+    fn default_key(&self) -> &'static str {
+        match self {
+            MetaField::Title => "title",
+            MetaField::Author => "author",
+            MetaField::Created => "date",
+            MetaField::Modified => "lastmod",
+            MetaField::SourceUrl => "source",
+            MetaField::WikiPath => "categories",
+            MetaField::Tags => "tags",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<MetaField> {
+        MetaField::ALL.into_iter().find(|f| f.name() == name)
+    }
+}
+
+/// which fields go into the front matter and under which key, in output order
+#[derive(Debug, Clone)]
+pub struct FrontMatterKeys(pub Vec<(MetaField, String)>);
+
+impl Default for FrontMatterKeys {
+    fn default() -> Self {
+        Self(
+            MetaField::ALL
+                .into_iter()
+                // the wiki path already goes to categories
+                .filter(|f| *f != MetaField::Tags)
+                .map(|f| (f, f.default_key().to_string()))
+                .collect(),
+        )
+    }
+}
+
+impl FrontMatterKeys {
+    /// `title,created=date,wiki_path=tags`, a field without `=key` keeps its default key,
+    /// fields not listed are left out
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut keys = vec![];
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, key) = match part.split_once('=') {
+                Some((name, key)) => (name.trim(), Some(key.trim())),
+                None => (part, None),
+            };
+            let field = MetaField::from_name(name).ok_or_else(|| {
+                format!(
+                    "unknown front matter field {}, expected one of {}",
+                    name,
+                    MetaField::ALL.map(|f| f.name()).join(", ")
+                )
+            })?;
+            keys.push((field, key.unwrap_or(field.default_key()).to_string()));
+        }
+        Ok(Self(keys))
+    }
+
+    /// add `field` under its default key unless it is already listed
+    pub fn with_field(mut self, field: MetaField) -> Self {
+        if !self.0.iter().any(|(f, _)| *f == field) {
+            self.0.push((field, field.default_key().to_string()));
+        }
+        self
+    }
+}
+
+enum MetaValue {
+    Text(String),
+    /// rfc 3339, written unquoted so both yaml and toml read it as a date
+    Time(String),
+    List(Vec<String>),
+}
+
+fn field_value(meta: &DocumentMeta, field: MetaField) -> Option<MetaValue> {
+    match field {
+        MetaField::Title => meta.title.clone().map(MetaValue::Text),
+        MetaField::Author => meta.author.clone().map(MetaValue::Text),
+        MetaField::Created => meta.created.map(|t| MetaValue::Time(t.to_rfc3339())),
+        MetaField::Modified => meta.modified.map(|t| MetaValue::Time(t.to_rfc3339())),
+        MetaField::SourceUrl => meta.source_url.clone().map(MetaValue::Text),
+        MetaField::WikiPath if meta.wiki_path.is_empty() => None,
+        MetaField::WikiPath => Some(MetaValue::List(meta.wiki_path.clone())),
+        MetaField::Tags => {
+            let tags = meta
+                .wiki_path
+                .iter()
+                .filter_map(|t| obsidian::tag_name(t))
+                .collect::<Vec<_>>();
+            (!tags.is_empty()).then_some(MetaValue::List(tags))
+        }
+    }
+}
+
+/// double quoted string, valid in both yaml and toml
+fn quote(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// keys are quoted unless they are plain identifiers
+fn render_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        quote(key)
+    }
+}
+
+/// the front matter block including its fences and a trailing blank line,
+/// empty when disabled or when no field has a value
+pub fn render(meta: &DocumentMeta, format: FrontMatterFormat, keys: &FrontMatterKeys) -> String {
+    let (fence, separator) = match format {
+        FrontMatterFormat::None => return String::new(),
+        FrontMatterFormat::Yaml => ("---", ": "),
+        FrontMatterFormat::Toml => ("+++", " = "),
+    };
+
+    let mut lines = vec![];
+    for (field, key) in &keys.0 {
+        let Some(value) = field_value(meta, *field) else {
+            continue;
+        };
+        let value = match value {
+            MetaValue::Text(text) => quote(&text),
+            MetaValue::Time(time) => time,
+            MetaValue::List(items) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|i| quote(i))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        lines.push(format!("{}{}{}", render_key(key), separator, value));
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    format!("{}\n{}\n{}\n\n", fence, lines.join("\n"), fence)
+}
@@ -0,0 +1,209 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use git2::build::CheckoutBuilder;
+use git2::{Delta, DiffOptions, IndexAddOption, Oid, Repository, Signature, Time};
+
+/// who a commit is attributed to, the committer is always the local git identity
+#[derive(Debug, Clone)]
+pub struct GitAuthor {
+    pub name: String,
+    pub email: String,
+    pub time: DateTime<Utc>,
+}
+
+/// paths relative to the repository root, as staged for the next commit
+#[derive(Debug, Clone, Default)]
+pub struct ChangeSet {
+    pub added: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A local git repository exports are written into and committed to
+pub struct GitOutput {
+    repo: Repository,
+    workdir: PathBuf,
+}
+
+impl GitOutput {
+    /// open the repository at `path`, creating it (and the directory) when missing.
+    /// Bare repositories have nowhere to write the export and are refused
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let repo = match Repository::open(path) {
+            Ok(repo) => repo,
+            Err(_) => {
+                std::fs::create_dir_all(path)?;
+                println!("Initializing git repository at {:?}", path);
+                Repository::init(path)?
+            }
+        };
+        let workdir = repo
+            .workdir()
+            .ok_or(format!(
+                "{:?} is a bare repository, it has no work tree",
+                path
+            ))?
+            .to_path_buf();
+        Ok(Self { repo, workdir })
+    }
+
+    pub fn workdir(&self) -> &Path {
+        &self.workdir
+    }
+
+    /// Switch HEAD to `branch`, creating it from the current HEAD when missing.
+    /// The checkout is safe: local modifications of the export make it fail instead of being lost.
+    pub fn checkout_branch(&self, branch: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let refname = format!("refs/heads/{}", branch);
+        let head = match self.repo.head() {
+            Ok(head) => head,
+            // nothing committed yet, the first commit creates the branch
+            Err(_) => {
+                self.repo.set_head(&refname)?;
+                return Ok(());
+            }
+        };
+        if head.name().ok() == Some(refname.as_str()) {
+            return Ok(());
+        }
+        if self.repo.find_reference(&refname).is_err() {
+            self.repo.branch(branch, &head.peel_to_commit()?, false)?;
+            println!("Created branch {}", branch);
+        }
+        let tree = self.repo.find_reference(&refname)?.peel_to_tree()?;
+        self.repo
+            .checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        self.repo.set_head(&refname)?;
+        Ok(())
+    }
+
+    /// Stage what differs from HEAD under `pathspecs` (relative to the repository root),
+    /// deletions included, and report what the next commit would contain.
+    /// Files outside the pathspecs are left alone.
+    pub fn stage_changes(
+        &self,
+        pathspecs: &[String],
+    ) -> Result<ChangeSet, Box<dyn std::error::Error>> {
+        let mut index = self.repo.index()?;
+        index.add_all(pathspecs, IndexAddOption::DEFAULT, None)?;
+        // add_all does not see files that are gone
+        index.update_all(pathspecs, None)?;
+        index.write()?;
+
+        let head_tree = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(_) => None,
+        };
+        let diff = self.repo.diff_tree_to_index(
+            head_tree.as_ref(),
+            Some(&index),
+            Some(&mut DiffOptions::new()),
+        )?;
+
+        let mut changes = ChangeSet::default();
+        for delta in diff.deltas() {
+            let path = |file: git2::DiffFile| file.path().map(|p| p.to_path_buf());
+            match delta.status() {
+                Delta::Added => changes.added.extend(path(delta.new_file())),
+                Delta::Deleted => changes.removed.extend(path(delta.old_file())),
+                _ => changes.changed.extend(path(delta.new_file())),
+            }
+        }
+        Ok(changes)
+    }
+
+    /// commit the index on top of HEAD, `author` keeps the original author and time
+    pub fn commit(
+        &self,
+        message: &str,
+        author: Option<&GitAuthor>,
+    ) -> Result<Oid, Box<dyn std::error::Error>> {
+        let mut index = self.repo.index()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let committer = self
+            .repo
+            .signature()
+            .or_else(|_| Signature::now("feishu2everywhere", "feishu2everywhere@localhost"))?;
+        let author = match author {
+            Some(a) => Signature::new(&a.name, &a.email, &Time::new(a.time.timestamp(), 0))?,
+            None => committer.clone(),
+        };
+        let parent = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(_) => None,
+        };
+        let oid = self.repo.commit(
+            Some("HEAD"),
+            &author,
+            &committer,
+            message,
+            &tree,
+            &parent.iter().collect::<Vec<_>>(),
+        )?;
+        println!("Committed {} to {:?}", oid, self.workdir());
+        Ok(oid)
+    }
+}
+
+/// what one export writes: `<stem>.md`, its `<stem>.rsc/` resources and the `<stem>.json` sidecar
+pub fn export_pathspecs(stem: &str) -> Vec<String> {
+    vec![
+        format!("{}.md", stem),
+        format!("{}.rsc", stem),
+        format!("{}.json", stem),
+    ]
+}
+
+/// `Sync <title>` followed by the added, changed and removed pages;
+/// resources (images, csv...) are only counted
+pub fn sync_message(title: &str, changes: &ChangeSet) -> String {
+    let is_page = |p: &&PathBuf| p.extension().is_some_and(|e| e == "md");
+    let mut message = format!("Sync {}\n", title);
+    let mut resources = 0;
+    for (heading, paths) in [
+        ("Added", &changes.added),
+        ("Changed", &changes.changed),
+        ("Removed", &changes.removed),
+    ] {
+        let pages = paths.iter().filter(is_page).collect::<Vec<_>>();
+        resources += paths.len() - pages.len();
+        if pages.is_empty() {
+            continue;
+        }
+        message.push_str(&format!("\n{}:\n", heading));
+        for page in pages {
+            message.push_str(&format!("- {}\n", page.display()));
+        }
+    }
+    if resources > 0 {
+        message.push_str(&format!("\n{} resource files updated\n", resources));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::temp_dir;
+
+    #[test]
+    fn test_open_refuses_bare_repository() {
+        let dir = temp_dir("bare-repo");
+        Repository::init_bare(&dir).unwrap();
+        assert!(GitOutput::open(&dir).is_err());
+
+        let dir = temp_dir("work-repo");
+        let git = GitOutput::open(&dir).unwrap();
+        assert_eq!(
+            git.workdir().canonicalize().unwrap(),
+            dir.canonicalize().unwrap()
+        );
+    }
+}
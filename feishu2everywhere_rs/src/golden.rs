@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Path;
+
+use crate::document::Document;
+use crate::mock_http::temp_dir;
+
+/// directory of the fixture document and the expected output of every exporter
+pub const GOLDEN_DIR: &str = "tests/golden";
+
+/// the fixture document, its image is `tests/golden/image.png`
+pub fn fixture() -> Document {
+    let json = fs::read_to_string(Path::new(GOLDEN_DIR).join("document.json")).unwrap();
+    Document::from_json(&json).unwrap()
+}
+
+/// Compare `actual` with `tests/golden/<name>`.
+/// Run with `UPDATE_GOLDEN=1` to write the current output instead, then review the diff
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(GOLDEN_DIR).join(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{:?}: {}, run with UPDATE_GOLDEN=1", path, e));
+    assert_eq!(actual, expected, "{} differs from {:?}", name, path);
+}
+
+/// Export the fixture to a scratch directory as `name` and compare it with `tests/golden/<name>`
+pub fn assert_export_golden(
+    name: &str,
+    export: impl FnOnce(&Document, &Path) -> Result<(), Box<dyn std::error::Error>>,
+) {
+    let path = temp_dir(name).join(name);
+    export(&fixture(), &path).unwrap();
+    assert_golden(name, &fs::read_to_string(&path).unwrap());
+}
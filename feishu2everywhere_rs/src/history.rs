@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use thirtyfour::{By, WebDriver};
+
+/// one entry of the version history panel
+#[derive(Debug, Clone)]
+pub struct VersionEntry {
+    /// position in the panel, used to open it again
+    pub index: usize,
+    pub label: String,
+    pub author: String,
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// which versions to export
+#[derive(Debug, Clone)]
+pub enum VersionSelection {
+    All,
+    /// 0 is the oldest version
+    Indices(Vec<usize>),
+}
+
+impl VersionSelection {
+    /// `all` or a comma separated list of indices, 0 is the oldest version
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if spec.trim() == "all" {
+            return Ok(VersionSelection::All);
+        }
+        spec.split(',')
+            .map(|part| {
+                part.trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("not a version index: {}", part))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(VersionSelection::Indices)
+    }
+}
+
+/// open the version history panel from the document header, returns whether it was found
+const OPEN_HISTORY_JS: &str = r#"
+const entry = document.querySelector(
+    '[data-e2e*=history], .history-entry, [class*=history-btn], [class*=history-entry]');
+if (!entry) return false;
+entry.click();
+return true;
+"#;
+
+const VERSION_ITEM_SELECTOR: &str = ".history-item, [class*=history-item], [class*=version-item]";
+
+/// [{label, author, time, datetime}] of the history panel, newest first as feishu lists them
+const LIST_VERSIONS_JS: &str = r#"
+const text = (root, sel) => {
+    const e = root.querySelector(sel);
+    return e ? (e.innerText || '').trim() : '';
+};
+return Array.from(document.querySelectorAll(arguments[0])).map((item) => {
+    const time = item.querySelector('time[datetime]');
+    return {
+        label: text(item, '[class*=name], [class*=title]'),
+        author: text(item, '[class*=user], [class*=author]'),
+        time: text(item, '[class*=time]'),
+        datetime: time ? time.getAttribute('datetime') : null,
+    };
+});
+"#;
+
+/// the panel shows local time without a zone
+fn parse_panel_time(datetime: Option<&str>, text: &str) -> Option<DateTime<Utc>> {
+    if let Some(t) = datetime.and_then(crate::document::parse_time) {
+        return Some(t);
+    }
+    [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y年%m月%d日 %H:%M",
+        "%Y/%m/%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text.trim(), format).ok())
+    .and_then(|t| Local.from_local_datetime(&t).single())
+    .map(|t| t.with_timezone(&Utc))
+}
+
+/// Open the history panel and list every version, oldest first
+pub async fn list_versions(driver: &WebDriver) -> Vec<VersionEntry> {
+    match driver.execute(OPEN_HISTORY_JS, vec![]).await {
+        Ok(ret) if ret.json().as_bool() == Some(true) => {}
+        _ => {
+            println!("version history entry not found");
+            return vec![];
+        }
+    }
+    let start = std::time::Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        if let Ok(items) = driver.find_all(By::Css(VERSION_ITEM_SELECTOR)).await
+            && !items.is_empty()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    let ret = match driver
+        .execute(LIST_VERSIONS_JS, vec![VERSION_ITEM_SELECTOR.into()])
+        .await
+    {
+        Ok(ret) => ret,
+        Err(err) => {
+            println!("err list versions: {:?}", err);
+            return vec![];
+        }
+    };
+    let items = ret.json().as_array().cloned().unwrap_or_default();
+    let count = items.len();
+    let str_of = |v: &serde_json::Value, name: &str| {
+        v.get(name)
+            .and_then(|s| s.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut versions = items
+        .iter()
+        .enumerate()
+        .map(|(panel_index, item)| VersionEntry {
+            index: panel_index,
+            label: str_of(item, "label"),
+            author: str_of(item, "author"),
+            time: parse_panel_time(
+                item.get("datetime").and_then(|d| d.as_str()),
+                &str_of(item, "time"),
+            ),
+        })
+        .collect::<Vec<_>>();
+    versions.reverse();
+    println!("document has {} versions", count);
+    versions
+}
+
+/// keep the selected versions, `versions` is oldest first
+pub fn select_versions(
+    versions: Vec<VersionEntry>,
+    selection: &VersionSelection,
+) -> Vec<VersionEntry> {
+    match selection {
+        VersionSelection::All => versions,
+        VersionSelection::Indices(indices) => versions
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| indices.contains(i))
+            .map(|(_, v)| v)
+            .collect(),
+    }
+}
+
+/// show `version` in the document area so the normal block collection reads it
+pub async fn open_version(driver: &WebDriver, version: &VersionEntry) -> bool {
+    let Ok(items) = driver.find_all(By::Css(VERSION_ITEM_SELECTOR)).await else {
+        return false;
+    };
+    let Some(item) = items.get(version.index) else {
+        println!("version {} is gone from the panel", version.index);
+        return false;
+    };
+    if let Err(err) = item.click().await {
+        println!("err open version {}: {:?}", version.index, err);
+        return false;
+    }
+    // the preview re-renders the whole document
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    true
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::document::FORMAT_VERSION;
+
+/// `schema` or null
+fn nullable(schema: Value) -> Value {
+    json!({ "anyOf": [schema, { "type": "null" }] })
+}
+
+fn string_or_null(description: &str) -> Value {
+    json!({ "type": ["string", "null"], "description": description })
+}
+
+fn array_of(definition: &str) -> Value {
+    json!({ "type": "array", "items": { "$ref": format!("#/$defs/{}", definition) } })
+}
+
+/// an object with exactly the given properties, all of them required
+fn record(description: &str, properties: Value) -> Value {
+    let required = properties
+        .as_object()
+        .map(|p| p.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    json!({
+        "type": "object",
+        "description": description,
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// blocks are objects with a single key, the snake case block kind
+fn variant(kind: &str, content: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { kind: content },
+        "required": [kind],
+        "additionalProperties": false,
+    })
+}
+
+fn block_schema() -> Value {
+    json!({
+        "description": "one block of the document, an object keyed by its kind",
+        "oneOf": [
+            variant("text", json!({
+                "type": "array",
+                "description": "a paragraph, runs of equally formatted text",
+                "items": { "$ref": "#/$defs/text_slice" },
+            })),
+            variant("title", record("a heading", json!({
+                "text": { "type": "string" },
+                "head_level": {
+                    "enum": ["h1", "h2", "h3", "h4", "h5", "h6", "h7", "h8", "h9", "h10"],
+                },
+            }))),
+            variant("list", record("a list, its items may hold blocks of their own", json!({
+                "list_type": { "enum": ["ordered", "unordered", "task"] },
+                "items": array_of("list_item"),
+            }))),
+            variant("image", record("an image stored by the exporter", json!({
+                "cached_path": {
+                    "type": "string",
+                    "description": "file of the image, relative to the working directory of the export",
+                },
+                "alt": string_or_null("alt text set on the image"),
+                "caption": string_or_null("caption shown under the image"),
+                "width": {
+                    "type": ["integer", "null"],
+                    "minimum": 0,
+                    "description": "display width in css pixels, null for full width",
+                },
+                "align": nullable(json!({ "enum": ["left", "center", "right"] })),
+            }))),
+            variant("code", record("a code block", json!({
+                "language": { "type": "string", "description": "as named by feishu, empty when unset" },
+                "code": { "type": "string" },
+            }))),
+            variant("sheet", record("an embedded sheet or bitable view", json!({
+                "cells": {
+                    "type": "array",
+                    "description": "row-major cell text, the first row is the header",
+                    "items": { "type": "array", "items": { "type": "string" } },
+                },
+            }))),
+            variant("diagram", record("a whiteboard, mind map, flowchart or diagram rendered to an image", json!({
+                "kind": { "enum": ["whiteboard", "mind_map", "flowchart", "diagram"] },
+                "cached_path": { "type": "string" },
+                "outline": nullable(json!({ "$ref": "#/$defs/outline_node" })),
+            }))),
+            variant("callout", record("a highlighted box holding other blocks", json!({
+                "emoji": string_or_null("emoji leading the box"),
+                "children": array_of("block"),
+            }))),
+        ],
+    })
+}
+
+/// JSON Schema (draft 2020-12) of the document sidecars written next to each export
+pub fn document_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "feishu2everywhere document",
+        "description": "A feishu document extracted by feishu2everywhere: metadata, the block tree and comments. \
+            `format_version` is raised on every change that is not backward compatible; \
+            files without it are version 1.",
+        "type": "object",
+        "properties": {
+            "format_version": { "const": FORMAT_VERSION },
+            "meta": { "$ref": "#/$defs/meta" },
+            "blocks": array_of("block"),
+            "comments": array_of("comment_thread"),
+        },
+        "required": ["meta", "blocks", "comments"],
+        "additionalProperties": false,
+        "$defs": {
+            "meta": record("what is known about the document, every field is best effort", json!({
+                "title": string_or_null("document title"),
+                "author": string_or_null("owner of the document"),
+                "created": nullable(json!({ "type": "string", "format": "date-time" })),
+                "modified": nullable(json!({ "type": "string", "format": "date-time" })),
+                "source_url": string_or_null("url the document was exported from"),
+                "wiki_path": {
+                    "type": "array",
+                    "description": "titles from the wiki space down to the parent of the document, empty outside wikis",
+                    "items": { "type": "string" },
+                },
+            })),
+            "block": block_schema(),
+            "text_slice": {
+                "type": "object",
+                "description": "a run of text, formatting flags default to false when left out",
+                "properties": {
+                    "text": { "type": "string" },
+                    "is_bold": { "type": "boolean" },
+                    "is_underline": { "type": "boolean" },
+                    "is_code": { "type": "boolean" },
+                    "link": string_or_null("target of the link the text is in"),
+                },
+                "required": ["text"],
+                "additionalProperties": false,
+            },
+            "list_item": {
+                "type": "object",
+                "properties": {
+                    "done": {
+                        "type": ["boolean", "null"],
+                        "description": "whether a task is checked, null outside task lists",
+                    },
+                    "headline": array_of("text_slice"),
+                    "following": {
+                        "type": "array",
+                        "description": "blocks nested under the item",
+                        "items": { "$ref": "#/$defs/block" },
+                    },
+                },
+                "required": ["headline"],
+                "additionalProperties": false,
+            },
+            "outline_node": record("a mind map node, the root is the central topic", json!({
+                "text": { "type": "string" },
+                "children": array_of("outline_node"),
+            })),
+            "comment_thread": record("a comment thread, the first comment opened it", json!({
+                "anchor": { "type": "string", "description": "the commented text, empty for the whole document" },
+                "resolved": { "type": "boolean" },
+                "comments": array_of("comment"),
+                "block_index": {
+                    "type": ["integer", "null"],
+                    "minimum": 0,
+                    "description": "index of the top level block the anchor was found in",
+                },
+            })),
+            "comment": record("one comment of a thread", json!({
+                "author": { "type": "string" },
+                "time": string_or_null("as shown by feishu, rfc 3339 when it comes from the api"),
+                "text": { "type": "string" },
+            })),
+        },
+    })
+}
+
+/// Write the schema to `path`, or print it without one
+pub fn write_schema(path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+    let schema = serde_json::to_string_pretty(&document_schema())?;
+    match path {
+        Some(path) => {
+            fs::write(path, schema + "\n")?;
+            println!("schema written to {:?}", path);
+        }
+        None => println!("{}", schema),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the published schema is regenerated with the `schema` command
+    #[test]
+    fn test_published_schema_is_current() {
+        let published = fs::read_to_string("schema/document.schema.json").unwrap();
+        assert_eq!(
+            published,
+            serde_json::to_string_pretty(&document_schema()).unwrap() + "\n"
+        );
+    }
+}
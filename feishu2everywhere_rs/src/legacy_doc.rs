@@ -0,0 +1,357 @@
+use serde_json::Value;
+use thirtyfour::{By, WebDriver};
+
+use crate::assets::AssetStore;
+use crate::block::{self, Block, HeadLevel, ListOne, ListType, TextSlice};
+use crate::diagram;
+
+/// Dump every line of the legacy editor in one round trip.
+///
+/// The legacy editor is etherpad based: `#innerdocbody` has one `.ace-line` per line and
+/// the line type lives in class names (`heading-h2`, `list-bullet1`, `list-number2`,
+/// `list-check1`, `list-done1`...). Unlike docx it is not virtualized, so all lines exist.
+/// Images get a `data-f2e-img` index so they can be looked up as elements afterwards.
+const DUMP_LINES_JS: &str = r#"
+const body = document.querySelector('#innerdocbody, .innerdocbody');
+if (!body) return [];
+const styleOf = (node, line) => {
+    const st = { bold: false, underline: false, code: false, link: null };
+    for (let el = node.parentElement; el && el !== line; el = el.parentElement) {
+        const tag = el.tagName;
+        const cls = typeof el.className === 'string' ? el.className : '';
+        const css = el.getAttribute('style') || '';
+        if (tag === 'B' || tag === 'STRONG' || /font-weight:\s*(bold|[6-9]00)/.test(css) || /\bbold\b/.test(cls)) st.bold = true;
+        if (tag === 'U' || /text-decoration[^;]*underline/.test(css) || /\bunderline\b/.test(cls)) st.underline = true;
+        if (tag === 'CODE' || /inline-code/.test(cls)) st.code = true;
+        if (tag === 'A' && el.href && !st.link) st.link = el.href;
+    }
+    return st;
+};
+const out = [];
+let imgSeq = 0;
+for (const line of body.querySelectorAll(':scope > *')) {
+    const cls = [line, ...line.querySelectorAll(':scope > *')]
+        .map((e) => (typeof e.className === 'string' ? e.className : '') + ' ' + e.tagName.toLowerCase())
+        .join(' ');
+    const codeElem = line.querySelector('pre, [class*=code-block]');
+    const images = [];
+    for (const img of line.querySelectorAll('img')) {
+        // emoji and avatars are inline decoration, not document images
+        if (img.naturalWidth < 32 && img.naturalHeight < 32) continue;
+        img.setAttribute('data-f2e-img', String(imgSeq));
+        images.push(imgSeq++);
+    }
+    const slices = [];
+    const walker = document.createTreeWalker(line, NodeFilter.SHOW_TEXT);
+    for (let node = walker.nextNode(); node; node = walker.nextNode()) {
+        const text = node.nodeValue.replace(/[\u200b\ufeff]/g, '');
+        if (!text) continue;
+        const st = styleOf(node, line);
+        const last = slices[slices.length - 1];
+        if (last && last.bold === st.bold && last.underline === st.underline
+            && last.code === st.code && last.link === st.link) {
+            last.text += text;
+        } else {
+            slices.push(Object.assign({ text: text }, st));
+        }
+    }
+    out.push({
+        cls: cls,
+        code: codeElem ? codeElem.innerText : null,
+        language: codeElem ? (codeElem.getAttribute('data-language') || '') : '',
+        images: images,
+        slices: slices,
+    });
+}
+return out;
+"#;
+
+enum LegacyLine {
+    Heading(HeadLevel, String),
+    ListItem {
+        level: usize,
+        list_type: ListType,
+        item: ListOne,
+    },
+    Code {
+        language: String,
+        code: String,
+    },
+    Block(Block),
+}
+
+fn parse_head_level(cls: &str) -> Option<HeadLevel> {
+    // heading-h1 ... heading-h9, or a plain <h1>...<h6> child
+    let level = cls
+        .split_whitespace()
+        .find_map(|c| {
+            c.strip_prefix("heading-h")
+                .or_else(|| c.strip_prefix('h').filter(|n| n.len() == 1))
+        })
+        .and_then(|n| n.parse::<u32>().ok())?;
+    match level {
+        1 => Some(HeadLevel::H1),
+        2 => Some(HeadLevel::H2),
+        3 => Some(HeadLevel::H3),
+        4 => Some(HeadLevel::H4),
+        5 => Some(HeadLevel::H5),
+        6 => Some(HeadLevel::H6),
+        7 => Some(HeadLevel::H7),
+        8 => Some(HeadLevel::H8),
+        9 => Some(HeadLevel::H9),
+        _ => None,
+    }
+}
+
+/// (indent level starting at 1, list type, done) from list-bullet1 / list-number2 / list-done1 ...
+fn parse_list_class(cls: &str) -> Option<(usize, ListType, Option<bool>)> {
+    for c in cls.split_whitespace() {
+        let Some(rest) = c.strip_prefix("list-") else {
+            continue;
+        };
+        // list-wrapper and other classes without a level are not the list class
+        let Some(kind_len) = rest.find(|ch: char| ch.is_ascii_digit()) else {
+            continue;
+        };
+        let (kind, level) = rest.split_at(kind_len);
+        let Ok(level) = level.parse::<usize>() else {
+            continue;
+        };
+        let (list_type, done) = match kind {
+            "bullet" => (ListType::Unordered, None),
+            "number" => (ListType::Ordered, None),
+            "check" | "todo" => (ListType::Task, Some(false)),
+            "done" | "checked" => (ListType::Task, Some(true)),
+            _ => continue,
+        };
+        return Some((level.max(1), list_type, done));
+    }
+    None
+}
+
+fn parse_slices(line: &Value) -> Vec<TextSlice> {
+    line.get("slices")
+        .and_then(|s| s.as_array())
+        .map(|slices| {
+            slices
+                .iter()
+                .map(|s| TextSlice {
+                    text: s
+                        .get("text")
+                        .and_then(|t| t.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                    is_bold: s.get("bold").and_then(|b| b.as_bool()).unwrap_or(false),
+                    is_underline: s
+                        .get("underline")
+                        .and_then(|b| b.as_bool())
+                        .unwrap_or(false),
+                    is_code: s.get("code").and_then(|b| b.as_bool()).unwrap_or(false),
+                    link: s
+                        .get("link")
+                        .and_then(|l| l.as_str())
+                        .map(|l| l.to_string()),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn image_block(driver: &WebDriver, assets: &AssetStore, index: u64) -> Option<Block> {
+    let img = driver
+        .find(By::Css(&format!("[data-f2e-img=\"{}\"]", index)))
+        .await
+        .ok()?;
+    let _ = img.scroll_into_view().await;
+    let (data, ext) = block::fetch_original_image(driver, &img).await?;
+    let cached_path = assets.store_image(&data, ext)?;
+    let line = img
+        .find(By::XPath("./ancestor::*[contains(@class, 'ace-line')][1]"))
+        .await
+        .unwrap_or_else(|_| img.clone());
+    let (alt, width, align) = block::read_image_layout(driver, &line, &img).await;
+    Some(Block::Image {
+        cached_path,
+        alt,
+        caption: None,
+        width,
+        align,
+    })
+}
+
+/// nest flat (level, item) list lines into ListOne.following, the same shape
+/// `construct_blocks` builds for docx
+fn nest_list_items(flat: Vec<(usize, ListType, ListOne)>) -> Vec<Block> {
+    fn push(out: &mut Vec<Block>, list_type: ListType, item: ListOne) {
+        if let Some(Block::List {
+            list_type: last_type,
+            items,
+        }) = out.last_mut()
+            && *last_type == list_type
+        {
+            items.push(item);
+            return;
+        }
+        out.push(Block::List {
+            list_type,
+            items: vec![item],
+        });
+    }
+
+    // blocks of the current nesting path, root first
+    fn last_item_following(blocks: &mut [Block], depth: usize) -> Option<&mut Vec<Block>> {
+        let Some(Block::List { items, .. }) = blocks.last_mut() else {
+            return None;
+        };
+        let following = &mut items.last_mut()?.following;
+        if depth == 0 {
+            Some(following)
+        } else {
+            last_item_following(following, depth - 1)
+        }
+    }
+
+    let mut out = vec![];
+    for (level, list_type, item) in flat {
+        // attach under the deepest existing item above this level
+        let mut depth = level - 1;
+        loop {
+            if depth == 0 {
+                push(&mut out, list_type, item);
+                break;
+            }
+            if let Some(parent) = last_item_following(&mut out, depth - 1) {
+                push(parent, list_type, item);
+                break;
+            }
+            depth -= 1;
+        }
+    }
+    out
+}
+
+/// Collect the legacy document into the same Block model as the docx extractor
+pub async fn collect_blocks(driver: &WebDriver, assets: &AssetStore) -> Vec<Block> {
+    // images are lazy loaded, pass over the whole body once
+    if let Ok(body) = driver.find(By::Css("#innerdocbody, .innerdocbody")).await {
+        diagram::walk_through_element(driver, &body).await;
+    }
+
+    let lines = match driver.execute(DUMP_LINES_JS, vec![]).await {
+        Ok(ret) => ret.json().as_array().cloned().unwrap_or_default(),
+        Err(err) => {
+            println!("err dump legacy lines: {:?}", err);
+            return vec![];
+        }
+    };
+    println!("legacy doc has {} lines", lines.len());
+
+    let mut parsed = vec![];
+    for line in &lines {
+        let cls = line.get("cls").and_then(|c| c.as_str()).unwrap_or_default();
+
+        if let Some(code) = line.get("code").and_then(|c| c.as_str()) {
+            let language = line
+                .get("language")
+                .and_then(|l| l.as_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            parsed.push(LegacyLine::Code {
+                language,
+                code: code.to_string(),
+            });
+            continue;
+        }
+
+        for index in line
+            .get("images")
+            .and_then(|i| i.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|i| i.as_u64())
+        {
+            if let Some(image) = image_block(driver, assets, index).await {
+                parsed.push(LegacyLine::Block(image));
+            }
+        }
+
+        let slices = parse_slices(line);
+        if slices.iter().all(|s| s.text.trim().is_empty()) {
+            continue;
+        }
+
+        if let Some((level, list_type, done)) = parse_list_class(cls) {
+            parsed.push(LegacyLine::ListItem {
+                level,
+                list_type,
+                item: ListOne::new(slices, done, vec![]),
+            });
+        } else if let Some(head_level) = parse_head_level(cls) {
+            let text = slices.iter().map(|s| s.text.as_str()).collect::<String>();
+            parsed.push(LegacyLine::Heading(head_level, text.trim().to_string()));
+        } else {
+            parsed.push(LegacyLine::Block(Block::Text(slices)));
+        }
+    }
+
+    // group runs of list lines and code lines
+    let mut blocks = vec![];
+    let mut pending_list = vec![];
+    for line in parsed {
+        if let LegacyLine::ListItem {
+            level,
+            list_type,
+            item,
+        } = line
+        {
+            pending_list.push((level, list_type, item));
+            continue;
+        }
+        blocks.extend(nest_list_items(std::mem::take(&mut pending_list)));
+
+        match line {
+            LegacyLine::Heading(head_level, text) => blocks.push(Block::Title { text, head_level }),
+            LegacyLine::Code { language, code } => {
+                if let Some(Block::Code {
+                    language: last_language,
+                    code: last_code,
+                }) = blocks.last_mut()
+                    && *last_language == language
+                {
+                    // one line per .ace-line, join consecutive code lines
+                    last_code.push_str(&code);
+                    last_code.push('\n');
+                } else {
+                    blocks.push(Block::Code {
+                        language,
+                        code: format!("{}\n", code),
+                    });
+                }
+            }
+            LegacyLine::Block(block) => blocks.push(block),
+            LegacyLine::ListItem { .. } => unreachable!(),
+        }
+    }
+    blocks.extend(nest_list_items(pending_list));
+
+    println!("legacy doc is all dump, {} blocks", blocks.len());
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list_class_skips_classes_without_level() {
+        assert_eq!(
+            parse_list_class("list-wrapper list-bullet1"),
+            Some((1, ListType::Unordered, None))
+        );
+        assert_eq!(
+            parse_list_class("list-wrapper list-done2"),
+            Some((2, ListType::Task, Some(true)))
+        );
+        assert_eq!(parse_list_class("list-wrapper"), None);
+    }
+}
@@ -1,8 +1,47 @@
+mod assets;
+mod bitable;
 mod block;
+mod comments;
+mod confluence_api;
+mod diagram;
+mod document;
+mod epub;
+mod feishu_api;
+mod flavour;
+mod front_matter;
+mod git_output;
+#[cfg(test)]
+mod golden;
+mod history;
+mod json_schema;
+mod legacy_doc;
 mod log;
+mod markdown_dialect;
+mod mindnote;
+#[cfg(test)]
+mod mock_http;
+mod notion_api;
+mod obsidian;
+mod outline;
 mod poll_keys;
+mod sheet;
+mod site;
+mod spreadsheet;
+mod to_asciidoc;
+mod to_confluence;
+mod to_docx;
+mod to_html;
+mod to_latex;
 mod to_markdown;
+mod to_notion;
+mod to_org;
+mod to_pandoc;
+mod to_rst;
+mod to_sqlite;
+mod to_typst;
+mod to_xlsx;
 mod webelement_ext;
+mod xml;
 
 use std::borrow::BorrowMut;
 use std::cell::RefCell;
@@ -11,6 +50,7 @@ use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::Write;
 use std::ops::Mul;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::{
     Arc,
@@ -19,21 +59,89 @@ use std::sync::{
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use assets::{ASSET_STORE_DIR, AssetStore, ImageFormatChoice, ImageOptions};
 use async_recursion::async_recursion;
 use base64::{Engine as _, engine::general_purpose};
 use block::{Block, ListOne, ListType, OneOf};
+use clap::{CommandFactory, Parser, ValueEnum};
+use comments::CommentStyle;
 use device_query::{DeviceQuery, DeviceState, Keycode};
+use document::Document;
+use flavour::EditorFlavour;
+use front_matter::{FrontMatterFormat, FrontMatterKeys};
 use log::LogType;
+use markdown_dialect::{HeadingOverflow, ListBullet, MarkdownDialect};
 use thirtyfour::{By, DesiredCapabilities, WebDriver, WebElement};
+use to_markdown::{MarkdownFlavour, MarkdownOptions};
+use to_org::OrgTasks;
 use tokio;
 use tokio::process::{Child, Command};
 
 #[tokio::main]
 async fn main() {
-    let config = Config {
-        headless: false,
-        output_md: "out.md".to_string(),
-    };
+    let config = Config::parse();
+    if let Some(SubCommand::Site(args)) = &config.command {
+        let title = args.title.as_deref().unwrap_or("Documents");
+        site::build_site(&args.input, &args.output, title, args.theme_css.as_deref()).unwrap();
+        return;
+    }
+    if let Some(SubCommand::Epub(args)) = &config.command {
+        let options = epub::BookOptions {
+            title: args.title.as_deref(),
+            author: args.author.as_deref(),
+            language: &args.language,
+        };
+        epub::build_epub(&args.input, &args.output, &options).unwrap();
+        return;
+    }
+    if let Some(SubCommand::Schema(args)) = &config.command {
+        json_schema::write_schema(args.output.as_deref()).unwrap();
+        return;
+    }
+    let assets = AssetStore::new(ASSET_STORE_DIR, config.image_options());
+
+    if config.mode == Mode::Bitable
+        || (config.mode == Mode::Auto && bitable::is_bitable_url(config.url()))
+    {
+        let client = feishu_api::FeishuClient::new(&config.api_base, config.api_auth())
+            .await
+            .unwrap();
+        let tables = bitable::fetch_bitable(&client, config.url()).await.unwrap();
+        let out_path = Path::new(&config.output_md);
+        let sqlite_path = out_path.with_extension("sqlite");
+        bitable::export_bitable(
+            &tables,
+            &out_path.with_extension(""),
+            &out_path.with_extension("json"),
+            config.sqlite.then_some(sqlite_path.as_path()),
+        )
+        .unwrap();
+        return;
+    }
+
+    if config.source == Source::Api {
+        let client = feishu_api::FeishuClient::new(&config.api_base, config.api_auth())
+            .await
+            .unwrap();
+        let mut doc = Document {
+            meta: feishu_api::fetch_document_meta(&client, config.url())
+                .await
+                .unwrap(),
+            blocks: feishu_api::fetch_document_blocks(&client, config.url(), &assets)
+                .await
+                .unwrap(),
+            comments: vec![],
+        };
+        if config.comments != CommentStyle::None {
+            doc.comments = feishu_api::fetch_document_comments(&client, config.url())
+                .await
+                .unwrap();
+            comments::attach_to_blocks(&mut doc.comments, &doc.blocks);
+        }
+        write_document(&config, &doc).unwrap();
+        publish_document(&config, &doc).await.unwrap();
+        return;
+    }
 
     kill_old_chrome().await;
 
@@ -55,10 +163,7 @@ async fn main() {
     let driver = WebDriver::new("http://localhost:9518", caps).await.unwrap();
 
     // Navigate to the Feishu document
-    driver
-        .goto("https://qcnoe3hd7k5c.feishu.cn/wiki/FSe2wulLqiHcI8kgCqIcmmlknnh")
-        .await
-        .unwrap();
+    driver.goto(config.url()).await.unwrap();
 
     // Wait for page to load
     tokio::time::sleep(Duration::from_secs(1)).await;
@@ -75,7 +180,61 @@ async fn main() {
 
     poll_keys::start_poll_keys(running.clone());
 
-    let final_blocks = collect_blocks(&running, &driver).await;
+    let editor_flavour = match config.mode {
+        Mode::Sheets => Some(EditorFlavour::Sheets),
+        Mode::Mindnote => Some(EditorFlavour::Mindnote),
+        Mode::Doc => flavour::detect_editor_flavour(&driver)
+            .await
+            .filter(|f| !matches!(f, EditorFlavour::Sheets | EditorFlavour::Mindnote)),
+        // bitable never gets here, it is read through the api above
+        Mode::Auto | Mode::Bitable => match flavour::flavour_from_url(config.url()) {
+            Some(f) => Some(f),
+            None => flavour::detect_editor_flavour(&driver).await,
+        },
+    };
+
+    // documents that are not exported to markdown blocks
+    if let Some(flavour @ (EditorFlavour::Sheets | EditorFlavour::Mindnote)) = editor_flavour {
+        let out_path = Path::new(&config.output_md);
+        if flavour == EditorFlavour::Sheets {
+            let tabs = spreadsheet::collect_tabs(&driver).await;
+            let xlsx_path = out_path.with_extension("xlsx");
+            spreadsheet::export_tabs(
+                &tabs,
+                &out_path.with_extension(""),
+                config.xlsx.then_some(xlsx_path.as_path()),
+            )
+            .unwrap();
+        } else if let Some(tree) = mindnote::collect_tree(&driver).await {
+            mindnote::export_tree(&tree, &config.output_md).unwrap();
+        }
+        tokio::signal::ctrl_c().await.unwrap();
+        driver.quit().await.unwrap();
+        child.kill().await.unwrap();
+        return;
+    }
+
+    // the title and info bar are at the top, read them before scrolling away
+    let meta = document::collect_page_meta(&driver, config.url()).await;
+
+    if let Some(selection) = &config.history {
+        export_history(&config, selection, &running, &driver, &assets, &meta)
+            .await
+            .unwrap();
+        tokio::signal::ctrl_c().await.unwrap();
+        driver.quit().await.unwrap();
+        child.kill().await.unwrap();
+        return;
+    }
+
+    let final_blocks = match editor_flavour {
+        Some(EditorFlavour::LegacyDoc) => legacy_doc::collect_blocks(&driver, &assets).await,
+        // fall back to docx, it keeps polling until the blocks show up
+        _ => collect_blocks(&running, &driver, &assets)
+            .await
+            .into_values()
+            .collect::<Vec<_>>(),
+    };
 
     // for a elem, child of whose child should be removed from its children
 
@@ -112,11 +271,17 @@ async fn main() {
     //     tokio::time::sleep(Duration::from_secs(1000)).await;
     // }
 
-    to_markdown::export_blocks_to_markdown(
-        &final_blocks.into_values().collect::<Vec<_>>(),
-        &config.output_md,
-    )
-    .unwrap();
+    let mut doc = Document {
+        meta,
+        blocks: final_blocks,
+        comments: vec![],
+    };
+    if config.comments != CommentStyle::None {
+        doc.comments = comments::collect_page_comments(&driver).await;
+        comments::attach_to_blocks(&mut doc.comments, &doc.blocks);
+    }
+    write_document(&config, &doc).unwrap();
+    publish_document(&config, &doc).await.unwrap();
 
     // wait for ctrl+c
     tokio::signal::ctrl_c().await.unwrap();
@@ -127,6 +292,245 @@ async fn main() {
     child.kill().await.unwrap();
 }
 
+/// Write `<stem>.md`, its sidecar, resources and extra formats into the repository
+/// work tree, returns the pathspecs covering them
+fn write_to_workdir(
+    config: &Config,
+    git: &git_output::GitOutput,
+    doc: &Document,
+    stem: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    // resources dropped since the last sync must show up as removed
+    let rsc_path = git.workdir().join(format!("{}.rsc", stem));
+    if rsc_path.exists() {
+        fs::remove_dir_all(&rsc_path)?;
+    }
+    write_document_files(config, doc, &git.workdir().join(format!("{}.md", stem)))?;
+
+    let mut pathspecs = git_output::export_pathspecs(stem);
+    for (enabled, extension) in [
+        (config.docx, "docx"),
+        (config.latex, "tex"),
+        (config.typst, "typ"),
+        (config.confluence, "xhtml"),
+        (config.notion, "notion.json"),
+        (config.pandoc, "pandoc.json"),
+        (config.rst, "rst"),
+        (config.asciidoc, "adoc"),
+        (config.org, "org"),
+    ] {
+        if enabled {
+            pathspecs.push(format!("{}.{}", stem, extension));
+        }
+    }
+    if config.markdown_flavour == MarkdownFlavour::Obsidian {
+        // shared by every page, nothing is removed from it
+        pathspecs.push(config.attachments_dir.to_string_lossy().replace('\\', "/"));
+    }
+    Ok(pathspecs)
+}
+
+/// the markdown at `md_path`, its `.json` sidecar and the extra `--format` outputs next to it
+fn write_document_files(
+    config: &Config,
+    doc: &Document,
+    md_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    to_markdown::export_document_to_markdown(
+        doc,
+        &md_path.to_string_lossy(),
+        &config.page_markdown_options(doc, md_path),
+    )?;
+    config.export_extra_formats(doc, md_path)?;
+    doc.save_json(&md_path.with_extension("json"))
+}
+
+/// Write the export to --output-md, or into --git-repo and commit what changed
+fn write_document(config: &Config, doc: &Document) -> Result<(), Box<dyn std::error::Error>> {
+    let output_md = config.output_md_path(doc);
+    let stem = output_md
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy()
+        .to_string();
+    let Some(repo_path) = &config.git_repo else {
+        return write_document_files(config, doc, &output_md);
+    };
+    let git = git_output::GitOutput::open(Path::new(repo_path))?;
+    if let Some(branch) = &config.git_branch {
+        git.checkout_branch(branch)?;
+    }
+
+    let pathspecs = write_to_workdir(config, &git, doc, &stem)?;
+    let changes = git.stage_changes(&pathspecs)?;
+    if changes.is_empty() {
+        println!("export is unchanged, nothing to commit");
+        return Ok(());
+    }
+    let title = doc.meta.title.clone().unwrap_or(stem);
+    git.commit(&git_output::sync_message(&title, &changes), None)?;
+    Ok(())
+}
+
+/// Publish the document to the confluence and notion targets given
+async fn publish_document(
+    config: &Config,
+    doc: &Document,
+) -> Result<(), Box<dyn std::error::Error>> {
+    publish_to_confluence(config, doc).await?;
+    publish_to_notion(config, doc).await
+}
+
+/// the document title, the output file stem without one
+fn published_title(config: &Config, doc: &Document) -> String {
+    match &doc.meta.title {
+        Some(title) => title.clone(),
+        None => config
+            .output_md_path(doc)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    }
+}
+
+/// Create or update the confluence page of the document when --confluence-url is set
+async fn publish_to_confluence(
+    config: &Config,
+    doc: &Document,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(url) = &config.confluence_url else {
+        return Ok(());
+    };
+    let (Some(space), Some(token)) = (&config.confluence_space, &config.confluence_token) else {
+        Config::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "publishing to confluence needs --confluence-space and --confluence-token",
+            )
+            .exit()
+    };
+    let auth = match &config.confluence_user {
+        Some(user) => confluence_api::ConfluenceAuth::Basic {
+            user: user.clone(),
+            token: token.clone(),
+        },
+        None => confluence_api::ConfluenceAuth::Bearer(token.clone()),
+    };
+    let client = confluence_api::ConfluenceClient::new(url, auth);
+    let target = confluence_api::PublishTarget {
+        space: space.clone(),
+        parent_id: config.confluence_parent.clone(),
+    };
+    confluence_api::publish_document(&client, &target, doc, &published_title(config, doc)).await?;
+    Ok(())
+}
+
+/// Create or replace the notion page of the document when --notion-parent is set
+async fn publish_to_notion(
+    config: &Config,
+    doc: &Document,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(parent) = &config.notion_parent else {
+        return Ok(());
+    };
+    let Some(token) = &config.notion_token else {
+        Config::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "publishing to notion needs --notion-token",
+            )
+            .exit()
+    };
+    let client = notion_api::NotionClient::new(&config.notion_api_base, token);
+    notion_api::publish_document(&client, parent, doc, &published_title(config, doc)).await?;
+    Ok(())
+}
+
+/// Collect every selected version from the history panel, each as its own snapshot
+/// (`<stem>.history/<n>_<time>.md`) or as successive commits of `<stem>.md` in a git repository
+async fn export_history(
+    config: &Config,
+    selection: &history::VersionSelection,
+    running: &AtomicBool,
+    driver: &WebDriver,
+    assets: &AssetStore,
+    meta: &document::DocumentMeta,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let versions = history::select_versions(history::list_versions(driver).await, selection);
+    let git = match &config.git_repo {
+        Some(repo_path) => {
+            let git = git_output::GitOutput::open(Path::new(repo_path))?;
+            if let Some(branch) = &config.git_branch {
+                git.checkout_branch(branch)?;
+            }
+            Some(git)
+        }
+        None => None,
+    };
+
+    for (n, version) in versions.iter().enumerate() {
+        println!("exporting version {}: {:?}", n, version);
+        if !history::open_version(driver, version).await {
+            continue;
+        }
+        let doc = Document {
+            meta: document::DocumentMeta {
+                modified: version.time,
+                ..meta.clone()
+            },
+            blocks: collect_blocks(running, driver, assets)
+                .await
+                .into_values()
+                .collect(),
+            comments: vec![],
+        };
+        // the same file a regular sync of this document writes
+        let out_path = config.output_md_path(&doc);
+        let stem = out_path
+            .file_stem()
+            .ok_or("output path has no file stem")?
+            .to_string_lossy()
+            .to_string();
+
+        let Some(git) = &git else {
+            let time = version
+                .time
+                .map(|t| t.format("%Y%m%d-%H%M").to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let snapshot = out_path
+                .with_extension("history")
+                .join(format!("{:03}_{}.md", n, time));
+            std::fs::create_dir_all(snapshot.parent().unwrap())?;
+            write_document_files(config, &doc, &snapshot)?;
+            continue;
+        };
+
+        let pathspecs = write_to_workdir(config, git, &doc, &stem)?;
+        if git.stage_changes(&pathspecs)?.is_empty() {
+            println!("version {} has no changes, no commit", n);
+            continue;
+        }
+        let label = if version.label.is_empty() {
+            format!("Version {}", n + 1)
+        } else {
+            version.label.clone()
+        };
+        let author = version.time.map(|time| git_output::GitAuthor {
+            name: if version.author.is_empty() {
+                "unknown".to_string()
+            } else {
+                version.author.clone()
+            },
+            // feishu does not show emails in the panel
+            email: "noreply@feishu.invalid".to_string(),
+            time,
+        });
+        git.commit(&format!("{}: {}", stem, label), author.as_ref())?;
+    }
+    Ok(())
+}
+
 /// A wrapper around WebElement that includes its block ID for ordering
 
 struct WebElementWithId {
@@ -217,7 +621,11 @@ struct InternalBlockPart {
 }
 
 /// return blockid -> (webelement, children ids)
-async fn collect_blocks(running: &AtomicBool, driver: &WebDriver) -> BTreeMap<BlockId, Block> {
+async fn collect_blocks(
+    running: &AtomicBool,
+    driver: &WebDriver,
+    assets: &AssetStore,
+) -> BTreeMap<BlockId, Block> {
     // let mut last_id = None;
     let mut all_skip_times = 0;
     let mut collected_blocks = HashMap::new();
@@ -269,7 +677,7 @@ async fn collect_blocks(running: &AtomicBool, driver: &WebDriver) -> BTreeMap<Bl
             println!("\n=============one element=============");
             println!("id: {}", id);
             println!("text: {}", e.text().await.unwrap());
-            let blockpart = Block::new_by_element(&driver, "one", &e).await;
+            let blockpart = Block::new_by_element(&driver, assets, &e).await;
 
             if blockpart.is_none() {
                 println!("unrecognized element");
@@ -394,9 +802,348 @@ async fn collect_blocks(running: &AtomicBool, driver: &WebDriver) -> BTreeMap<Bl
     final_blocks
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Source {
+    /// scrape the rendered document with chromedriver
+    Browser,
+    /// read the document through the feishu open api
+    Api,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    Auto,
+    /// docx or legacy doc, exported to markdown
+    Doc,
+    /// standalone sheet, every tab to csv (and xlsx with --xlsx)
+    Sheets,
+    /// mind note, to opml, freemind .mm and a markdown outline
+    Mindnote,
+    /// base, read through the open api: csv per table, typed json (and sqlite with --sqlite)
+    Bitable,
+}
+
+#[derive(clap::Subcommand)]
+enum SubCommand {
+    /// render a static html site from a folder of exports (their `.json` sidecars)
+    Site(SiteArgs),
+    /// package a folder of exports into an epub book, a chapter per document
+    Epub(EpubArgs),
+    /// print the json schema of the `.json` sidecars
+    Schema(SchemaArgs),
+}
+
+#[derive(clap::Args)]
+struct SiteArgs {
+    /// folder searched recursively for exported documents
+    #[arg(default_value = ".")]
+    input: PathBuf,
+    #[arg(long, default_value = "site")]
+    output: PathBuf,
+    /// shown at the top of the navigation and in page titles
+    #[arg(long)]
+    title: Option<String>,
+    /// stylesheet loaded after the default theme
+    #[arg(long)]
+    theme_css: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct EpubArgs {
+    /// folder searched recursively for exported documents
+    #[arg(default_value = ".")]
+    input: PathBuf,
+    #[arg(long, default_value = "book.epub")]
+    output: PathBuf,
+    /// book title, defaults to the document title when there is only one
+    #[arg(long)]
+    title: Option<String>,
+    /// defaults to the document authors
+    #[arg(long)]
+    author: Option<String>,
+    /// BCP 47 language tag of the book
+    #[arg(long, default_value = "zh-CN")]
+    language: String,
+}
+
+#[derive(clap::Args)]
+struct SchemaArgs {
+    /// write the schema to this file instead
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// Export a feishu document to markdown
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Config {
-    headless: bool,
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+    /// document url, eg. https://xxx.feishu.cn/wiki/<token> or https://xxx.feishu.cn/docx/<id>
+    url: Option<String>,
+    #[arg(long, default_value = "out.md")]
     output_md: String,
+    /// run chrome without a window
+    #[arg(long)]
+    headless: bool,
+    #[arg(long, value_enum, default_value_t = Source::Browser)]
+    source: Source,
+    /// what kind of document the url is, auto looks at the url and the page
+    #[arg(long, value_enum, default_value_t = Mode::Auto)]
+    mode: Mode,
+    /// sheets mode: also write every tab into one workbook next to the csv folder
+    #[arg(long)]
+    xlsx: bool,
+    /// bitable mode: also write every table into a sqlite database
+    #[arg(long)]
+    sqlite: bool,
+    /// also write the document as a word file next to the markdown
+    #[arg(long)]
+    docx: bool,
+    /// also write a latex source next to the markdown, for xelatex
+    #[arg(long)]
+    latex: bool,
+    /// also write a typst source next to the markdown
+    #[arg(long)]
+    typst: bool,
+    /// also write the page in confluence storage format (`<stem>.xhtml`)
+    #[arg(long)]
+    confluence: bool,
+
+    /// publish the document to this confluence site, eg. https://example.atlassian.net/wiki
+    #[arg(long, env = "CONFLUENCE_URL")]
+    confluence_url: Option<String>,
+    /// key of the space pages are created in
+    #[arg(long, env = "CONFLUENCE_SPACE")]
+    confluence_space: Option<String>,
+    /// id of the page the published pages (and their wiki ancestors) go under
+    #[arg(long)]
+    confluence_parent: Option<String>,
+    /// account email for an api token, leave out to send the token as a bearer token
+    #[arg(long, env = "CONFLUENCE_USER")]
+    confluence_user: Option<String>,
+    /// api token (cloud) or personal access token (data center)
+    #[arg(long, env = "CONFLUENCE_TOKEN", hide_env_values = true)]
+    confluence_token: Option<String>,
+
+    /// also write the page as notion block json (`<stem>.notion.json`)
+    #[arg(long)]
+    notion: bool,
+    /// also write pandoc's json ast (`<stem>.pandoc.json`), eg. `pandoc -f json -t rst`
+    #[arg(long)]
+    pandoc: bool,
+    /// also write a restructuredtext source next to the markdown, for sphinx
+    #[arg(long)]
+    rst: bool,
+    /// also write an asciidoc source next to the markdown, for asciidoctor or antora
+    #[arg(long)]
+    asciidoc: bool,
+    /// also write an org file next to the markdown
+    #[arg(long)]
+    org: bool,
+    /// how task lists are written in the org file
+    #[arg(long, value_enum, default_value_t = OrgTasks::Checkbox)]
+    org_tasks: OrgTasks,
+    /// publish the document as a child page of this notion page (id)
+    #[arg(long, env = "NOTION_PARENT_PAGE")]
+    notion_parent: Option<String>,
+    /// integration token, the integration needs access to the parent page
+    #[arg(long, env = "NOTION_TOKEN", hide_env_values = true)]
+    notion_token: Option<String>,
+    /// notion api origin, point it to a mock server for offline runs
+    #[arg(long, default_value = notion_api::DEFAULT_API_BASE)]
+    notion_api_base: String,
+
+    /// open api origin, point it to a mock server for offline runs
+    #[arg(long, default_value = feishu_api::DEFAULT_API_BASE)]
+    api_base: String,
+    /// app id for a tenant_access_token
+    #[arg(long, env = "FEISHU_APP_ID")]
+    app_id: Option<String>,
+    #[arg(long, env = "FEISHU_APP_SECRET", hide_env_values = true)]
+    app_secret: Option<String>,
+    /// use this user_access_token instead of app credentials
+    #[arg(long, env = "FEISHU_USER_ACCESS_TOKEN", hide_env_values = true)]
+    user_access_token: Option<String>,
+
+    /// put the document meta at the top of the markdown
+    #[arg(long, value_enum, default_value_t = FrontMatterFormat::None)]
+    front_matter: FrontMatterFormat,
+    /// fields and their keys, eg. `title,created=date,wiki_path=tags`
+    /// (fields: title, author, created, modified, source_url, wiki_path, tags)
+    #[arg(long, value_parser = FrontMatterKeys::parse)]
+    front_matter_keys: Option<FrontMatterKeys>,
+    /// export the version history instead of the current version:
+    /// `all` or comma separated indices, 0 is the oldest
+    #[arg(long, value_parser = history::VersionSelection::parse)]
+    history: Option<history::VersionSelection>,
+    /// write `<stem>.md` and `<stem>.rsc/` into this git repository and commit the changes,
+    /// with --history every version becomes its own commit
+    #[arg(long)]
+    git_repo: Option<String>,
+    /// with --git-repo: commit on this branch, created from HEAD when missing
+    #[arg(long)]
+    git_branch: Option<String>,
+    /// collect comment threads and put them in footnotes or a discussion section
+    #[arg(long, value_enum, default_value_t = CommentStyle::None)]
+    comments: CommentStyle,
+    /// obsidian: the file is named after the document title, links between exported pages
+    /// become `[[links]]` and the front matter is yaml with tags
+    #[arg(long, value_enum, default_value_t = MarkdownFlavour::Standard)]
+    markdown_flavour: MarkdownFlavour,
+    /// obsidian: vault attachments folder, relative to the markdown file
+    #[arg(long, default_value = "attachments")]
+    attachments_dir: PathBuf,
+    /// markdown dialect to escape for, commonmark gets html tables and no footnotes
+    #[arg(long, value_enum, default_value_t = MarkdownDialect::Gfm)]
+    dialect: MarkdownDialect,
+    /// spaces per nesting level of lists, widened to the parent marker (`1. ` is 3)
+    /// and capped 3 past it so nested content stays in the item
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(2..=8))]
+    list_indent: u8,
+    #[arg(long, value_enum, default_value_t = ListBullet::Dash)]
+    list_bullet: ListBullet,
+    /// how headings deeper than H6 are written
+    #[arg(long, value_enum, default_value_t = HeadingOverflow::Clamp)]
+    heading_overflow: HeadingOverflow,
+
+    #[arg(long, value_enum, default_value_t = ImageFormatChoice::Original)]
+    image_format: ImageFormatChoice,
+    /// jpeg/webp quality, 1-100
+    #[arg(long, default_value_t = 85)]
+    image_quality: u8,
+    /// downscale images larger than this many pixels on either side
+    #[arg(long)]
+    image_max_dimension: Option<u32>,
+}
+
+impl Config {
+    fn url(&self) -> &str {
+        match &self.url {
+            Some(url) => url,
+            None => Config::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "the document url is required",
+                )
+                .exit(),
+        }
+    }
+
+    fn image_options(&self) -> ImageOptions {
+        ImageOptions {
+            format: self.image_format,
+            quality: self.image_quality,
+            max_dimension: self.image_max_dimension,
+        }
+    }
+
+    fn markdown_options(&self) -> MarkdownOptions {
+        MarkdownOptions {
+            front_matter: self.front_matter,
+            front_matter_keys: self.front_matter_keys.clone().unwrap_or_default(),
+            comments: self.comments,
+            flavour: self.markdown_flavour,
+            attachments_dir: (self.markdown_flavour == MarkdownFlavour::Obsidian)
+                .then(|| self.attachments_dir.clone()),
+            page_links: HashMap::new(),
+            dialect: self.dialect,
+            list_indent: self.list_indent as usize,
+            list_bullet: self.list_bullet,
+            heading_overflow: self.heading_overflow,
+        }
+    }
+
+    /// the formats written next to the markdown besides it
+    fn export_extra_formats(
+        &self,
+        doc: &Document,
+        output_md: &Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.docx {
+            to_docx::export_document_to_docx(doc, &output_md.with_extension("docx"))?;
+        }
+        if self.latex {
+            to_latex::export_document_to_latex(doc, &output_md.with_extension("tex"))?;
+        }
+        if self.typst {
+            to_typst::export_document_to_typst(doc, &output_md.with_extension("typ"))?;
+        }
+        if self.confluence {
+            to_confluence::export_document_to_confluence(doc, &output_md.with_extension("xhtml"))?;
+        }
+        if self.notion {
+            to_notion::export_document_to_notion(doc, &output_md.with_extension("notion.json"))?;
+        }
+        if self.pandoc {
+            to_pandoc::export_document_to_pandoc(doc, &output_md.with_extension("pandoc.json"))?;
+        }
+        if self.rst {
+            to_rst::export_document_to_rst(doc, &output_md.with_extension("rst"))?;
+        }
+        if self.asciidoc {
+            to_asciidoc::export_document_to_asciidoc(doc, &output_md.with_extension("adoc"))?;
+        }
+        if self.org {
+            to_org::export_document_to_org(doc, &output_md.with_extension("org"), self.org_tasks)?;
+        }
+        Ok(())
+    }
+
+    /// obsidian notes are named after their document
+    fn output_md_path(&self, doc: &Document) -> PathBuf {
+        let output_md = PathBuf::from(&self.output_md);
+        match (&doc.meta.title, self.markdown_flavour) {
+            (Some(title), MarkdownFlavour::Obsidian) => {
+                output_md.with_file_name(format!("{}.md", obsidian::safe_file_name(title)))
+            }
+            _ => output_md,
+        }
+    }
+
+    /// options for writing `doc` to `md_path`, with the pages of the vault it can link to
+    fn page_markdown_options(&self, doc: &Document, md_path: &Path) -> MarkdownOptions {
+        let mut options = self.markdown_options();
+        if self.markdown_flavour == MarkdownFlavour::Obsidian {
+            let vault = md_path.parent().unwrap_or(Path::new("."));
+            let vault = if vault.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                vault
+            };
+            options.page_links = obsidian::vault_page_links(vault);
+            if let (Some((_, token)), Some(stem)) = (
+                doc.meta
+                    .source_url
+                    .as_deref()
+                    .and_then(feishu_api::parse_doc_url),
+                md_path.file_stem(),
+            ) {
+                options
+                    .page_links
+                    .insert(token, stem.to_string_lossy().to_string());
+            }
+        }
+        options
+    }
+
+    fn api_auth(&self) -> feishu_api::ApiAuth {
+        if let Some(token) = &self.user_access_token {
+            return feishu_api::ApiAuth::User(token.clone());
+        }
+        match (&self.app_id, &self.app_secret) {
+            (Some(app_id), Some(app_secret)) => feishu_api::ApiAuth::Tenant {
+                app_id: app_id.clone(),
+                app_secret: app_secret.clone(),
+            },
+            _ => {
+                eprintln!(
+                    "the open api needs --user-access-token or both --app-id and --app-secret"
+                );
+                std::process::exit(2);
+            }
+        }
+    }
 }
 
 async fn kill_old_chrome() {
@@ -752,31 +1499,32 @@ fn construct_blocks(
             let mut parent_block = mutable_blocks.get(parent).unwrap().borrow_mut();
             let parent_block = parent_block.as_mut().unwrap();
 
-            let parent_listone = match &mut parent_block.content {
-                OneOf::B((_, listone)) => listone,
+            // list items and callouts hold child blocks
+            let parent_following = match &mut parent_block.content {
+                OneOf::B((_, listone)) => listone.get_following_mut(),
+                OneOf::A(Block::Callout { children, .. }) => children,
                 _ => panic!("parent is not listone-like"),
             };
 
             match take_cur_block.content {
                 OneOf::A(block) => {
-                    parent_listone.get_following_mut().push(block);
+                    parent_following.push(block);
                 }
                 OneOf::B((child_list_type, child_list_one)) => {
                     fn push_new_list_to_parent_following(
-                        parent_listone: &mut ListOne,
+                        parent_following: &mut Vec<Block>,
                         cl_type: ListType,
                         cl_one: ListOne,
                     ) {
                         unsafe {
-                            parent_listone.get_following_mut().push(Block::List {
+                            parent_following.push(Block::List {
                                 list_type: cl_type,
                                 items: vec![cl_one],
                             });
                         }
                     }
 
-                    if let Some(last_block_in_parent_following) =
-                        parent_listone.get_following_mut().iter_mut().last()
+                    if let Some(last_block_in_parent_following) = parent_following.iter_mut().last()
                     {
                         match last_block_in_parent_following {
                             Block::List {
@@ -787,7 +1535,7 @@ fn construct_blocks(
                             }
                             _ => {
                                 push_new_list_to_parent_following(
-                                    parent_listone,
+                                    parent_following,
                                     child_list_type,
                                     child_list_one,
                                 );
@@ -795,7 +1543,7 @@ fn construct_blocks(
                         }
                     } else {
                         push_new_list_to_parent_following(
-                            parent_listone,
+                            parent_following,
                             child_list_type,
                             child_list_one,
                         );
@@ -832,6 +1580,13 @@ fn construct_blocks(
     // 由于我们直接修改了原始结构，需要反转所有ListOne的following
     {
         fn reverse_recursive(block: &mut Block) {
+            // callout children were pushed last first as well
+            if let Block::Callout { children, .. } = block {
+                children.reverse();
+                for child in children {
+                    reverse_recursive(child);
+                }
+            }
             if let Block::List { items, .. } = block {
                 // Reverse the order of items within the current list first
                 items.reverse();
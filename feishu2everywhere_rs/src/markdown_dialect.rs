@@ -0,0 +1,161 @@
+/// which markdown the output is written for, decides escaping and the fallbacks
+/// for what the dialect lacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MarkdownDialect {
+    /// the plain spec: no tables (html instead), no footnotes
+    #[value(name = "commonmark")]
+    CommonMark,
+    /// github: tables, task lists, footnotes, `~` strikethrough
+    #[default]
+    Gfm,
+    /// pandoc's markdown: also escapes `^ ~ $` (super/subscript, math), bracketed spans
+    Pandoc,
+    /// mdx (docusaurus...): also escapes `{ }` and `<`, jsx style self closing tags
+    Mdx,
+}
+
+/// what to do with the H7-H10 headings feishu has and markdown has not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum HeadingOverflow {
+    /// an H6 heading
+    #[default]
+    Clamp,
+    /// a bold paragraph, out of the document outline
+    Bold,
+    /// a plain paragraph
+    Paragraph,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ListBullet {
+    #[default]
+    Dash,
+    Asterisk,
+    Plus,
+}
+
+impl ListBullet {
+    pub fn char(&self) -> char {
+        match self {
+            ListBullet::Dash => '-',
+            ListBullet::Asterisk => '*',
+            ListBullet::Plus => '+',
+        }
+    }
+}
+
+impl MarkdownDialect {
+    /// line break inside a table cell or a one line comment
+    pub fn html_break(&self) -> &'static str {
+        match self {
+            MarkdownDialect::Mdx => "<br />",
+            _ => "<br>",
+        }
+    }
+
+    /// characters escaped wherever they are
+    fn escapes(&self, c: char) -> bool {
+        match c {
+            '\\' | '`' | '*' | '[' | ']' | '<' => true,
+            '~' => matches!(self, MarkdownDialect::Gfm | MarkdownDialect::Pandoc),
+            '^' | '$' => *self == MarkdownDialect::Pandoc,
+            '{' | '}' => *self == MarkdownDialect::Mdx,
+            _ => false,
+        }
+    }
+}
+
+/// `&name;` or `&#123;` ahead, which would be read as a character reference
+fn starts_entity(rest: &[char]) -> bool {
+    let body = rest
+        .iter()
+        .skip(1)
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '#')
+        .count();
+    body > 0 && rest.get(1 + body) == Some(&';')
+}
+
+/// `#`, `>`, `-`, `+`, `=`, `1.` or `1)` at the start of a line would start a block
+fn escape_line_start(chars: &[char], out: &mut String) -> usize {
+    let digits = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && matches!(chars.get(digits), Some('.' | ')')) {
+        out.extend(&chars[..digits]);
+        out.push('\\');
+        return digits;
+    }
+    match chars.first() {
+        Some('#' | '>' | '=') => out.push('\\'),
+        Some('-' | '+') if chars.get(1).is_none_or(|c| c.is_whitespace()) => out.push('\\'),
+        _ => {}
+    }
+    0
+}
+
+/// Escape text so every character shows up literally. `at_line_start` tells whether
+/// the text begins a line, where block markers have to be escaped too.
+pub fn escape_text(text: &str, dialect: MarkdownDialect, at_line_start: bool) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(text.len());
+    let mut line_start = at_line_start;
+    let mut i = 0;
+    while i < chars.len() {
+        if line_start {
+            let indent = chars[i..].iter().take_while(|c| **c == ' ').count();
+            out.extend(&chars[i..i + indent]);
+            i += indent;
+            i += escape_line_start(&chars[i..], &mut out);
+            line_start = false;
+            if i >= chars.len() {
+                break;
+            }
+        }
+        let c = chars[i];
+        let escape = match c {
+            // intraword underscores do not emphasize
+            '_' => {
+                let alnum = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+                !(i > 0 && alnum(chars.get(i - 1)) && alnum(chars.get(i + 1)))
+            }
+            '&' => starts_entity(&chars[i..]),
+            c => dialect.escapes(c),
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+        if c == '\n' {
+            line_start = true;
+        }
+        i += 1;
+    }
+    out
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+/// inline code, the delimiter is longer than any backtick run inside
+pub fn code_span(code: &str) -> String {
+    let fence = "`".repeat(longest_backtick_run(code) + 1);
+    // a space keeps a leading or trailing backtick off the delimiter
+    if code.starts_with('`') || code.ends_with('`') {
+        format!("{} {} {}", fence, code, fence)
+    } else {
+        format!("{}{}{}", fence, code, fence)
+    }
+}
+
+/// code block fence, longer than any backtick run in the code
+pub fn code_fence(code: &str) -> String {
+    "`".repeat((longest_backtick_run(code) + 1).max(3))
+}
+
+/// link destination without spaces or parentheses, which would end it
+pub fn link_destination(url: &str) -> String {
+    url.replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('<', "%3C")
+        .replace('>', "%3E")
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::time::Duration;
+
+use thirtyfour::{By, WebDriver};
+
+use crate::block::{Block, HeadLevel};
+use crate::diagram;
+use crate::outline::{self, OutlineNode};
+use crate::to_markdown;
+
+const PAPER_SELECTOR: &str = ".mindnote-paper, .mindnote-container";
+
+/// click every collapsed node toggle currently rendered, returns how many were clicked
+///
+/// expanding a node can reveal more collapsed children, so this runs until it returns 0
+const EXPAND_COLLAPSED_JS: &str = r#"
+const toggles = document.querySelectorAll(
+    '[data-node-id] [class*=collapsed][class*=btn], [data-node-id] [class*=expand-btn], '
+    + '[data-node-id] [aria-expanded=false]');
+let clicked = 0;
+for (const t of toggles) {
+    t.click();
+    clicked++;
+}
+return clicked;
+"#;
+
+/// at most this many expand passes, a toggle that never goes away must not hang the export
+const MAX_EXPAND_PASSES: usize = 50;
+
+async fn expand_all(driver: &WebDriver) {
+    for pass in 0..MAX_EXPAND_PASSES {
+        let clicked = match driver.execute(EXPAND_COLLAPSED_JS, vec![]).await {
+            Ok(ret) => ret.json().as_u64().unwrap_or(0),
+            Err(err) => {
+                println!("err expand mind note: {:?}", err);
+                return;
+            }
+        };
+        if clicked == 0 {
+            println!("mind note fully expanded after {} passes", pass);
+            return;
+        }
+        // wait for the children to render
+        tokio::time::sleep(Duration::from_millis(300)).await;
+    }
+    println!("mind note still has collapsed nodes, exporting what is expanded");
+}
+
+/// Expand the whole mind note opened in `driver` and read its tree
+pub async fn collect_tree(driver: &WebDriver) -> Option<OutlineNode> {
+    let start = std::time::Instant::now();
+    let paper = loop {
+        if let Ok(paper) = driver.find(By::Css(PAPER_SELECTOR)).await {
+            break paper;
+        }
+        if start.elapsed() > Duration::from_secs(20) {
+            println!("mind note paper not found");
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    };
+
+    expand_all(driver).await;
+    let tree = diagram::try_read_mindmap_tree(driver, &paper).await;
+    if tree.is_none() {
+        println!("mind note has no readable nodes");
+    }
+    tree
+}
+
+/// Write the tree as `{stem}.opml`, `{stem}.mm` and a markdown outline at `output_md_path`
+pub fn export_tree(
+    root: &OutlineNode,
+    output_md_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let md_path = Path::new(output_md_path);
+    std::fs::write(md_path.with_extension("opml"), outline::to_opml(root))?;
+    std::fs::write(md_path.with_extension("mm"), outline::to_freemind(root))?;
+
+    // the central topic is the title, its branches the nested list
+    let blocks = vec![
+        Block::Title {
+            text: root.text.clone(),
+            head_level: HeadLevel::H1,
+        },
+        root.to_list_block(),
+    ];
+    to_markdown::export_blocks_to_markdown(&blocks, output_md_path)?;
+    println!("Saved mind note outline next to: {:?}", md_path);
+    Ok(())
+}
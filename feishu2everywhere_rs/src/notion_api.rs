@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde_json::{Value, json};
+
+use crate::document::Document;
+use crate::to_notion::{blocks, image_paths};
+
+pub const DEFAULT_API_BASE: &str = "https://api.notion.com";
+/// the api version the block json is written for
+const NOTION_VERSION: &str = "2022-06-28";
+/// most children one append request takes
+const MAX_CHILDREN: usize = 100;
+/// attempts of a rate limited request
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Client for the notion public api, authorized by an integration token
+pub struct NotionClient {
+    http: reqwest::Client,
+    base: String,
+    token: String,
+}
+
+/// the response body as json, the status and the error body otherwise
+async fn json_response(what: &str, resp: Response) -> Result<Value, Box<dyn std::error::Error>> {
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(format!("{} failed: {} {}", what, status, text).into());
+    }
+    Ok(resp.json().await?)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("bmp") => "image/bmp",
+        Some("tif" | "tiff") => "image/tiff",
+        _ => "image/png",
+    }
+}
+
+/// Take the children out of a block so it can be created on its own. Table rows stay,
+/// a table cannot be created without them.
+fn take_children(block: &mut Value) -> Vec<Value> {
+    let kind = block
+        .get("type")
+        .and_then(|t| t.as_str())
+        .unwrap_or_default()
+        .to_string();
+    if kind == "table" {
+        return vec![];
+    }
+    block
+        .get_mut(&kind)
+        .and_then(|content| content.as_object_mut())
+        .and_then(|content| content.remove("children"))
+        .and_then(|children| match children {
+            Value::Array(children) => Some(children),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+impl NotionClient {
+    /// `base` is the api origin, point it to a local mock server to run without notion
+    pub fn new(base: &str, token: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base: base.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}/v1{}", self.base, path))
+            .bearer_auth(&self.token)
+            .header("Notion-Version", NOTION_VERSION)
+    }
+
+    /// send the request again while notion answers 429, after the delay it asks for
+    async fn send(
+        &self,
+        what: &str,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        for _ in 1..MAX_ATTEMPTS {
+            let resp = build().send().await?;
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                return json_response(what, resp).await;
+            }
+            let wait = resp
+                .headers()
+                .get("Retry-After")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(1);
+            println!("{} is rate limited, retry in {}s", what, wait);
+            tokio::time::sleep(Duration::from_secs(wait)).await;
+        }
+        json_response(what, build().send().await?).await
+    }
+
+    /// id of the child page of `parent_id` titled `title`
+    async fn find_child_page(
+        &self,
+        parent_id: &str,
+        title: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        for child in self.children(parent_id).await? {
+            if child.pointer("/child_page/title").and_then(|t| t.as_str()) == Some(title) {
+                return Ok(child.get("id").and_then(|i| i.as_str()).map(str::to_string));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn children(&self, block_id: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let mut children = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut query = vec![("page_size".to_string(), "100".to_string())];
+            if let Some(cursor) = &cursor {
+                query.push(("start_cursor".to_string(), cursor.clone()));
+            }
+            let data = self
+                .send("list children", || {
+                    self.request(Method::GET, &format!("/blocks/{}/children", block_id))
+                        .query(&query)
+                })
+                .await?;
+            children.extend(
+                data.get("results")
+                    .and_then(|r| r.as_array())
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+            cursor = data
+                .get("next_cursor")
+                .and_then(|c| c.as_str())
+                .map(str::to_string);
+            if data.get("has_more").and_then(|m| m.as_bool()) != Some(true) || cursor.is_none() {
+                return Ok(children);
+            }
+        }
+    }
+
+    async fn create_page(
+        &self,
+        parent_id: &str,
+        title: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let page = json!({
+            "parent": { "page_id": parent_id },
+            "properties": { "title": { "title": [{ "text": { "content": title } }] } },
+        });
+        let data = self
+            .send("create page", || {
+                self.request(Method::POST, "/pages").json(&page)
+            })
+            .await?;
+        Ok(data
+            .get("id")
+            .and_then(|i| i.as_str())
+            .ok_or("created page without id")?
+            .to_string())
+    }
+
+    async fn delete_block(&self, block_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.send("delete block", || {
+            self.request(Method::DELETE, &format!("/blocks/{}", block_id))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// upload an image with the file upload api, the id is valid for an image block
+    async fn upload_file(&self, path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+        let file_name = path
+            .file_name()
+            .ok_or("image without file name")?
+            .to_string_lossy()
+            .to_string();
+        let upload = json!({ "filename": file_name, "content_type": content_type(path) });
+        let data = self
+            .send("create file upload", || {
+                self.request(Method::POST, "/file_uploads").json(&upload)
+            })
+            .await?;
+        let id = data
+            .get("id")
+            .and_then(|i| i.as_str())
+            .ok_or("file upload without id")?
+            .to_string();
+        let bytes = fs::read(path)?;
+        self.send("send file upload", || {
+            let part = reqwest::multipart::Part::bytes(bytes.clone())
+                .file_name(file_name.clone())
+                .mime_str(content_type(path))
+                .unwrap();
+            self.request(Method::POST, &format!("/file_uploads/{}/send", id))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+        })
+        .await?;
+        Ok(id)
+    }
+
+    /// Append `children` to `parent_id`. Notion takes two levels of nesting and 100 blocks
+    /// a request, so blocks are created one level at a time, nested ones under the created ids.
+    async fn append_children(
+        &self,
+        parent_id: &str,
+        mut children: Vec<Value>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let nested = children.iter_mut().map(take_children).collect::<Vec<_>>();
+        let mut created = vec![];
+        for chunk in children.chunks(MAX_CHILDREN) {
+            let body = json!({ "children": chunk });
+            let data = self
+                .send("append children", || {
+                    self.request(Method::PATCH, &format!("/blocks/{}/children", parent_id))
+                        .json(&body)
+                })
+                .await?;
+            created.extend(
+                data.get("results")
+                    .and_then(|r| r.as_array())
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+        }
+        for (block, nested) in created.iter().zip(nested) {
+            if nested.is_empty() {
+                continue;
+            }
+            let id = block
+                .get("id")
+                .and_then(|i| i.as_str())
+                .ok_or("appended block without id")?;
+            Box::pin(self.append_children(id, nested)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Create or replace the child page of `parent_id` titled `title` with the blocks of `doc`,
+/// images are uploaded with the file upload api. Returns the page id.
+pub async fn publish_document(
+    client: &NotionClient,
+    parent_id: &str,
+    doc: &Document,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let page_id = match client.find_child_page(parent_id, title).await? {
+        Some(page_id) => {
+            // blocks cannot be compared cheaply, the old content is replaced
+            for child in client.children(&page_id).await? {
+                if let Some(id) = child.get("id").and_then(|i| i.as_str()) {
+                    client.delete_block(id).await?;
+                }
+            }
+            page_id
+        }
+        None => client.create_page(parent_id, title).await?,
+    };
+
+    let mut uploads: HashMap<PathBuf, String> = HashMap::new();
+    for path in image_paths(&doc.blocks) {
+        if uploads.contains_key(path) {
+            continue;
+        }
+        if !path.is_file() {
+            println!("image {:?} not found, not uploaded", path);
+            continue;
+        }
+        uploads.insert(path.to_path_buf(), client.upload_file(path).await?);
+    }
+    let mut image_source = |path: &Path| {
+        uploads
+            .get(path)
+            .map(|id| json!({ "type": "file_upload", "file_upload": { "id": id } }))
+    };
+    let children = doc
+        .blocks
+        .iter()
+        .flat_map(|b| blocks(b, &mut image_source))
+        .collect::<Vec<_>>();
+    client.append_children(&page_id, children).await?;
+    println!("published {:?} to notion page {}", title, page_id);
+    Ok(page_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::block::{Block, ListOne, ListType, TextSlice};
+    use crate::mock_http::{MockServer, Request, Response};
+
+    fn text(text: &str) -> Vec<TextSlice> {
+        vec![TextSlice {
+            text: text.to_string(),
+            ..Default::default()
+        }]
+    }
+
+    fn child_page(id: &str, title: &str) -> Value {
+        json!({ "id": id, "type": "child_page", "child_page": { "title": title } })
+    }
+
+    /// lists children in pages of two, appended blocks get ids b1, b2...
+    /// and the first append is rate limited
+    fn notion() -> MockServer {
+        let created = AtomicUsize::new(0);
+        let appends = AtomicUsize::new(0);
+        MockServer::start(move |request: &Request| {
+            let list = |first: Vec<Value>, rest: Vec<Value>| match request.query("start_cursor") {
+                None => Response::json(
+                    json!({ "results": first, "has_more": true, "next_cursor": "c2" }),
+                ),
+                Some(_) => Response::json(
+                    json!({ "results": rest, "has_more": false, "next_cursor": null }),
+                ),
+            };
+            match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/v1/blocks/parent/children") => list(
+                    vec![
+                        child_page("other", "Other"),
+                        child_page("other2", "Other too"),
+                    ],
+                    vec![child_page("page1", "Golden")],
+                ),
+                ("GET", "/v1/blocks/page1/children") => list(
+                    vec![json!({ "id": "old1" }), json!({ "id": "old2" })],
+                    vec![json!({ "id": "old3" })],
+                ),
+                ("DELETE", _) => Response::json(json!({ "archived": true })),
+                ("POST", "/v1/file_uploads") => Response::json(json!({ "id": "up1" })),
+                ("POST", "/v1/file_uploads/up1/send") => {
+                    Response::json(json!({ "status": "uploaded" }))
+                }
+                ("PATCH", _) if appends.fetch_add(1, Ordering::SeqCst) == 0 => {
+                    Response::json(json!({ "code": "rate_limited" }))
+                        .with_status(429)
+                        .with_header("Retry-After", "0")
+                }
+                ("PATCH", _) => {
+                    let results = request.json()["children"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|_| json!({ "id": format!("b{}", created.fetch_add(1, Ordering::SeqCst) + 1) }))
+                        .collect::<Vec<_>>();
+                    Response::json(json!({ "results": results }))
+                }
+                _ => Response::json(json!({ "code": "object_not_found" })).with_status(404),
+            }
+        })
+    }
+
+    fn document() -> Document {
+        let mut blocks = vec![Block::List {
+            list_type: ListType::Unordered,
+            items: vec![ListOne::new(
+                text("one"),
+                None,
+                vec![Block::List {
+                    list_type: ListType::Ordered,
+                    items: vec![ListOne::new(text("first"), None, vec![])],
+                }],
+            )],
+        }];
+        blocks.extend((0..150).map(|i| Block::Text(text(&format!("paragraph {}", i)))));
+        blocks.push(Block::Sheet {
+            cells: vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["1".to_string(), "2".to_string()],
+            ],
+        });
+        blocks.push(Block::Image {
+            cached_path: "tests/golden/image.png".into(),
+            alt: None,
+            caption: None,
+            width: None,
+            align: None,
+        });
+        Document {
+            blocks,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_replaces_page_content() {
+        let server = notion();
+        let client = NotionClient::new(&server.base, "secret_x");
+        let page_id = publish_document(&client, "parent", &document(), "Golden")
+            .await
+            .unwrap();
+        assert_eq!(page_id, "page1");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("authorization"), Some("Bearer secret_x"));
+        assert_eq!(requests[0].header("notion-version"), Some(NOTION_VERSION));
+
+        // both listings follow next_cursor
+        let listings = requests
+            .iter()
+            .filter(|r| r.method == "GET")
+            .map(|r| (r.path.as_str(), r.query("start_cursor")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            listings,
+            [
+                ("/v1/blocks/parent/children", None),
+                ("/v1/blocks/parent/children", Some("c2")),
+                ("/v1/blocks/page1/children", None),
+                ("/v1/blocks/page1/children", Some("c2")),
+            ]
+        );
+        let deleted = requests
+            .iter()
+            .filter(|r| r.method == "DELETE")
+            .map(|r| r.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deleted,
+            ["/v1/blocks/old1", "/v1/blocks/old2", "/v1/blocks/old3"]
+        );
+
+        let upload = requests
+            .iter()
+            .find(|r| r.path == "/v1/file_uploads")
+            .unwrap()
+            .json();
+        assert_eq!(
+            upload,
+            json!({ "filename": "image.png", "content_type": "image/png" })
+        );
+
+        // the rate limited append is sent again, then the rest in chunks of 100
+        let appends = requests
+            .iter()
+            .filter(|r| r.method == "PATCH")
+            .map(|r| {
+                (
+                    r.path.as_str(),
+                    r.json()["children"].as_array().unwrap().clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let sizes = appends
+            .iter()
+            .map(|(path, children)| (*path, children.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            [
+                ("/v1/blocks/page1/children", 100),
+                ("/v1/blocks/page1/children", 100),
+                ("/v1/blocks/page1/children", 53),
+                ("/v1/blocks/b1/children", 1),
+            ]
+        );
+        assert_eq!(appends[0].1, appends[1].1);
+
+        // nested blocks go under the id the list item was created with
+        let item = &appends[1].1[0];
+        assert_eq!(item["type"], "bulleted_list_item");
+        assert!(item["bulleted_list_item"].get("children").is_none());
+        assert_eq!(appends[3].1[0]["type"], "numbered_list_item");
+
+        // a table is created together with its rows
+        let tail = &appends[2].1;
+        let table = &tail[51];
+        assert_eq!(table["type"], "table");
+        assert_eq!(table["table"]["children"].as_array().unwrap().len(), 2);
+        assert_eq!(tail[52]["image"]["file_upload"]["id"], "up1");
+    }
+}
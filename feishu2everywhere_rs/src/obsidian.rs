@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::document::Document;
+use crate::feishu_api;
+
+/// Windows reserves these names whatever the extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A file name valid on windows, macos and linux that obsidian can also link to:
+/// no path separators or reserved characters, no `#^[]|`, no trailing dot or space
+pub fn safe_file_name(name: &str) -> String {
+    let mut safe = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' | '#' | '^' | '[' | ']' => '-',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    safe = safe
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_string();
+    // keep well under the 255 bytes most file systems allow, `.md` included
+    while safe.len() > 200 {
+        safe.pop();
+    }
+    if safe.is_empty() {
+        return "untitled".to_string();
+    }
+    let base = safe.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(base.trim()))
+    {
+        safe.insert(0, '_');
+    }
+    safe
+}
+
+/// obsidian tag from a wiki node title: no spaces or punctuation, not only digits
+pub fn tag_name(text: &str) -> Option<String> {
+    let tag = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'))
+        .collect::<String>();
+    let tag = tag.trim_matches(['-', '/']).to_string();
+    if tag.is_empty() || tag.chars().all(|c| c.is_ascii_digit()) {
+        None
+    } else {
+        Some(tag)
+    }
+}
+
+/// obsidian callout type for the emoji leading a feishu callout
+pub fn callout_type(emoji: Option<&str>) -> &'static str {
+    match emoji.map(|e| e.trim_end_matches('\u{fe0f}')) {
+        Some("💡") => "tip",
+        Some("⚠") => "warning",
+        Some("❗" | "‼") => "important",
+        Some("❓" | "❔") => "question",
+        Some("ℹ") => "info",
+        Some("✅" | "✔") => "success",
+        Some("⛔" | "❌" | "🚫") => "danger",
+        Some("🐛") => "bug",
+        Some("📝" | "✏") => "note",
+        Some("📌") => "abstract",
+        Some("🔥") => "example",
+        _ => "note",
+    }
+}
+
+fn collect_page_links(dir: &Path, links: &mut HashMap<String, String>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || name.ends_with(".rsc") {
+            continue;
+        }
+        if path.is_dir() {
+            collect_page_links(&path, links);
+        } else if path.extension().is_some_and(|e| e == "json")
+            && let Ok(doc) = Document::load_json(&path)
+            && let Some((_, token)) = doc
+                .meta
+                .source_url
+                .as_deref()
+                .and_then(feishu_api::parse_doc_url)
+        {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            links.insert(token, stem.to_string());
+        }
+    }
+}
+
+/// Document token -> note name of every page already exported into the vault at `dir`,
+/// found by their json sidecars. Links to pages exported later resolve on their next export.
+pub fn vault_page_links(dir: &Path) -> HashMap<String, String> {
+    let mut links = HashMap::new();
+    collect_page_links(dir, &mut links);
+    links
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::block::{Block, ListOne, ListType, TextSlice};
+use crate::xml;
+
+/// One node of a mind map, the root is the central topic
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutlineNode {
+    pub text: String,
+    pub children: Vec<OutlineNode>,
+}
+
+impl OutlineNode {
+    /// parse the `{text, children: [...]}` tree returned by in-page scripts
+    pub fn from_json(v: &Value) -> Option<OutlineNode> {
+        let text = v.get("text")?.as_str()?.trim().to_string();
+        let children = v
+            .get("children")
+            .and_then(|c| c.as_array())
+            .map(|c| c.iter().filter_map(OutlineNode::from_json).collect())
+            .unwrap_or_default();
+        Some(OutlineNode { text, children })
+    }
+
+    /// children as a nested unordered list, the root topic itself is not included
+    pub fn to_list_block(&self) -> Block {
+        Block::List {
+            list_type: ListType::Unordered,
+            items: self
+                .children
+                .iter()
+                .map(|child| {
+                    let following = if child.children.is_empty() {
+                        vec![]
+                    } else {
+                        vec![child.to_list_block()]
+                    };
+                    ListOne::new(
+                        vec![TextSlice {
+                            text: child.text.clone(),
+                            ..Default::default()
+                        }],
+                        None,
+                        following,
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+fn push_opml_outline(node: &OutlineNode, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth + 2);
+    if node.children.is_empty() {
+        out.push_str(&format!(
+            "{}<outline text=\"{}\"/>\n",
+            indent,
+            xml::escape(&node.text)
+        ));
+    } else {
+        out.push_str(&format!(
+            "{}<outline text=\"{}\">\n",
+            indent,
+            xml::escape(&node.text)
+        ));
+        for child in &node.children {
+            push_opml_outline(child, depth + 1, out);
+        }
+        out.push_str(&format!("{}</outline>\n", indent));
+    }
+}
+
+/// OPML 2.0 document, the root topic becomes the single top level outline
+pub fn to_opml(root: &OutlineNode) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<opml version=\"2.0\">\n");
+    out.push_str("  <head>\n");
+    out.push_str(&format!("    <title>{}</title>\n", xml::escape(&root.text)));
+    out.push_str("  </head>\n");
+    out.push_str("  <body>\n");
+    push_opml_outline(root, 0, &mut out);
+    out.push_str("  </body>\n");
+    out.push_str("</opml>\n");
+    out
+}
+
+fn push_freemind_node(node: &OutlineNode, depth: usize, out: &mut String) {
+    let indent = " ".repeat(depth);
+    if node.children.is_empty() {
+        out.push_str(&format!(
+            "{}<node TEXT=\"{}\"/>\n",
+            indent,
+            xml::escape(&node.text)
+        ));
+    } else {
+        out.push_str(&format!(
+            "{}<node TEXT=\"{}\">\n",
+            indent,
+            xml::escape(&node.text)
+        ));
+        for child in &node.children {
+            push_freemind_node(child, depth + 1, out);
+        }
+        out.push_str(&format!("{}</node>\n", indent));
+    }
+}
+
+/// FreeMind `.mm` map, also opened by Freeplane, XMind and most mind map tools
+pub fn to_freemind(root: &OutlineNode) -> String {
+    let mut out = String::new();
+    out.push_str("<map version=\"1.0.1\">\n");
+    push_freemind_node(root, 0, &mut out);
+    out.push_str("</map>\n");
+    out
+}
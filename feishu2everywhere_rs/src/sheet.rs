@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use thirtyfour::{WebDriver, WebElement};
+
+/// read cells currently rendered by the virtual grid under arguments[0]
+/// returns [[row, col, text, rowSpan, colSpan, bold], ...], the column header comes first
+const READ_VISIBLE_CELLS_JS: &str = r#"
+const root = arguments[0];
+const out = [];
+const text = (c) => (c.innerText || '').replace(/\r/g, '').trim();
+const idx = (v) => { const n = parseInt(v, 10); return isNaN(n) ? null : n; };
+const span = (c, name) => idx(c.getAttribute('aria-' + name) || c.getAttribute('data-' + name) || c.getAttribute(name)) || 1;
+const bold = (c) => { const w = getComputedStyle(c.firstElementChild || c).fontWeight; return w === 'bold' || parseInt(w, 10) >= 600; };
+const push = (row, col, c) => out.push([row, col, text(c), span(c, 'rowspan'), span(c, 'colspan'), bold(c)]);
+
+// aria grid (bitable and most virtualized grids), aria indices are 1-based and
+// count the header row, row 0 is only used for a header row without an index
+root.querySelectorAll('[role=columnheader][aria-colindex]').forEach((c) => {
+    const rowElem = c.closest('[aria-rowindex]');
+    const row = rowElem ? idx(rowElem.getAttribute('aria-rowindex')) : 0;
+    const col = idx(c.getAttribute('aria-colindex'));
+    if (row !== null && col !== null) push(row, col, c);
+});
+root.querySelectorAll('[role=gridcell][aria-colindex]').forEach((c) => {
+    const rowElem = c.closest('[aria-rowindex]');
+    const row = rowElem ? idx(rowElem.getAttribute('aria-rowindex')) : null;
+    const col = idx(c.getAttribute('aria-colindex'));
+    if (row !== null && col !== null) push(row, col, c);
+});
+
+// dom rendered sheet cells, indices are 0-based and header lives in row 0
+if (out.length === 0) {
+    root.querySelectorAll('[data-row][data-col]').forEach((c) => {
+        const row = idx(c.getAttribute('data-row'));
+        const col = idx(c.getAttribute('data-col'));
+        if (row !== null && col !== null) push(row, col + 1, c);
+    });
+}
+return out;
+"#;
+
+/// scroll the first scrollable container under arguments[0] to (arguments[1], arguments[2])
+/// returns [top, left, maxTop, maxLeft, pageHeight, pageWidth] after scrolling
+const SCROLL_GRID_JS: &str = r#"
+const root = arguments[0];
+const isScrollable = (el) => {
+    const s = getComputedStyle(el);
+    return (el.scrollHeight > el.clientHeight + 1 && /(auto|scroll)/.test(s.overflowY))
+        || (el.scrollWidth > el.clientWidth + 1 && /(auto|scroll)/.test(s.overflowX));
+};
+let box = isScrollable(root) ? root : null;
+if (!box) {
+    for (const el of root.querySelectorAll('*')) {
+        if (isScrollable(el)) { box = el; break; }
+    }
+}
+if (!box) return [0, 0, 0, 0, 0, 0];
+box.scrollTop = arguments[1];
+box.scrollLeft = arguments[2];
+return [box.scrollTop, box.scrollLeft,
+        box.scrollHeight - box.clientHeight, box.scrollWidth - box.clientWidth,
+        box.clientHeight, box.clientWidth];
+"#;
+
+/// everything scraped from a grid, positions are relative to the top left cell
+#[derive(Debug, Clone, Default)]
+pub struct SheetGrid {
+    pub rows: Vec<Vec<String>>,
+    /// (first_row, first_col, last_row, last_col), inclusive
+    pub merges: Vec<(usize, usize, usize, usize)>,
+    pub bold: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ScrapedCell {
+    text: String,
+    row_span: usize,
+    col_span: usize,
+    bold: bool,
+}
+
+async fn read_visible_cells(
+    driver: &WebDriver,
+    grid: &WebElement,
+    cells: &mut BTreeMap<(usize, usize), ScrapedCell>,
+) {
+    let ret = match driver
+        .execute(READ_VISIBLE_CELLS_JS, vec![grid.to_json().unwrap()])
+        .await
+    {
+        Ok(ret) => ret,
+        Err(err) => {
+            println!("err read grid cells: {:?}", err);
+            return;
+        }
+    };
+    let Some(triples) = ret.json().as_array() else {
+        return;
+    };
+    for cell in triples {
+        let (Some(row), Some(col), Some(text)) = (
+            cell.get(0).and_then(|v| v.as_u64()),
+            cell.get(1).and_then(|v| v.as_u64()),
+            cell.get(2).and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+        cells.insert(
+            (row as usize, col as usize),
+            ScrapedCell {
+                text: text.to_string(),
+                row_span: cell.get(3).and_then(|v| v.as_u64()).unwrap_or(1).max(1) as usize,
+                col_span: cell.get(4).and_then(|v| v.as_u64()).unwrap_or(1).max(1) as usize,
+                bold: cell.get(5).and_then(|v| v.as_bool()).unwrap_or(false),
+            },
+        );
+    }
+}
+
+async fn scroll_grid(driver: &WebDriver, grid: &WebElement, top: i64, left: i64) -> [i64; 6] {
+    let mut pos = [0; 6];
+    let Ok(ret) = driver
+        .execute(
+            SCROLL_GRID_JS,
+            vec![grid.to_json().unwrap(), top.into(), left.into()],
+        )
+        .await
+    else {
+        return pos;
+    };
+    if let Some(values) = ret.json().as_array() {
+        for (slot, v) in pos.iter_mut().zip(values) {
+            *slot = v.as_f64().unwrap_or(0.0) as i64;
+        }
+    }
+    pos
+}
+
+/// Scrape every cell of a virtualized grid (embedded sheet, bitable view, standalone sheet tab).
+///
+/// Only the rows and columns inside the viewport exist in the DOM, so the grid is
+/// scrolled page by page (columns outer, rows inner) and cells are merged by position,
+/// the same way `collect_blocks` walks the document.
+/// Returns None when the element has no readable cells (eg. a canvas-only render).
+pub async fn scrape_virtual_grid(
+    driver: &WebDriver,
+    grid: &WebElement,
+) -> Option<Vec<Vec<String>>> {
+    scrape_virtual_grid_detailed(driver, grid)
+        .await
+        .map(|grid| grid.rows)
+}
+
+/// like `scrape_virtual_grid`, also keeping merged ranges and bold cells for xlsx output
+pub async fn scrape_virtual_grid_detailed(
+    driver: &WebDriver,
+    grid: &WebElement,
+) -> Option<SheetGrid> {
+    let mut cells: BTreeMap<(usize, usize), ScrapedCell> = BTreeMap::new();
+
+    let [_, _, max_top, max_left, page_h, page_w] = scroll_grid(driver, grid, 0, 0).await;
+    let step_h = (page_h * 4 / 5).max(1);
+    let step_w = (page_w * 4 / 5).max(1);
+
+    let mut left = 0;
+    loop {
+        let mut top = 0;
+        loop {
+            scroll_grid(driver, grid, top, left).await;
+            // wait for the grid to render the new viewport
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            read_visible_cells(driver, grid, &mut cells).await;
+            if top >= max_top {
+                break;
+            }
+            top = (top + step_h).min(max_top);
+        }
+        if left >= max_left {
+            break;
+        }
+        left = (left + step_w).min(max_left);
+    }
+    // leave the grid where the user expects it
+    scroll_grid(driver, grid, 0, 0).await;
+
+    if cells.is_empty() {
+        return None;
+    }
+
+    Some(cells_to_grid(cells))
+}
+
+/// turn sparse (row, col) cells into a dense row-major table,
+/// rows without any text are dropped unless a merged cell spans them
+fn cells_to_grid(cells: BTreeMap<(usize, usize), ScrapedCell>) -> SheetGrid {
+    let mut kept_rows = BTreeSet::new();
+    for ((r, _), cell) in &cells {
+        if !cell.text.is_empty() || cell.row_span > 1 {
+            kept_rows.extend(*r..*r + cell.row_span);
+        }
+    }
+    let row_index = kept_rows
+        .iter()
+        .enumerate()
+        .map(|(index, r)| (*r, index))
+        .collect::<BTreeMap<_, _>>();
+    let min_col = cells.keys().map(|(_, c)| *c).min().unwrap_or(0);
+    let max_col = cells.keys().map(|(_, c)| *c).max().unwrap_or(0);
+
+    let mut grid = SheetGrid {
+        rows: vec![vec![String::new(); max_col - min_col + 1]; row_index.len()],
+        ..Default::default()
+    };
+    for ((r, c), cell) in cells {
+        let Some(&r) = row_index.get(&r) else {
+            continue;
+        };
+        let c = c - min_col;
+        if cell.row_span > 1 || cell.col_span > 1 {
+            grid.merges
+                .push((r, c, r + cell.row_span - 1, c + cell.col_span - 1));
+        }
+        if cell.bold {
+            grid.bold.insert((r, c));
+        }
+        grid.rows[r][c] = cell.text;
+    }
+    grid
+}
+
+/// RFC 4180 csv, fields containing separators, quotes or line breaks are quoted
+pub fn rows_to_csv(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .map(|field| {
+                if field.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        out.push_str(&line);
+        out.push_str("\r\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(text: &str, row_span: usize) -> ScrapedCell {
+        ScrapedCell {
+            text: text.to_string(),
+            row_span,
+            col_span: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cells_to_grid_drops_empty_rows() {
+        // header without a row index, data from aria-rowindex 2 and a blank row 4
+        let cells = BTreeMap::from([
+            ((0, 1), cell("name", 1)),
+            ((0, 2), cell("size", 1)),
+            ((2, 1), cell("a", 2)),
+            ((2, 2), cell("1", 1)),
+            ((3, 2), cell("2", 1)),
+            ((4, 1), cell("", 1)),
+            ((5, 1), cell("b", 1)),
+        ]);
+        let grid = cells_to_grid(cells);
+        assert_eq!(
+            grid.rows,
+            [["name", "size"], ["a", "1"], ["", "2"], ["b", ""]]
+        );
+        assert_eq!(grid.merges, [(1, 0, 2, 0)]);
+    }
+}
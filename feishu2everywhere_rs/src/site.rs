@@ -0,0 +1,461 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+
+use crate::comments::block_plain_text;
+use crate::document::Document;
+use crate::to_html::{self, Heading};
+use crate::xml::escape;
+
+/// light and dark by the system setting, --theme-css is loaded after it to override
+const STYLE_CSS: &str = r#":root {
+    --bg: #ffffff; --fg: #1f2329; --muted: #646a73; --accent: #3370ff;
+    --border: #dee0e3; --code-bg: #f5f6f7; --note-bg: #fff8e6;
+}
+@media (prefers-color-scheme: dark) {
+    :root {
+        --bg: #1a1a1a; --fg: #e6e6e6; --muted: #a0a4aa; --accent: #6b96ff;
+        --border: #3a3a3a; --code-bg: #262626; --note-bg: #2e2a1f;
+    }
+}
+* { box-sizing: border-box; }
+body {
+    margin: 0; display: grid; grid-template-columns: 16rem minmax(0, 1fr) 14rem;
+    background: var(--bg); color: var(--fg);
+    font: 16px/1.6 -apple-system, "Segoe UI", "PingFang SC", "Microsoft YaHei", sans-serif;
+}
+a { color: var(--accent); text-decoration: none; }
+a:hover { text-decoration: underline; }
+.site-nav, .toc { position: sticky; top: 0; height: 100vh; overflow-y: auto; padding: 1rem; font-size: 0.9rem; }
+.site-nav { border-right: 1px solid var(--border); }
+.site-nav ul, .toc ul { list-style: none; padding-left: 0.8rem; margin: 0.2rem 0; }
+.site-nav > ul { padding-left: 0; }
+.site-nav .folder { color: var(--muted); }
+.site-nav .current > a { font-weight: bold; }
+.site-title { display: block; font-weight: bold; font-size: 1.1rem; margin-bottom: 0.8rem; color: var(--fg); }
+#search { width: 100%; padding: 0.3rem 0.5rem; border: 1px solid var(--border); border-radius: 4px; background: var(--bg); color: var(--fg); }
+#search-results { padding-left: 0; margin: 0.5rem 0; }
+#search-results li { list-style: none; margin-bottom: 0.5rem; }
+#search-results small { display: block; color: var(--muted); }
+main { padding: 1rem 3rem; min-width: 0; }
+article { max-width: 48rem; }
+.toc { border-left: 1px solid var(--border); }
+.toc .level-1 { padding-left: 0; }
+.toc .level-2 { padding-left: 0.8rem; }
+.toc .level-3 { padding-left: 1.6rem; }
+.toc .level-4, .toc .level-5, .toc .level-6 { padding-left: 2.4rem; }
+.breadcrumb { color: var(--muted); font-size: 0.9rem; }
+pre { background: var(--code-bg); padding: 0.8rem; overflow-x: auto; border-radius: 4px; }
+code { background: var(--code-bg); padding: 0 0.2rem; border-radius: 3px; }
+pre code { padding: 0; }
+table { border-collapse: collapse; display: block; overflow-x: auto; }
+th, td { border: 1px solid var(--border); padding: 0.3rem 0.6rem; }
+figure { margin: 1rem 0; }
+figure img { max-width: 100%; height: auto; }
+figure.align-center { text-align: center; }
+figure.align-right { text-align: right; }
+figcaption { color: var(--muted); font-size: 0.9rem; }
+.task-list { list-style: none; padding-left: 1rem; }
+.callout { background: var(--code-bg); border-left: 4px solid var(--accent); border-radius: 4px; padding: 0.2rem 1rem; margin: 1rem 0; }
+.callout-emoji { float: left; margin: 0.8rem 0.6rem 0 0; }
+.with-notes { position: relative; }
+.margin-note {
+    float: right; clear: right; width: 14rem; margin: 0 -16rem 0.5rem 1rem;
+    background: var(--note-bg); border-radius: 4px; padding: 0.4rem 0.6rem; font-size: 0.85rem;
+}
+.margin-note blockquote { margin: 0 0 0.3rem; color: var(--muted); }
+.margin-note p { margin: 0.2rem 0; }
+.margin-note.resolved { opacity: 0.6; }
+.discussion .margin-note { float: none; width: auto; margin: 0.5rem 0; }
+@media (max-width: 70rem) {
+    body { grid-template-columns: 14rem minmax(0, 1fr); }
+    .toc { display: none; }
+    .margin-note { float: none; width: auto; margin: 0.5rem 0; }
+}
+@media (max-width: 45rem) {
+    body { display: block; }
+    .site-nav { position: static; height: auto; border-right: none; border-bottom: 1px solid var(--border); }
+    main { padding: 1rem; }
+}
+"#;
+
+/// filters `window.SEARCH_INDEX` as the user types, every term has to match
+const SEARCH_JS: &str = r#"(function () {
+    var input = document.getElementById('search');
+    var results = document.getElementById('search-results');
+    var root = document.body.getAttribute('data-root') || '';
+    var esc = function (s) {
+        return s.replace(/[&<>"]/g, function (c) {
+            return { '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;' }[c];
+        });
+    };
+    input.addEventListener('input', function () {
+        var terms = input.value.toLowerCase().split(/\s+/).filter(function (t) { return t; });
+        results.innerHTML = '';
+        if (!terms.length) return;
+        var hits = (window.SEARCH_INDEX || []).filter(function (page) {
+            var hay = (page.title + '\n' + page.text).toLowerCase();
+            return terms.every(function (t) { return hay.indexOf(t) >= 0; });
+        }).slice(0, 20);
+        hits.forEach(function (page) {
+            var at = page.text.toLowerCase().indexOf(terms[0]);
+            var snippet = at < 0 ? '' : page.text.substring(Math.max(0, at - 30), at + 70).replace(/\s+/g, ' ');
+            var li = document.createElement('li');
+            li.innerHTML = '<a href="' + esc(root + page.url) + '">' + esc(page.title) + '</a>'
+                + (snippet ? '<small>' + esc(snippet) + '</small>' : '');
+            results.appendChild(li);
+        });
+        if (!hits.length) results.innerHTML = '<li><small>no results</small></li>';
+    });
+})();
+"#;
+
+/// one exported document, found by its json sidecar
+struct SitePage {
+    doc: Document,
+    sidecar: PathBuf,
+    title: String,
+    /// output path relative to the site root, `/` separated
+    url: String,
+}
+
+impl SitePage {
+    /// `../` back to the site root from this page
+    fn root(&self) -> String {
+        "../".repeat(self.url.matches('/').count())
+    }
+
+    /// wiki ancestors then the page itself
+    fn nav_path(&self) -> Vec<String> {
+        let mut path = self.doc.meta.wiki_path.clone();
+        path.push(self.title.clone());
+        path
+    }
+}
+
+/// navigation tree following the wiki hierarchy, folders are wiki nodes that were not exported
+#[derive(Debug, Default)]
+pub struct NavNode {
+    pub title: String,
+    pub url: Option<String>,
+    pub children: Vec<NavNode>,
+}
+
+impl NavNode {
+    pub fn insert(&mut self, path: &[String], url: &str) {
+        let Some((first, rest)) = path.split_first() else {
+            self.url = Some(url.to_string());
+            return;
+        };
+        let index = match self.children.iter().position(|c| &c.title == first) {
+            Some(index) => index,
+            None => {
+                self.children.push(NavNode {
+                    title: first.clone(),
+                    ..Default::default()
+                });
+                self.children.len() - 1
+            }
+        };
+        self.children[index].insert(rest, url);
+    }
+
+    fn render(&self, root: &str, current: Option<&str>) -> String {
+        let mut html = String::from("<ul>\n");
+        for child in &self.children {
+            let is_current = child.url.is_some() && child.url.as_deref() == current;
+            html.push_str(if is_current {
+                "<li class=\"current\">"
+            } else {
+                "<li>"
+            });
+            match &child.url {
+                Some(url) => html.push_str(&format!(
+                    "<a href=\"{}{}\">{}</a>",
+                    root,
+                    escape(url),
+                    escape(&child.title)
+                )),
+                None => html.push_str(&format!(
+                    "<span class=\"folder\">{}</span>",
+                    escape(&child.title)
+                )),
+            }
+            if !child.children.is_empty() {
+                html.push('\n');
+                html.push_str(&child.render(root, current));
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ul>\n");
+        html
+    }
+}
+
+/// the document title, else the name it was exported under
+pub fn page_title(doc: &Document, sidecar: &Path) -> String {
+    doc.meta.title.clone().unwrap_or_else(|| {
+        sidecar
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    })
+}
+
+/// every `*.json` under `dir` that is an exported document, the site output itself excluded
+pub fn find_documents(dir: &Path, skip: &Path, found: &mut Vec<(PathBuf, Document)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || path.canonicalize().is_ok_and(|p| p == skip) {
+            continue;
+        }
+        if path.is_dir() {
+            // resources and history snapshots of an export hold no sidecars
+            if !name.ends_with(".rsc") && !name.ends_with(".history") {
+                find_documents(&path, skip, found);
+            }
+        } else if path.extension().is_some_and(|e| e == "json")
+            // block json and ast written by --notion and --pandoc
+            && !name.ends_with(".notion.json")
+            && !name.ends_with(".pandoc.json")
+        {
+            match Document::load_json(&path) {
+                Ok(doc) => found.push((path, doc)),
+                Err(e) => println!("skip {:?}, not an exported document: {}", path, e),
+            }
+        }
+    }
+}
+
+fn render_toc(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<nav class=\"toc\">\n<strong>On this page</strong>\n<ul>\n");
+    for heading in headings {
+        html.push_str(&format!(
+            "<li class=\"level-{}\"><a href=\"#{}\">{}</a></li>\n",
+            heading.level.min(6),
+            escape(&heading.id),
+            escape(&heading.text)
+        ));
+    }
+    html.push_str("</ul>\n</nav>\n");
+    html
+}
+
+struct Layout<'a> {
+    site_title: &'a str,
+    has_theme: bool,
+    nav: &'a NavNode,
+}
+
+impl Layout<'_> {
+    fn page(
+        &self,
+        title: &str,
+        root: &str,
+        current: Option<&str>,
+        main: &str,
+        toc: &str,
+    ) -> String {
+        let theme = if self.has_theme {
+            format!(
+                "<link rel=\"stylesheet\" href=\"{}assets/theme.css\" />\n",
+                root
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            r#"<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+<meta charset="utf-8" />
+<meta name="viewport" content="width=device-width, initial-scale=1" />
+<title>{title}</title>
+<link rel="stylesheet" href="{root}assets/style.css" />
+{theme}</head>
+<body data-root="{root}">
+<nav class="site-nav">
+<a class="site-title" href="{root}index.html">{site_title}</a>
+<input id="search" type="search" placeholder="Search" autocomplete="off" />
+<ul id="search-results"></ul>
+{nav}</nav>
+<main>
+{main}</main>
+{toc}<script src="{root}assets/search-index.js"></script>
+<script src="{root}assets/search.js"></script>
+</body>
+</html>
+"#,
+            title = escape(title),
+            root = root,
+            theme = theme,
+            site_title = escape(self.site_title),
+            nav = self.nav.render(root, current),
+            main = main,
+            toc = toc,
+        )
+    }
+}
+
+/// where the image of `cached_path` is now: the asset store, or the `.rsc` folder next to the sidecar
+pub fn find_image(cached_path: &Path, sidecar: &Path) -> Option<PathBuf> {
+    if cached_path.is_file() {
+        return Some(cached_path.to_path_buf());
+    }
+    let stem = sidecar.file_stem()?.to_string_lossy();
+    let path = sidecar
+        .with_file_name(format!("{}.rsc", stem))
+        .join(cached_path.file_name()?);
+    path.is_file().then_some(path)
+}
+
+/// Build a static site in `output` from every export under `input`: one page per document
+/// rendered from its json sidecar, a navigation tree following the wiki hierarchy,
+/// a table of contents per page and a client side search index
+pub fn build_site(
+    input: &Path,
+    output: &Path,
+    site_title: &str,
+    theme_css: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(output)?;
+    let mut found = vec![];
+    find_documents(input, &output.canonicalize()?, &mut found);
+    if found.is_empty() {
+        return Err(format!("no exported documents (*.json sidecars) under {:?}", input).into());
+    }
+
+    let mut pages = found
+        .into_iter()
+        .map(|(sidecar, doc)| {
+            let rel = sidecar.strip_prefix(input).unwrap_or(&sidecar);
+            let url = rel
+                .with_extension("html")
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("/");
+            let title = page_title(&doc, &sidecar);
+            SitePage {
+                doc,
+                sidecar,
+                title,
+                url,
+            }
+        })
+        .collect::<Vec<_>>();
+    pages.sort_by_key(|p| p.nav_path());
+
+    let mut nav = NavNode::default();
+    for page in &pages {
+        nav.insert(&page.nav_path(), &page.url);
+    }
+
+    let assets_dir = output.join("assets");
+    let images_dir = assets_dir.join("img");
+    fs::create_dir_all(&images_dir)?;
+    fs::write(assets_dir.join("style.css"), STYLE_CSS)?;
+    fs::write(assets_dir.join("search.js"), SEARCH_JS)?;
+    if let Some(theme_css) = theme_css {
+        fs::copy(theme_css, assets_dir.join("theme.css"))?;
+    }
+    let layout = Layout {
+        site_title,
+        has_theme: theme_css.is_some(),
+        nav: &nav,
+    };
+
+    let mut search_index = vec![];
+    for page in &pages {
+        let root = page.root();
+        let mut link_image = |cached_path: &Path| -> Result<String, Box<dyn std::error::Error>> {
+            let file_name = cached_path
+                .file_name()
+                .ok_or("image path has no file name")?
+                .to_string_lossy()
+                .to_string();
+            // asset file names are content hashes, pages sharing an image share the copy
+            let target = images_dir.join(&file_name);
+            if !target.exists() {
+                match find_image(cached_path, &page.sidecar) {
+                    Some(source) => {
+                        fs::copy(source, &target)?;
+                    }
+                    None => println!("image {:?} of {:?} not found", cached_path, page.sidecar),
+                }
+            }
+            Ok(format!("{}assets/img/{}", root, file_name))
+        };
+        let html = to_html::render_document(&page.doc, &mut link_image)?;
+
+        let mut main = String::new();
+        if !page.doc.meta.wiki_path.is_empty() {
+            main.push_str(&format!(
+                "<p class=\"breadcrumb\">{}</p>\n",
+                escape(&page.doc.meta.wiki_path.join(" / "))
+            ));
+        }
+        main.push_str(&format!(
+            "<article>\n<h1 class=\"page-title\">{}</h1>\n{}</article>\n",
+            escape(&page.title),
+            html.body
+        ));
+        let out_path = output.join(&page.url);
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            &out_path,
+            layout.page(
+                &format!("{} - {}", page.title, site_title),
+                &root,
+                Some(&page.url),
+                &main,
+                &render_toc(&html.headings),
+            ),
+        )?;
+
+        search_index.push(json!({
+            "title": page.title,
+            "url": page.url,
+            "text": page
+                .doc
+                .blocks
+                .iter()
+                .map(block_plain_text)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }));
+    }
+
+    fs::write(
+        assets_dir.join("search-index.js"),
+        format!(
+            "window.SEARCH_INDEX = {};\n",
+            serde_json::to_string(&search_index)?
+        ),
+    )?;
+    let index_main = format!(
+        "<article>\n<h1 class=\"page-title\">{}</h1>\n{}</article>\n",
+        escape(site_title),
+        nav.render("", None)
+    );
+    fs::write(
+        output.join("index.html"),
+        layout.page(site_title, "", None, &index_main, ""),
+    )?;
+    println!("Wrote {} pages to {:?}", pages.len(), output);
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::time::Duration;
+
+use thirtyfour::{By, WebDriver, WebElement};
+
+use crate::sheet::{self, SheetGrid};
+use crate::to_xlsx;
+
+/// tab strip of a standalone sheet, the order is the workbook order
+const TAB_SELECTOR: &str = ".sheet-tab-item, .sheet-bar-tab, [role=tab]";
+/// container of the active tab's grid
+const GRID_SELECTOR: &str = ".spreadsheet-container, .sheet-container, [role=grid]";
+
+/// one exported tab of a workbook
+#[derive(Debug, Clone)]
+pub struct SheetTab {
+    pub name: String,
+    pub grid: SheetGrid,
+}
+
+async fn find_grid(driver: &WebDriver) -> Option<WebElement> {
+    let start = std::time::Instant::now();
+    while start.elapsed() < Duration::from_secs(20) {
+        if let Ok(grid) = driver.find(By::Css(GRID_SELECTOR)).await {
+            return Some(grid);
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    None
+}
+
+/// Scrape every tab of the standalone sheet opened in `driver`.
+///
+/// Tabs are activated one by one and the grid of each is scrolled through with
+/// `sheet::scrape_virtual_grid_detailed`. A tab without readable cells is kept empty.
+pub async fn collect_tabs(driver: &WebDriver) -> Vec<SheetTab> {
+    let tab_count = driver
+        .find_all(By::Css(TAB_SELECTOR))
+        .await
+        .map(|tabs| tabs.len())
+        .unwrap_or(0);
+    println!("sheet has {} tabs", tab_count);
+
+    let mut tabs = vec![];
+    // a workbook with a single tab may not render the tab strip at all
+    for index in 0..tab_count.max(1) {
+        let mut name = format!("Sheet{}", index + 1);
+        // re-query every time, switching tabs re-renders the strip
+        if let Ok(tab_elems) = driver.find_all(By::Css(TAB_SELECTOR)).await
+            && let Some(tab) = tab_elems.get(index)
+        {
+            if let Ok(text) = tab.text().await
+                && !text.trim().is_empty()
+            {
+                name = text.trim().to_string();
+            }
+            if let Err(err) = tab.click().await {
+                println!("err switch to tab {}: {:?}", name, err);
+                continue;
+            }
+            // wait for the grid to swap to the new tab
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+        }
+
+        let Some(grid_elem) = find_grid(driver).await else {
+            println!("no grid found for tab {}", name);
+            continue;
+        };
+        let grid = sheet::scrape_virtual_grid_detailed(driver, &grid_elem)
+            .await
+            .unwrap_or_else(|| {
+                println!("tab {} has no readable cells", name);
+                SheetGrid::default()
+            });
+        println!("extracted tab {}: {} rows", name, grid.rows.len());
+        tabs.push(SheetTab { name, grid });
+    }
+    tabs
+}
+
+/// keep tab names usable as file names
+pub fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Write one `{n}_{tab}.csv` per tab into `out_dir`, plus the whole workbook when `xlsx_path` is set
+pub fn export_tabs(
+    tabs: &[SheetTab],
+    out_dir: &Path,
+    xlsx_path: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(out_dir)?;
+    for (index, tab) in tabs.iter().enumerate() {
+        let csv_path = out_dir.join(format!("{}_{}.csv", index, file_stem(&tab.name)));
+        std::fs::write(&csv_path, sheet::rows_to_csv(&tab.grid.rows))?;
+        println!("Saved tab to: {:?}", csv_path);
+    }
+
+    if let Some(xlsx_path) = xlsx_path {
+        to_xlsx::export_tabs_to_xlsx(tabs, xlsx_path)?;
+        println!("Saved workbook to: {:?}", xlsx_path);
+    }
+    Ok(())
+}
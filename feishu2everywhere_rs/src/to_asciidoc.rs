@@ -0,0 +1,374 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::obsidian::callout_type;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+use crate::to_rst::SectionDepth;
+
+/// deepest section, `======`, deeper headings are discrete
+const MAX_SECTION_DEPTH: usize = 5;
+
+/// a list marker, block title, delimiter or attribute entry at the start of a line
+fn starts_block_markup(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        return matches!(line[digits..].chars().next(), Some('.' | ')'));
+    }
+    line.starts_with(['.', '*', '-', '=', '/', '|', '<', '[', ':', '\'', '+'])
+}
+
+/// Escape text for a paragraph, `at_line_start` tells whether it begins a line
+pub fn escape(text: &str, at_line_start: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        // `{empty}` renders nothing and keeps a leading `.`, `*`, `=`... from being markup
+        if (index > 0 || at_line_start) && starts_block_markup(line) {
+            out.push_str("{empty}");
+        }
+        let mut previous = None;
+        for c in line.chars() {
+            // `[[` opens an anchor and `<<` a cross reference
+            if (c == '[' || c == '<') && previous == Some(c) {
+                out.pop();
+                out.push('\\');
+                out.push(c);
+            }
+            previous = Some(c);
+            match c {
+                '*' | '_' | '`' | '#' | '^' | '~' | '{' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c if c.is_control() && c != '\t' => {}
+                c => out.push(c),
+            }
+        }
+    }
+    out
+}
+
+/// text of a macro like `link:url[text]`, where `]` ends it
+fn macro_text(text: &str) -> String {
+    escape(&text.replace('\n', " "), false).replace(']', "\\]")
+}
+
+fn render_text_slices(slices: &[TextSlice], at_line_start: bool) -> String {
+    let mut adoc = String::new();
+    for slice in slices {
+        if slice.text.is_empty() {
+            continue;
+        }
+        let line_start = if adoc.is_empty() {
+            at_line_start
+        } else {
+            adoc.ends_with('\n')
+        };
+        let core = slice.text.trim();
+        let marked = slice.is_bold || slice.is_underline || slice.is_code || slice.link.is_some();
+        if core.is_empty() || !marked {
+            adoc.push_str(&escape(&slice.text, line_start));
+            continue;
+        }
+        // spaces stay outside the marks and the link text
+        let lead = &slice.text[..slice.text.len() - slice.text.trim_start().len()];
+        let trail = &slice.text[slice.text.trim_end().len()..];
+        let core = core.replace('\n', " ");
+        // unconstrained (doubled) marks work next to cjk characters as well
+        let mut current = if slice.is_code {
+            format!("``+{}+``", core)
+        } else if slice.link.is_some() {
+            macro_text(&core)
+        } else {
+            escape(&core, false)
+        };
+        if slice.is_bold {
+            current = format!("**{}**", current);
+        }
+        if slice.is_underline {
+            current = format!("[.underline]##{}##", current);
+        }
+        if let Some(link) = &slice.link {
+            current = format!("link:++{}++[{}]", link, current);
+        }
+        adoc.push_str(&escape(lead, line_start));
+        adoc.push_str(&current);
+        adoc.push_str(trail);
+    }
+    // a line ending in ` +` breaks there
+    adoc.trim_end().replace('\n', " +\n")
+}
+
+fn render_outline(node: &OutlineNode, depth: usize) -> String {
+    let mut adoc = format!("{} {}\n", "*".repeat(depth), escape(&node.text, false));
+    for child in &node.children {
+        adoc.push_str(&render_outline(child, depth + 1));
+    }
+    adoc
+}
+
+/// admonition style for the emoji leading a feishu callout
+fn admonition(emoji: Option<&str>) -> &'static str {
+    match callout_type(emoji) {
+        "tip" | "success" => "TIP",
+        "warning" | "bug" => "WARNING",
+        "danger" => "CAUTION",
+        "important" => "IMPORTANT",
+        _ => "NOTE",
+    }
+}
+
+/// a delimiter longer than any line of the content made of its character
+fn delimiter(content: &str, c: char) -> String {
+    let longest = content
+        .lines()
+        .filter(|l| !l.is_empty() && l.chars().all(|x| x == c))
+        .map(|l| l.chars().count())
+        .max()
+        .unwrap_or(0);
+    c.to_string().repeat(longest.max(3) + 1)
+}
+
+struct AsciidocWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+    sections: SectionDepth,
+}
+
+impl AsciidocWriter {
+    fn image(
+        &self,
+        cached_path: &Path,
+        alt: Option<&str>,
+        caption: Option<&str>,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let path = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let mut attributes = vec![format!(
+            "\"{}\"",
+            alt.or(caption)
+                .unwrap_or_default()
+                .replace('\n', " ")
+                .replace('"', "\\\"")
+        )];
+        if let Some(width) = width {
+            attributes.push(format!("width={}", width));
+        }
+        match align {
+            Some(ImageAlign::Left) => attributes.push("align=left".to_string()),
+            Some(ImageAlign::Center) => attributes.push("align=center".to_string()),
+            Some(ImageAlign::Right) => attributes.push("align=right".to_string()),
+            None => {}
+        }
+        let mut adoc = String::new();
+        if let Some(caption) = caption {
+            // a block title, shown as the figure caption
+            adoc.push_str(&format!(
+                ".{}\n",
+                escape(&caption.replace('\n', " "), false)
+            ));
+        }
+        adoc.push_str(&format!("image::{}[{}]\n\n", path, attributes.join(",")));
+        Ok(adoc)
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+        depth: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut adoc = String::new();
+        for item in items {
+            let marker = match list_type {
+                ListType::Ordered => ".".repeat(depth),
+                _ => "*".repeat(depth),
+            };
+            let check = match (list_type, item.done) {
+                (ListType::Task, Some(true)) => "[x] ",
+                (ListType::Task, _) => "[ ] ",
+                _ => "",
+            };
+            adoc.push_str(&format!(
+                "{} {}{}\n",
+                marker,
+                check,
+                render_text_slices(&item.headline, false)
+            ));
+            for following in &item.following {
+                // nested lists go one marker deeper, other blocks attach with a continuation
+                let block = match following {
+                    Block::List { list_type, items } => {
+                        self.list_items(*list_type, items, depth + 1)?
+                    }
+                    other => format!("+\n{}", self.block(other)?),
+                };
+                adoc.push_str(block.trim_end());
+                adoc.push('\n');
+            }
+        }
+        if depth == 1 {
+            // a blank line alone would let the next list continue this one
+            adoc.push_str("\n//-\n\n");
+        }
+        Ok(adoc)
+    }
+
+    fn block(&mut self, block: &Block) -> Result<String, Box<dyn std::error::Error>> {
+        let adoc = match block {
+            Block::Text(slices) => {
+                let text = render_text_slices(slices, true);
+                if text.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", text)
+                }
+            }
+            Block::Title { text, head_level } => {
+                let depth = self.sections.enter(head_level_to_usize(head_level));
+                let title = escape(&text.replace('\n', " "), false);
+                if depth > MAX_SECTION_DEPTH {
+                    format!("[discrete]\n====== {}\n\n", title)
+                } else {
+                    format!("{} {}\n\n", "=".repeat(depth + 1), title)
+                }
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items, 1)?,
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => self.image(
+                cached_path,
+                alt.as_deref(),
+                caption.as_deref(),
+                *width,
+                *align,
+            )?,
+            Block::Code { language, code } => {
+                let code = code.trim_end_matches('\n');
+                let fence = delimiter(code, '-');
+                let style = if language.is_empty() {
+                    "[source]".to_string()
+                } else {
+                    format!("[source,{}]", language.to_lowercase().replace(' ', "-"))
+                };
+                format!("{}\n{}\n{}\n{}\n\n", style, fence, code, fence)
+            }
+            Block::Sheet { cells } => {
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                if columns == 0 {
+                    return Ok(String::new());
+                }
+                let mut adoc = format!(
+                    "[%header,cols=\"{}\"]\n|===\n",
+                    vec!["1"; columns].join(",")
+                );
+                for row in cells {
+                    for c in 0..columns {
+                        let text = row.get(c).map(String::as_str).unwrap_or_default();
+                        let text = escape(text, false).replace('|', "\\|");
+                        adoc.push_str(&format!("|{}\n", text.replace('\n', " +\n")));
+                    }
+                    adoc.push('\n');
+                }
+                adoc.push_str("|===\n\n");
+                adoc
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut adoc = self.image(cached_path, None, None, None, None)?;
+                if let Some(root) = outline {
+                    adoc.push_str(&render_outline(root, 1));
+                    adoc.push('\n');
+                }
+                adoc
+            }
+            Block::Callout { emoji, children } => {
+                let mut content = String::new();
+                for child in children {
+                    content.push_str(&self.block(child)?);
+                }
+                let content = content.trim_end();
+                let fence = delimiter(content, '=');
+                format!(
+                    "[{}]\n{}\n{}\n{}\n\n",
+                    admonition(emoji.as_deref()),
+                    fence,
+                    content,
+                    fence
+                )
+            }
+        };
+        Ok(adoc)
+    }
+}
+
+/// Write the document as asciidoc for asciidoctor or antora,
+/// images go to `<stem>.rsc` next to it
+pub fn export_document_to_asciidoc(
+    doc: &Document,
+    adoc_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = adoc_path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = adoc_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let mut writer = AsciidocWriter {
+        rsc_dir_name,
+        rsc_path,
+        sections: SectionDepth::default(),
+    };
+
+    let mut adoc = String::new();
+    if let Some(title) = &doc.meta.title {
+        adoc.push_str(&format!("= {}\n", escape(&title.replace('\n', " "), false)));
+        if let Some(author) = &doc.meta.author {
+            adoc.push_str(&format!(":author: {}\n", author));
+        }
+        if let Some(date) = doc.meta.modified.or(doc.meta.created) {
+            adoc.push_str(&format!(":revdate: {}\n", date.format("%Y-%m-%d")));
+        }
+        adoc.push('\n');
+    }
+    for block in &doc.blocks {
+        adoc.push_str(&writer.block(block)?);
+    }
+    fs::write(adoc_path, adoc.trim_end().to_string() + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_export_golden;
+
+    #[test]
+    fn test_escape_keeps_block_markup_literal() {
+        assert_eq!(escape("1. not a list", true), "{empty}1. not a list");
+        assert_eq!(escape("* star", false), "\\* star");
+        assert_eq!(escape("see [[anchor]]", true), "see \\[[anchor]]");
+    }
+
+    #[test]
+    fn test_export_golden() {
+        assert_export_golden("document.adoc", export_document_to_asciidoc);
+    }
+}
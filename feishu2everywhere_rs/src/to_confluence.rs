@@ -0,0 +1,302 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::obsidian::callout_type;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+use crate::xml::escape;
+
+/// A page in confluence storage format, its images are attachments of the page
+#[derive(Debug, Clone, Default)]
+pub struct StoragePage {
+    pub body: String,
+    /// images referenced by attachment file name, unique
+    pub attachments: Vec<PathBuf>,
+}
+
+struct StorageWriter {
+    attachments: Vec<PathBuf>,
+    /// numbers the tasks of the page, confluence wants them unique
+    task_count: usize,
+}
+
+/// code macro body, `]]>` cannot appear inside a cdata section
+fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// code macro languages are lowercase names, a few feishu names differ
+fn code_macro_language(language: &str) -> String {
+    match language.to_lowercase().as_str() {
+        "c++" | "cpp" => "cpp".to_string(),
+        "c#" | "csharp" => "c#".to_string(),
+        "shell" | "sh" | "zsh" => "bash".to_string(),
+        "golang" => "go".to_string(),
+        "js" => "javascript".to_string(),
+        "ts" => "typescript".to_string(),
+        "plaintext" | "plain text" => "text".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// info, tip, note or warning panel for the emoji leading a feishu callout
+fn panel_macro(emoji: Option<&str>) -> &'static str {
+    match callout_type(emoji) {
+        "tip" | "success" => "tip",
+        "warning" | "bug" => "note",
+        "danger" | "important" => "warning",
+        _ => "info",
+    }
+}
+
+fn render_text_slices(slices: &[TextSlice]) -> String {
+    let mut html = String::new();
+    for slice in slices {
+        let mut current = escape(&slice.text).replace('\n', "<br />");
+        if slice.is_code {
+            current = format!("<code>{}</code>", current);
+        }
+        if slice.is_bold {
+            current = format!("<strong>{}</strong>", current);
+        }
+        if slice.is_underline {
+            current = format!("<u>{}</u>", current);
+        }
+        if let Some(link) = &slice.link {
+            current = format!("<a href=\"{}\">{}</a>", escape(link), current);
+        }
+        html.push_str(&current);
+    }
+    html
+}
+
+fn render_outline(node: &OutlineNode) -> String {
+    let mut html = format!("<li>{}", escape(&node.text));
+    if !node.children.is_empty() {
+        html.push_str("<ul>");
+        for child in &node.children {
+            html.push_str(&render_outline(child));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</li>\n");
+    html
+}
+
+impl StorageWriter {
+    fn image(
+        &mut self,
+        cached_path: &Path,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+    ) -> String {
+        let file_name = cached_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        // store names are content hashes, an image used twice is attached once
+        if !self.attachments.iter().any(|a| a == cached_path) {
+            self.attachments.push(cached_path.to_path_buf());
+        }
+        let mut attributes = String::new();
+        if let Some(width) = width {
+            attributes.push_str(&format!(" ac:width=\"{}\"", width));
+        }
+        match align {
+            Some(ImageAlign::Center) => attributes.push_str(" ac:align=\"center\""),
+            Some(ImageAlign::Right) => attributes.push_str(" ac:align=\"right\""),
+            _ => {}
+        }
+        format!(
+            "<ac:image{}><ri:attachment ri:filename=\"{}\" /></ac:image>",
+            attributes,
+            escape(&file_name)
+        )
+    }
+
+    fn list_items(&mut self, list_type: ListType, items: &[ListOne]) -> String {
+        let mut html = String::new();
+        if list_type == ListType::Task {
+            html.push_str("<ac:task-list>\n");
+            for item in items {
+                self.task_count += 1;
+                let status = if item.done == Some(true) {
+                    "complete"
+                } else {
+                    "incomplete"
+                };
+                html.push_str(&format!(
+                    "<ac:task>\n<ac:task-id>{}</ac:task-id>\n<ac:task-status>{}</ac:task-status>\n<ac:task-body>{}",
+                    self.task_count,
+                    status,
+                    render_text_slices(&item.headline)
+                ));
+                for following in &item.following {
+                    html.push_str(&self.block(following));
+                }
+                html.push_str("</ac:task-body>\n</ac:task>\n");
+            }
+            html.push_str("</ac:task-list>\n");
+            return html;
+        }
+        let (open, close) = match list_type {
+            ListType::Ordered => ("<ol>", "</ol>"),
+            _ => ("<ul>", "</ul>"),
+        };
+        html.push_str(open);
+        html.push('\n');
+        for item in items {
+            html.push_str(&format!("<li>{}", render_text_slices(&item.headline)));
+            for following in &item.following {
+                html.push_str(&self.block(following));
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str(close);
+        html.push('\n');
+        html
+    }
+
+    fn block(&mut self, block: &Block) -> String {
+        match block {
+            Block::Text(slices) => format!("<p>{}</p>\n", render_text_slices(slices)),
+            Block::Title { text, head_level } => {
+                let level = head_level_to_usize(head_level).min(6);
+                format!("<h{level}>{}</h{level}>\n", escape(text), level = level)
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items),
+            Block::Image {
+                cached_path,
+                caption,
+                width,
+                align,
+                ..
+            } => {
+                let mut html = format!("<p>{}</p>\n", self.image(cached_path, *width, *align));
+                if let Some(caption) = caption {
+                    html.push_str(&format!("<p><em>{}</em></p>\n", escape(caption)));
+                }
+                html
+            }
+            Block::Code { language, code } => {
+                let mut html = String::from("<ac:structured-macro ac:name=\"code\">");
+                if !language.is_empty() {
+                    html.push_str(&format!(
+                        "<ac:parameter ac:name=\"language\">{}</ac:parameter>",
+                        escape(&code_macro_language(language))
+                    ));
+                }
+                html.push_str(&format!(
+                    "<ac:plain-text-body>{}</ac:plain-text-body></ac:structured-macro>\n",
+                    cdata(code.trim_end_matches('\n'))
+                ));
+                html
+            }
+            Block::Sheet { cells } => {
+                let mut html = String::from("<table><tbody>\n");
+                for (index, row) in cells.iter().enumerate() {
+                    let tag = if index == 0 { "th" } else { "td" };
+                    html.push_str("<tr>");
+                    for cell in row {
+                        html.push_str(&format!(
+                            "<{tag}>{}</{tag}>",
+                            escape(cell).replace('\n', "<br />"),
+                            tag = tag
+                        ));
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</tbody></table>\n");
+                html
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut html = format!("<p>{}</p>\n", self.image(cached_path, None, None));
+                if let Some(root) = outline {
+                    html.push_str(&format!("<ul>\n{}</ul>\n", render_outline(root)));
+                }
+                html
+            }
+            Block::Callout { emoji, children } => {
+                let mut html = format!(
+                    "<ac:structured-macro ac:name=\"{}\"><ac:rich-text-body>\n",
+                    panel_macro(emoji.as_deref())
+                );
+                for child in children {
+                    html.push_str(&self.block(child));
+                }
+                html.push_str("</ac:rich-text-body></ac:structured-macro>\n");
+                html
+            }
+        }
+    }
+}
+
+/// Render the blocks of `doc` in confluence storage format
+pub fn render_storage(doc: &Document) -> StoragePage {
+    let mut writer = StorageWriter {
+        attachments: vec![],
+        task_count: 0,
+    };
+    let body = doc
+        .blocks
+        .iter()
+        .map(|block| writer.block(block))
+        .collect::<String>();
+    StoragePage {
+        body,
+        attachments: writer.attachments,
+    }
+}
+
+/// Write the page body to `path` and its attachments to `<stem>.rsc` next to it,
+/// ready to paste into the storage format editor or to upload
+pub fn export_document_to_confluence(
+    doc: &Document,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let page = render_storage(doc);
+    let stem = path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    for attachment in &page.attachments {
+        copy_image_to_rsc(attachment, &rsc_dir_name, &rsc_path)?;
+    }
+    fs::write(path, page.body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::{assert_golden, fixture};
+
+    #[test]
+    fn test_render_storage_golden() {
+        let page = render_storage(&fixture());
+        assert_golden("document.xhtml", &page.body);
+        assert_eq!(page.attachments, [PathBuf::from("tests/golden/image.png")]);
+    }
+
+    #[test]
+    fn test_cdata_splits_end_marker() {
+        assert_eq!(cdata("a]]>b"), "<![CDATA[a]]]]><![CDATA[>b]]>");
+        assert_eq!(
+            cdata("]]>]]>"),
+            "<![CDATA[]]]]><![CDATA[>]]]]><![CDATA[>]]>"
+        );
+    }
+}
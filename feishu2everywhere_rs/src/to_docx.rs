@@ -0,0 +1,637 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::outline::OutlineNode;
+use crate::to_markdown::head_level_to_usize;
+use crate::xml::escape;
+
+const NS_W: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+const NS_R: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const NS_WP: &str = "http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing";
+const NS_A: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+const NS_PIC: &str = "http://schemas.openxmlformats.org/drawingml/2006/picture";
+const REL_IMAGE: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships/image";
+const REL_HYPERLINK: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink";
+
+/// abstract numbering ids in numbering.xml
+const NUMBERING_BULLET: usize = 0;
+const NUMBERING_DECIMAL: usize = 1;
+const NUMBERING_TASK: usize = 2;
+
+/// twips of indentation per list level
+const LEVEL_INDENT: usize = 720;
+/// english metric units per css pixel
+const EMU_PER_PX: u64 = 9525;
+/// text width of a4/letter with the default margins, 6 inches
+const MAX_IMAGE_EMU: u64 = 5_486_400;
+
+const STYLES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:docDefaults><w:rPrDefault><w:rPr><w:rFonts w:ascii="Calibri" w:hAnsi="Calibri" w:eastAsia="Microsoft YaHei" w:cs="Calibri"/><w:sz w:val="22"/><w:szCs w:val="22"/><w:lang w:val="en-US" w:eastAsia="zh-CN"/></w:rPr></w:rPrDefault><w:pPrDefault><w:pPr><w:spacing w:after="120" w:line="264" w:lineRule="auto"/></w:pPr></w:pPrDefault></w:docDefaults>
+<w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/><w:qFormat/></w:style>
+<w:style w:type="paragraph" w:styleId="Title"><w:name w:val="Title"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:spacing w:after="240"/></w:pPr><w:rPr><w:sz w:val="52"/><w:szCs w:val="52"/></w:rPr></w:style>
+HEADING_STYLES
+<w:style w:type="paragraph" w:styleId="ListParagraph"><w:name w:val="List Paragraph"/><w:basedOn w:val="Normal"/><w:qFormat/><w:pPr><w:spacing w:after="60"/><w:contextualSpacing/></w:pPr></w:style>
+<w:style w:type="paragraph" w:styleId="Code"><w:name w:val="Code"/><w:basedOn w:val="Normal"/><w:qFormat/><w:pPr><w:shd w:val="clear" w:color="auto" w:fill="F3F3F3"/><w:spacing w:after="120" w:line="240" w:lineRule="auto"/></w:pPr><w:rPr><w:rFonts w:ascii="Consolas" w:hAnsi="Consolas" w:cs="Consolas"/><w:sz w:val="20"/><w:szCs w:val="20"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Caption"><w:name w:val="caption"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:rPr><w:i/><w:color w:val="595959"/><w:sz w:val="18"/><w:szCs w:val="18"/></w:rPr></w:style>
+<w:style w:type="character" w:styleId="CodeChar"><w:name w:val="Code Char"/><w:rPr><w:rFonts w:ascii="Consolas" w:hAnsi="Consolas" w:cs="Consolas"/><w:shd w:val="clear" w:color="auto" w:fill="F3F3F3"/></w:rPr></w:style>
+<w:style w:type="character" w:styleId="Hyperlink"><w:name w:val="Hyperlink"/><w:rPr><w:color w:val="0563C1"/><w:u w:val="single"/></w:rPr></w:style>
+<w:style w:type="table" w:styleId="TableGrid"><w:name w:val="Table Grid"/><w:tblPr><w:tblBorders><w:top w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:left w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:bottom w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:right w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideH w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideV w:val="single" w:sz="4" w:space="0" w:color="auto"/></w:tblBorders><w:tblCellMar><w:left w:w="108" w:type="dxa"/><w:right w:w="108" w:type="dxa"/></w:tblCellMar></w:tblPr></w:style>
+</w:styles>
+"#;
+
+/// Heading1-Heading9, word has no deeper built-in heading
+fn heading_styles() -> String {
+    let sizes = [36, 32, 28, 26, 24, 22, 22, 22, 22];
+    sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| {
+            format!(
+                "<w:style w:type=\"paragraph\" w:styleId=\"Heading{n}\"><w:name w:val=\"heading {n}\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before=\"240\" w:after=\"120\"/><w:outlineLvl w:val=\"{lvl}\"/></w:pPr><w:rPr><w:b/><w:sz w:val=\"{size}\"/><w:szCs w:val=\"{size}\"/></w:rPr></w:style>",
+                n = i + 1,
+                lvl = i,
+                size = size
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// one abstract numbering with all nine levels
+fn abstract_numbering(id: usize) -> String {
+    let mut xml = format!(
+        "<w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>",
+        id
+    );
+    for level in 0..9 {
+        let (format, text) = match id {
+            NUMBERING_BULLET => ("bullet", ["•", "◦", "▪"][level % 3].to_string()),
+            NUMBERING_DECIMAL => (
+                ["decimal", "lowerLetter", "lowerRoman"][level % 3],
+                format!("%{}.", level + 1),
+            ),
+            // the checkbox is part of the item text
+            _ => ("none", String::new()),
+        };
+        xml.push_str(&format!(
+            "<w:lvl w:ilvl=\"{}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{}\"/><w:lvlText w:val=\"{}\"/><w:lvlJc w:val=\"left\"/><w:pPr><w:ind w:left=\"{}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+            level,
+            format,
+            escape(&text),
+            LEVEL_INDENT * (level + 1)
+        ));
+    }
+    xml.push_str("</w:abstractNum>");
+    xml
+}
+
+struct Relationship {
+    id: String,
+    kind: &'static str,
+    target: String,
+    external: bool,
+}
+
+/// a list block, numbered from 1 again
+struct NumberingInstance {
+    abstract_id: usize,
+    level: usize,
+}
+
+#[derive(Default)]
+struct DocxWriter {
+    relationships: Vec<Relationship>,
+    /// file name in word/media and its bytes
+    media: Vec<(String, Vec<u8>)>,
+    numbering: Vec<NumberingInstance>,
+    drawing_count: usize,
+}
+
+/// `<w:t>` with the whitespace kept
+fn text_run_content(text: &str) -> String {
+    text.split('\n')
+        .map(|line| format!("<w:t xml:space=\"preserve\">{}</w:t>", escape(line)))
+        .collect::<Vec<_>>()
+        .join("<w:br/>")
+}
+
+fn indent_props(depth: usize) -> String {
+    if depth == 0 {
+        String::new()
+    } else {
+        format!("<w:ind w:left=\"{}\"/>", LEVEL_INDENT * depth)
+    }
+}
+
+fn paragraph(props: &str, runs: &str) -> String {
+    if props.is_empty() {
+        format!("<w:p>{}</w:p>\n", runs)
+    } else {
+        format!("<w:p><w:pPr>{}</w:pPr>{}</w:p>\n", props, runs)
+    }
+}
+
+/// a table cell must end with a paragraph
+fn cell_content(content: String) -> String {
+    let content_end = content.trim_end();
+    if content_end.ends_with("</w:p>") || content_end.ends_with("<w:p/>") {
+        content
+    } else {
+        format!("{}<w:p/>", content)
+    }
+}
+
+/// pixel size of an image, None when it cannot be read
+fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+impl DocxWriter {
+    fn add_relationship(&mut self, kind: &'static str, target: String, external: bool) -> String {
+        let id = format!("rId{}", self.relationships.len() + 10);
+        self.relationships.push(Relationship {
+            id: id.clone(),
+            kind,
+            target,
+            external,
+        });
+        id
+    }
+
+    fn new_numbering(&mut self, abstract_id: usize, level: usize) -> usize {
+        self.numbering
+            .push(NumberingInstance { abstract_id, level });
+        self.numbering.len()
+    }
+
+    fn runs(&mut self, slices: &[TextSlice]) -> String {
+        let mut xml = String::new();
+        for slice in slices {
+            let mut props = String::new();
+            if slice.link.is_some() {
+                props.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+            } else if slice.is_code {
+                props.push_str("<w:rStyle w:val=\"CodeChar\"/>");
+            }
+            if slice.is_bold {
+                props.push_str("<w:b/>");
+            }
+            if slice.is_underline {
+                props.push_str("<w:u w:val=\"single\"/>");
+            }
+            let props = if props.is_empty() {
+                props
+            } else {
+                format!("<w:rPr>{}</w:rPr>", props)
+            };
+            let run = format!("<w:r>{}{}</w:r>", props, text_run_content(&slice.text));
+            match &slice.link {
+                Some(link) => {
+                    let id = self.add_relationship(REL_HYPERLINK, link.clone(), true);
+                    xml.push_str(&format!(
+                        "<w:hyperlink r:id=\"{}\" w:history=\"1\">{}</w:hyperlink>",
+                        id, run
+                    ));
+                }
+                None => xml.push_str(&run),
+            }
+        }
+        xml
+    }
+
+    /// embed the image, word reads png, jpeg, gif and bmp, anything else is converted to png
+    fn image_run(
+        &mut self,
+        path: &Path,
+        alt: &str,
+        width: Option<u32>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let Ok(mut bytes) = fs::read(path) else {
+            println!("image {} is missing, left out of the docx", path.display());
+            return Ok(None);
+        };
+        let mut extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "bmp") {
+            let Ok(decoded) = image::load_from_memory(&bytes) else {
+                println!("image {} cannot be embedded in docx", path.display());
+                return Ok(None);
+            };
+            let mut png = Cursor::new(vec![]);
+            decoded.write_to(&mut png, image::ImageFormat::Png)?;
+            bytes = png.into_inner();
+            extension = "png".to_string();
+        }
+        let Some((px_width, px_height)) = image_size(&bytes) else {
+            println!(
+                "image {} cannot be read, left out of the docx",
+                path.display()
+            );
+            return Ok(None);
+        };
+
+        // shown width in css pixels, scaled down to the text width
+        let shown_width = width.unwrap_or(px_width).max(1) as u64;
+        let mut cx = shown_width * EMU_PER_PX;
+        let mut cy = cx * px_height.max(1) as u64 / px_width.max(1) as u64;
+        if cx > MAX_IMAGE_EMU {
+            cy = cy * MAX_IMAGE_EMU / cx;
+            cx = MAX_IMAGE_EMU;
+        }
+
+        self.drawing_count += 1;
+        let n = self.drawing_count;
+        let file_name = format!("image{}.{}", n, extension);
+        let id = self.add_relationship(REL_IMAGE, format!("media/{}", file_name), false);
+        self.media.push((file_name.clone(), bytes));
+        Ok(Some(format!(
+            "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\"><wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{n}\" name=\"Picture {n}\" descr=\"{alt}\"/><a:graphic xmlns:a=\"{ns_a}\"><a:graphicData uri=\"{ns_pic}\"><pic:pic xmlns:pic=\"{ns_pic}\"><pic:nvPicPr><pic:cNvPr id=\"{n}\" name=\"{file}\"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill><a:blip r:embed=\"{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill><pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm><a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic></a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+            cx = cx,
+            cy = cy,
+            n = n,
+            alt = escape(alt),
+            ns_a = NS_A,
+            ns_pic = NS_PIC,
+            file = file_name,
+            id = id
+        )))
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+        depth: usize,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let abstract_id = match list_type {
+            ListType::Ordered => NUMBERING_DECIMAL,
+            ListType::Unordered => NUMBERING_BULLET,
+            ListType::Task => NUMBERING_TASK,
+        };
+        let level = depth.min(8);
+        let num_id = self.new_numbering(abstract_id, level);
+        let props = format!(
+            "<w:pStyle w:val=\"ListParagraph\"/><w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+            level, num_id
+        );
+        let mut xml = String::new();
+        for item in items {
+            let mut runs = String::new();
+            if list_type == ListType::Task {
+                let checkbox = if item.done == Some(true) {
+                    "☒"
+                } else {
+                    "☐"
+                };
+                runs.push_str(&format!(
+                    "<w:r><w:t xml:space=\"preserve\">{} </w:t></w:r>",
+                    checkbox
+                ));
+            }
+            runs.push_str(&self.runs(&item.headline));
+            xml.push_str(&paragraph(&props, &runs));
+            // following blocks line up with the item text, nested lists go one level deeper
+            for following in &item.following {
+                xml.push_str(&self.block(following, depth + 1)?);
+            }
+        }
+        Ok(xml)
+    }
+
+    fn outline_items(&mut self, node: &OutlineNode, num_id: usize, depth: usize) -> String {
+        let props = format!(
+            "<w:pStyle w:val=\"ListParagraph\"/><w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+            depth.min(8),
+            num_id
+        );
+        let mut xml = paragraph(
+            &props,
+            &format!("<w:r>{}</w:r>", text_run_content(&node.text)),
+        );
+        for child in &node.children {
+            xml.push_str(&self.outline_items(child, num_id, depth + 1));
+        }
+        xml
+    }
+
+    fn block(&mut self, block: &Block, depth: usize) -> Result<String, Box<dyn std::error::Error>> {
+        let indent = indent_props(depth);
+        let xml = match block {
+            Block::Text(slices) => paragraph(&indent, &self.runs(slices)),
+            Block::Title { text, head_level } => {
+                let level = head_level_to_usize(head_level).clamp(1, 9);
+                paragraph(
+                    &format!("<w:pStyle w:val=\"Heading{}\"/>", level),
+                    &format!("<w:r>{}</w:r>", text_run_content(text)),
+                )
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items, depth)?,
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => {
+                let alt_text = alt.as_deref().or(caption.as_deref()).unwrap_or_default();
+                let jc = match align {
+                    Some(ImageAlign::Center) => "<w:jc w:val=\"center\"/>",
+                    Some(ImageAlign::Right) => "<w:jc w:val=\"right\"/>",
+                    _ => "",
+                };
+                let mut xml = match self.image_run(cached_path, alt_text, *width)? {
+                    Some(run) => paragraph(&format!("{}{}", indent, jc), &run),
+                    None => String::new(),
+                };
+                if let Some(caption) = caption {
+                    xml.push_str(&paragraph(
+                        &format!("<w:pStyle w:val=\"Caption\"/>{}{}", indent, jc),
+                        &format!("<w:r>{}</w:r>", text_run_content(caption)),
+                    ));
+                }
+                xml
+            }
+            Block::Code { code, .. } => paragraph(
+                &format!("<w:pStyle w:val=\"Code\"/>{}", indent),
+                &format!(
+                    "<w:r>{}</w:r>",
+                    text_run_content(code.trim_end_matches('\n'))
+                ),
+            ),
+            Block::Sheet { cells } => {
+                if cells.is_empty() {
+                    return Ok(String::new());
+                }
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                let mut xml = format!(
+                    "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"0\" w:type=\"auto\"/>{}</w:tblPr><w:tblGrid>{}</w:tblGrid>\n",
+                    if depth > 0 {
+                        format!(
+                            "<w:tblInd w:w=\"{}\" w:type=\"dxa\"/>",
+                            LEVEL_INDENT * depth
+                        )
+                    } else {
+                        String::new()
+                    },
+                    "<w:gridCol/>".repeat(columns)
+                );
+                for (r, row) in cells.iter().enumerate() {
+                    // the header row repeats on every page
+                    xml.push_str(if r == 0 {
+                        "<w:tr><w:trPr><w:tblHeader/></w:trPr>"
+                    } else {
+                        "<w:tr>"
+                    });
+                    for c in 0..columns {
+                        let text = row.get(c).map(String::as_str).unwrap_or_default();
+                        let run_props = if r == 0 { "<w:rPr><w:b/></w:rPr>" } else { "" };
+                        xml.push_str(&format!(
+                            "<w:tc><w:p><w:r>{}{}</w:r></w:p></w:tc>",
+                            run_props,
+                            text_run_content(text)
+                        ));
+                    }
+                    xml.push_str("</w:tr>\n");
+                }
+                xml.push_str("</w:tbl>\n");
+                // keeps a following table from merging into this one
+                xml.push_str("<w:p/>\n");
+                xml
+            }
+            Block::Diagram {
+                kind,
+                cached_path,
+                outline,
+            } => {
+                let mut xml = match self.image_run(cached_path, kind.name(), None)? {
+                    Some(run) => paragraph(&indent, &run),
+                    None => String::new(),
+                };
+                if let Some(root) = outline {
+                    let num_id = self.new_numbering(NUMBERING_BULLET, depth.min(8));
+                    xml.push_str(&self.outline_items(root, num_id, depth));
+                }
+                xml
+            }
+            Block::Callout { emoji, children } => {
+                // a shaded one cell table holds the callout blocks
+                let mut content = String::new();
+                if let Some(emoji) = emoji {
+                    content.push_str(&paragraph(
+                        "",
+                        &format!("<w:r>{}</w:r>", text_run_content(emoji)),
+                    ));
+                }
+                for child in children {
+                    content.push_str(&self.block(child, 0)?);
+                }
+                let table_indent = if depth > 0 {
+                    format!(
+                        "<w:tblInd w:w=\"{}\" w:type=\"dxa\"/>",
+                        LEVEL_INDENT * depth
+                    )
+                } else {
+                    String::new()
+                };
+                format!(
+                    "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"5000\" w:type=\"pct\"/>{}</w:tblPr><w:tblGrid><w:gridCol/></w:tblGrid><w:tr><w:tc><w:tcPr><w:shd w:val=\"clear\" w:color=\"auto\" w:fill=\"FFF5D6\"/></w:tcPr>{}</w:tc></w:tr></w:tbl>\n<w:p/>\n",
+                    table_indent,
+                    cell_content(content)
+                )
+            }
+        };
+        Ok(xml)
+    }
+
+    fn numbering_xml(&self) -> String {
+        let mut xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:numbering xmlns:w=\"{}\">\n",
+            NS_W
+        );
+        for id in [NUMBERING_BULLET, NUMBERING_DECIMAL, NUMBERING_TASK] {
+            xml.push_str(&abstract_numbering(id));
+            xml.push('\n');
+        }
+        for (index, instance) in self.numbering.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/><w:lvlOverride w:ilvl=\"{}\"><w:startOverride w:val=\"1\"/></w:lvlOverride></w:num>\n",
+                index + 1,
+                instance.abstract_id,
+                instance.level
+            ));
+        }
+        xml.push_str("</w:numbering>\n");
+        xml
+    }
+
+    fn document_relationships_xml(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\n<Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles\" Target=\"styles.xml\"/>\n<Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering\" Target=\"numbering.xml\"/>\n",
+        );
+        for rel in &self.relationships {
+            xml.push_str(&format!(
+                "<Relationship Id=\"{}\" Type=\"{}\" Target=\"{}\"{}/>\n",
+                rel.id,
+                rel.kind,
+                escape(&rel.target),
+                if rel.external {
+                    " TargetMode=\"External\""
+                } else {
+                    ""
+                }
+            ));
+        }
+        xml.push_str("</Relationships>\n");
+        xml
+    }
+}
+
+fn content_types_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\n<Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\n<Default Extension=\"xml\" ContentType=\"application/xml\"/>\n",
+    );
+    for (extension, content_type) in [
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("jpeg", "image/jpeg"),
+        ("gif", "image/gif"),
+        ("bmp", "image/bmp"),
+    ] {
+        xml.push_str(&format!(
+            "<Default Extension=\"{}\" ContentType=\"{}\"/>\n",
+            extension, content_type
+        ));
+    }
+    for (part, content_type) in [
+        (
+            "/word/document.xml",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml",
+        ),
+        (
+            "/word/styles.xml",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml",
+        ),
+        (
+            "/word/numbering.xml",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml",
+        ),
+        (
+            "/docProps/core.xml",
+            "application/vnd.openxmlformats-package.core-properties+xml",
+        ),
+    ] {
+        xml.push_str(&format!(
+            "<Override PartName=\"{}\" ContentType=\"{}\"/>\n",
+            part, content_type
+        ));
+    }
+    xml.push_str("</Types>\n");
+    xml
+}
+
+const PACKAGE_RELATIONSHIPS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties" Target="docProps/core.xml"/>
+</Relationships>
+"#;
+
+/// title, author and dates shown in word's document properties
+fn core_properties_xml(doc: &Document) -> String {
+    let mut props = String::new();
+    if let Some(title) = &doc.meta.title {
+        props.push_str(&format!("<dc:title>{}</dc:title>", escape(title)));
+    }
+    if let Some(author) = &doc.meta.author {
+        props.push_str(&format!("<dc:creator>{}</dc:creator>", escape(author)));
+    }
+    if let Some(url) = &doc.meta.source_url {
+        props.push_str(&format!("<dc:source>{}</dc:source>", escape(url)));
+    }
+    if let Some(created) = doc.meta.created {
+        props.push_str(&format!(
+            "<dcterms:created xsi:type=\"dcterms:W3CDTF\">{}</dcterms:created>",
+            created.format("%Y-%m-%dT%H:%M:%SZ")
+        ));
+    }
+    if let Some(modified) = doc.meta.modified {
+        props.push_str(&format!(
+            "<dcterms:modified xsi:type=\"dcterms:W3CDTF\">{}</dcterms:modified>",
+            modified.format("%Y-%m-%dT%H:%M:%SZ")
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:dcterms=\"http://purl.org/dc/terms/\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">{}</cp:coreProperties>\n",
+        props
+    )
+}
+
+/// Write the document as a Word file: headings use the built-in heading styles, lists are
+/// real word lists, images are embedded and links stay clickable
+pub fn export_document_to_docx(
+    doc: &Document,
+    docx_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = DocxWriter::default();
+    let mut body = String::new();
+    if let Some(title) = &doc.meta.title {
+        body.push_str(&paragraph(
+            "<w:pStyle w:val=\"Title\"/>",
+            &format!("<w:r>{}</w:r>", text_run_content(title)),
+        ));
+    }
+    for block in &doc.blocks {
+        body.push_str(&writer.block(block, 0)?);
+    }
+    let document_xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<w:document xmlns:w=\"{}\" xmlns:r=\"{}\" xmlns:wp=\"{}\">\n<w:body>\n{}<w:sectPr><w:pgSz w:w=\"11906\" w:h=\"16838\"/><w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" w:header=\"720\" w:footer=\"720\" w:gutter=\"0\"/></w:sectPr>\n</w:body>\n</w:document>\n",
+        NS_W, NS_R, NS_WP, body
+    );
+
+    if let Some(parent) = docx_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut zip = ZipWriter::new(File::create(docx_path)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let parts = [
+        ("[Content_Types].xml", content_types_xml()),
+        ("_rels/.rels", PACKAGE_RELATIONSHIPS_XML.to_string()),
+        ("docProps/core.xml", core_properties_xml(doc)),
+        ("word/document.xml", document_xml),
+        (
+            "word/styles.xml",
+            STYLES_XML.replace("HEADING_STYLES", &heading_styles()),
+        ),
+        ("word/numbering.xml", writer.numbering_xml()),
+        (
+            "word/_rels/document.xml.rels",
+            writer.document_relationships_xml(),
+        ),
+    ];
+    for (name, content) in parts {
+        zip.start_file(name, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    // images are compressed already
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, bytes) in &writer.media {
+        zip.start_file(format!("word/media/{}", name), stored)?;
+        zip.write_all(bytes)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
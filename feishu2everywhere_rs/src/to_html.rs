@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::comments::{Comment, CommentThread};
+use crate::document::Document;
+use crate::outline::OutlineNode;
+use crate::to_markdown::head_level_to_usize;
+use crate::xml::escape;
+
+/// gives the `src` of an image, eg. after copying it next to the page
+pub type ImageLinker<'a> = dyn FnMut(&Path) -> Result<String, Box<dyn std::error::Error>> + 'a;
+
+/// one heading of a page, for the table of contents
+#[derive(Debug, Clone)]
+pub struct Heading {
+    /// 1-10 as in the document, rendered as h6 past 6
+    pub level: usize,
+    pub id: String,
+    pub text: String,
+}
+
+/// The body of a page, xhtml so it can also go into epub
+#[derive(Debug, Clone, Default)]
+pub struct HtmlPage {
+    pub body: String,
+    pub headings: Vec<Heading>,
+}
+
+struct Renderer<'a, 'b> {
+    link_image: &'a mut ImageLinker<'b>,
+    headings: Vec<Heading>,
+    used_ids: HashMap<String, usize>,
+}
+
+/// anchor id from heading text, unique within the page
+fn heading_id(text: &str, used_ids: &mut HashMap<String, usize>) -> String {
+    let mut id = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            id.push(c);
+        } else if !id.is_empty() && !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+    let id = match id.trim_end_matches('-') {
+        "" => "section".to_string(),
+        id => id.to_string(),
+    };
+    let count = used_ids.entry(id.clone()).or_insert(0);
+    *count += 1;
+    if *count == 1 {
+        id
+    } else {
+        format!("{}-{}", id, count)
+    }
+}
+
+fn render_text_slices(slices: &[TextSlice]) -> String {
+    let mut html = String::new();
+    for slice in slices {
+        let mut current = escape(&slice.text).replace('\n', "<br />");
+        if slice.is_code {
+            current = format!("<code>{}</code>", current);
+        }
+        if slice.is_bold {
+            current = format!("<strong>{}</strong>", current);
+        }
+        if slice.is_underline {
+            current = format!("<u>{}</u>", current);
+        }
+        if let Some(link) = &slice.link {
+            current = format!("<a href=\"{}\">{}</a>", escape(link), current);
+        }
+        html.push_str(&current);
+    }
+    html
+}
+
+fn render_comment(comment: &Comment) -> String {
+    let mut html = format!("<strong>{}</strong>", escape(&comment.author));
+    if let Some(time) = &comment.time {
+        html.push_str(&format!(" <time>{}</time>", escape(time)));
+    }
+    html.push_str(&format!(
+        ": {}",
+        escape(comment.text.trim()).replace('\n', "<br />")
+    ));
+    html
+}
+
+/// a thread as a margin note, the css floats it next to its block
+fn render_thread(thread: &CommentThread) -> String {
+    let class = if thread.resolved {
+        "margin-note resolved"
+    } else {
+        "margin-note"
+    };
+    let mut html = format!("<aside class=\"{}\">\n", class);
+    if !thread.anchor.is_empty() {
+        html.push_str(&format!(
+            "<blockquote>{}</blockquote>\n",
+            escape(&thread.anchor)
+        ));
+    }
+    let mut comments = thread.comments.iter();
+    if let Some(first) = comments.next() {
+        html.push_str(&format!("<p>{}</p>\n", render_comment(first)));
+    }
+    let replies = comments
+        .map(|c| format!("<li>{}</li>\n", render_comment(c)))
+        .collect::<String>();
+    if !replies.is_empty() {
+        html.push_str(&format!("<ul>\n{}</ul>\n", replies));
+    }
+    if thread.resolved {
+        html.push_str("<p><em>resolved</em></p>\n");
+    }
+    html.push_str("</aside>\n");
+    html
+}
+
+fn render_outline(node: &OutlineNode) -> String {
+    let mut html = format!("<li>{}", escape(&node.text));
+    if !node.children.is_empty() {
+        html.push_str("\n<ul>\n");
+        for child in &node.children {
+            html.push_str(&render_outline(child));
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</li>\n");
+    html
+}
+
+impl Renderer<'_, '_> {
+    fn image(
+        &mut self,
+        path: &Path,
+        alt: &str,
+        width: Option<u32>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let src = (self.link_image)(path)?;
+        let width_attr = width
+            .map(|w| format!(" width=\"{}\"", w))
+            .unwrap_or_default();
+        Ok(format!(
+            "<img src=\"{}\" alt=\"{}\"{} />",
+            escape(&src),
+            escape(alt),
+            width_attr
+        ))
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let (open, close) = match list_type {
+            ListType::Ordered => ("<ol>", "</ol>"),
+            ListType::Unordered => ("<ul>", "</ul>"),
+            ListType::Task => ("<ul class=\"task-list\">", "</ul>"),
+        };
+        let mut html = format!("{}\n", open);
+        for item in items {
+            html.push_str("<li>");
+            if list_type == ListType::Task {
+                let checked = if item.done == Some(true) {
+                    " checked=\"checked\""
+                } else {
+                    ""
+                };
+                html.push_str(&format!(
+                    "<input type=\"checkbox\" disabled=\"disabled\"{} /> ",
+                    checked
+                ));
+            }
+            html.push_str(&render_text_slices(&item.headline));
+            for following in &item.following {
+                html.push('\n');
+                html.push_str(&self.block(following)?);
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str(close);
+        html.push('\n');
+        Ok(html)
+    }
+
+    fn block(&mut self, block: &Block) -> Result<String, Box<dyn std::error::Error>> {
+        let html = match block {
+            Block::Text(slices) => format!("<p>{}</p>\n", render_text_slices(slices)),
+            Block::Title { text, head_level } => {
+                let level = head_level_to_usize(head_level);
+                let id = heading_id(text, &mut self.used_ids);
+                self.headings.push(Heading {
+                    level,
+                    id: id.clone(),
+                    text: text.clone(),
+                });
+                // html stops at h6, deeper levels keep their level in the class
+                let tag = level.min(6);
+                let class = if level > 6 {
+                    format!(" class=\"h{}\"", level)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "<h{tag} id=\"{}\"{}>{}</h{tag}>\n",
+                    escape(&id),
+                    class,
+                    escape(text),
+                    tag = tag
+                )
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items)?,
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => {
+                let alt_text = alt.as_deref().or(caption.as_deref()).unwrap_or_default();
+                let class = match align {
+                    Some(ImageAlign::Left) => " class=\"align-left\"",
+                    Some(ImageAlign::Center) => " class=\"align-center\"",
+                    Some(ImageAlign::Right) => " class=\"align-right\"",
+                    None => "",
+                };
+                let mut html = format!(
+                    "<figure{}>\n{}\n",
+                    class,
+                    self.image(cached_path, alt_text, *width)?
+                );
+                if let Some(caption) = caption {
+                    html.push_str(&format!("<figcaption>{}</figcaption>\n", escape(caption)));
+                }
+                html.push_str("</figure>\n");
+                html
+            }
+            Block::Code { language, code } => {
+                let class = if language.is_empty() {
+                    String::new()
+                } else {
+                    format!(" class=\"language-{}\"", escape(&language.to_lowercase()))
+                };
+                format!("<pre><code{}>{}</code></pre>\n", class, escape(code))
+            }
+            Block::Sheet { cells } => {
+                let mut rows = cells.iter();
+                let mut html = String::from("<table>\n");
+                if let Some(header) = rows.next() {
+                    html.push_str("<thead>\n<tr>");
+                    for cell in header {
+                        html.push_str(&format!("<th>{}</th>", escape(cell)));
+                    }
+                    html.push_str("</tr>\n</thead>\n");
+                }
+                html.push_str("<tbody>\n");
+                for row in rows {
+                    html.push_str("<tr>");
+                    for cell in row {
+                        html.push_str(&format!(
+                            "<td>{}</td>",
+                            escape(cell).replace('\n', "<br />")
+                        ));
+                    }
+                    html.push_str("</tr>\n");
+                }
+                html.push_str("</tbody>\n</table>\n");
+                html
+            }
+            Block::Diagram {
+                kind,
+                cached_path,
+                outline,
+            } => {
+                let mut html = format!(
+                    "<figure class=\"diagram {}\">\n{}\n</figure>\n",
+                    kind.name(),
+                    self.image(cached_path, kind.name(), None)?
+                );
+                if let Some(root) = outline {
+                    html.push_str(&format!(
+                        "<ul class=\"outline\">\n{}</ul>\n",
+                        render_outline(root)
+                    ));
+                }
+                html
+            }
+            Block::Callout { emoji, children } => {
+                let mut html = String::from("<aside class=\"callout\">\n");
+                if let Some(emoji) = emoji {
+                    html.push_str(&format!(
+                        "<span class=\"callout-emoji\">{}</span>\n",
+                        escape(emoji)
+                    ));
+                }
+                for child in children {
+                    html.push_str(&self.block(child)?);
+                }
+                html.push_str("</aside>\n");
+                html
+            }
+        };
+        Ok(html)
+    }
+}
+
+/// Render the blocks of `doc`, comment threads become margin notes after their block
+/// and threads not attached to a block end up in a trailing discussion section
+pub fn render_document(
+    doc: &Document,
+    link_image: &mut ImageLinker,
+) -> Result<HtmlPage, Box<dyn std::error::Error>> {
+    let mut renderer = Renderer {
+        link_image,
+        headings: vec![],
+        used_ids: HashMap::new(),
+    };
+    let mut body = String::new();
+    for (index, block) in doc.blocks.iter().enumerate() {
+        let block_html = renderer.block(block)?;
+        let notes = doc
+            .comments
+            .iter()
+            .filter(|t| t.block_index == Some(index))
+            .map(render_thread)
+            .collect::<String>();
+        if notes.is_empty() {
+            body.push_str(&block_html);
+        } else {
+            body.push_str(&format!(
+                "<div class=\"with-notes\">\n{}{}</div>\n",
+                block_html, notes
+            ));
+        }
+    }
+
+    let unattached = doc
+        .comments
+        .iter()
+        .filter(|t| t.block_index.is_none())
+        .collect::<Vec<_>>();
+    if !unattached.is_empty() {
+        body.push_str("<section class=\"discussion\">\n<h2>Discussion</h2>\n");
+        for thread in unattached {
+            body.push_str(&render_thread(thread));
+        }
+        body.push_str("</section>\n");
+    }
+    Ok(HtmlPage {
+        body,
+        headings: renderer.headings,
+    })
+}
@@ -0,0 +1,578 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, HeadLevel, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+
+/// css pixels to points
+const PT_PER_PX: f64 = 0.75;
+/// text width of a4 with 2.5cm margins
+const TEXT_WIDTH_PT: f64 = 455.0;
+
+/// han, kana, hangul and their punctuation, which need a cjk font
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF
+        | 0x2E80..=0x2FDF
+        | 0x3000..=0x30FF
+        | 0x3130..=0x318F
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF
+        | 0x20000..=0x2FA1F)
+}
+
+/// Shown width of an image: its css width in points, None for the full text width
+pub fn image_width_pt(width: Option<u32>) -> Option<f64> {
+    width
+        .map(|w| w as f64 * PT_PER_PX)
+        .filter(|w| *w < TEXT_WIDTH_PT)
+}
+
+/// `\\` ending a line of a paragraph
+const LINE_BREAK: &str = "\\\\{}\n";
+
+/// escape text for latex, every character shows up literally
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '^' => out.push_str("\\textasciicircum{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            // would be ligatures or spanish punctuation in some fonts
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            // `{}` ends the break, a following `[` or `*` would be read as its argument
+            '\n' => out.push_str(LINE_BREAK),
+            c if c.is_control() && c != '\t' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `\href` wants `#`, `%` and `\` escaped, the rest of the url is taken as is
+fn escape_url(url: &str) -> String {
+    url.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace('%', "\\%")
+        .replace('{', "\\{")
+        .replace('}', "\\}")
+}
+
+/// languages `listings` knows, anything else is shown without highlighting
+fn listings_language(language: &str) -> Option<&'static str> {
+    Some(match language.to_lowercase().as_str() {
+        "c" => "C",
+        "c++" | "cpp" => "C++",
+        "c#" | "csharp" => "[Sharp]C",
+        "java" => "Java",
+        "python" | "py" => "Python",
+        "bash" | "shell" | "sh" | "zsh" => "bash",
+        "sql" => "SQL",
+        "html" => "HTML",
+        "xml" => "XML",
+        "ruby" => "Ruby",
+        "php" => "PHP",
+        "perl" => "Perl",
+        "haskell" => "Haskell",
+        "lua" => "Lua",
+        "matlab" => "Matlab",
+        "r" => "R",
+        "scala" => "Scala",
+        "makefile" | "make" => "make",
+        "latex" | "tex" => "[LaTeX]TeX",
+        "fortran" => "Fortran",
+        "pascal" => "Pascal",
+        "lisp" => "Lisp",
+        _ => return None,
+    })
+}
+
+fn section_command(level: &HeadLevel) -> &'static str {
+    match head_level_to_usize(level) {
+        1 => "section",
+        2 => "subsection",
+        3 => "subsubsection",
+        4 => "paragraph",
+        _ => "subparagraph",
+    }
+}
+
+struct LatexWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+    uses_svg: bool,
+}
+
+fn render_text_slices(slices: &[TextSlice]) -> String {
+    // a line break cannot start a paragraph, drop the ones at either end
+    let first = slices
+        .iter()
+        .position(|s| !s.text.trim_matches('\n').is_empty());
+    let last = slices
+        .iter()
+        .rposition(|s| !s.text.trim_matches('\n').is_empty());
+    let (Some(first), Some(last)) = (first, last) else {
+        return String::new();
+    };
+    let mut latex = String::new();
+    for (index, slice) in slices.iter().enumerate().take(last + 1).skip(first) {
+        let mut text = slice.text.as_str();
+        if index == first {
+            text = text.trim_start_matches('\n');
+        }
+        if index == last {
+            text = text.trim_end_matches('\n');
+        }
+        let mut current = escape(text);
+        if slice.is_code {
+            current = format!("\\texttt{{{}}}", current);
+        }
+        if slice.is_bold {
+            current = format!("\\textbf{{{}}}", current);
+        }
+        if slice.is_underline {
+            current = format!("\\uline{{{}}}", current);
+        }
+        if let Some(link) = &slice.link {
+            current = format!("\\href{{{}}}{{{}}}", escape_url(link), current);
+        }
+        latex.push_str(&current);
+    }
+    latex
+}
+
+fn render_outline(node: &OutlineNode) -> String {
+    // `{}` so a leading `[` is not read as the optional label
+    let mut latex = format!("\\item{{}} {}\n", escape(&node.text));
+    if !node.children.is_empty() {
+        latex.push_str("\\begin{itemize}\n");
+        for child in &node.children {
+            latex.push_str(&render_outline(child));
+        }
+        latex.push_str("\\end{itemize}\n");
+    }
+    latex
+}
+
+impl LatexWriter {
+    /// copy the image next to the source, formats graphicx cannot read become png
+    fn place_image(&mut self, cached_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+        let relative = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let extension = cached_path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "png" | "jpg" | "jpeg" | "pdf" => Ok(relative),
+            "svg" => {
+                self.uses_svg = true;
+                Ok(relative)
+            }
+            _ => {
+                let decoded = image::load_from_memory(&fs::read(cached_path)?)?;
+                let png_name = Path::new(&relative).with_extension("png");
+                let mut png = Cursor::new(vec![]);
+                decoded.write_to(&mut png, image::ImageFormat::Png)?;
+                fs::write(
+                    self.rsc_path.join(png_name.file_name().unwrap_or_default()),
+                    png.into_inner(),
+                )?;
+                Ok(png_name.to_string_lossy().replace('\\', "/"))
+            }
+        }
+    }
+
+    fn image(
+        &mut self,
+        cached_path: &Path,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+        caption: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let path = self.place_image(cached_path)?;
+        // small images keep their size, large ones fit the text width
+        let width = width.or_else(|| image::image_dimensions(cached_path).ok().map(|(w, _)| w));
+        let width = match image_width_pt(width) {
+            Some(pt) => format!("{:.0}pt", pt),
+            None => "\\linewidth".to_string(),
+        };
+        let command = if path.ends_with(".svg") {
+            "includesvg"
+        } else {
+            "includegraphics"
+        };
+        let environment = match align {
+            Some(ImageAlign::Left) => "flushleft",
+            Some(ImageAlign::Right) => "flushright",
+            _ => "center",
+        };
+        // not a float, the image stays where it is in the document, also inside lists
+        let mut latex = format!(
+            "\\begin{{{env}}}\n\\{}[width={}]{{{}}}\n",
+            command,
+            width,
+            path,
+            env = environment
+        );
+        if let Some(caption) = caption {
+            latex.push_str(&format!("\\captionof{{figure}}{{{}}}\n", escape(caption)));
+        }
+        latex.push_str(&format!("\\end{{{}}}\n\n", environment));
+        Ok(latex)
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let environment = match list_type {
+            ListType::Ordered => "enumerate",
+            ListType::Unordered | ListType::Task => "itemize",
+        };
+        let mut latex = format!("\\begin{{{}}}\n", environment);
+        for item in items {
+            let marker = match (list_type, item.done) {
+                (ListType::Task, Some(true)) => "[$\\boxtimes$]",
+                (ListType::Task, _) => "[$\\square$]",
+                // a headline starting with `[` would become the label
+                _ => "{}",
+            };
+            latex.push_str(&format!(
+                "\\item{} {}\n",
+                marker,
+                render_text_slices(&item.headline)
+            ));
+            for following in &item.following {
+                latex.push('\n');
+                latex.push_str(&self.block(following, true)?);
+            }
+        }
+        latex.push_str(&format!("\\end{{{}}}\n\n", environment));
+        Ok(latex)
+    }
+
+    fn table(&self, cells: &[Vec<String>], nested: bool) -> String {
+        let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let column = format!(
+            ">{{\\raggedright\\arraybackslash}}p{{\\dimexpr 0.95\\linewidth/{} - 2\\tabcolsep\\relax}}|",
+            columns
+        );
+        // longtable breaks across pages but cannot sit inside a list
+        let environment = if nested { "tabular" } else { "longtable" };
+        let mut latex = format!(
+            "\\begin{{{}}}{{|{}}}\n\\hline\n",
+            environment,
+            column.repeat(columns)
+        );
+        for (r, row) in cells.iter().enumerate() {
+            let cells = (0..columns)
+                .map(|c| {
+                    let text = escape(row.get(c).map(String::as_str).unwrap_or_default())
+                        .replace(LINE_BREAK, "\\newline{} ");
+                    if r == 0 {
+                        format!("\\textbf{{{}}}", text)
+                    } else {
+                        text
+                    }
+                })
+                .collect::<Vec<_>>();
+            latex.push_str(&format!("{} \\\\\n\\hline\n", cells.join(" & ")));
+            if r == 0 && !nested {
+                // the header row repeats on every page
+                latex.push_str("\\endhead\n");
+            }
+        }
+        latex.push_str(&format!("\\end{{{}}}\n\n", environment));
+        latex
+    }
+
+    fn block(&mut self, block: &Block, nested: bool) -> Result<String, Box<dyn std::error::Error>> {
+        let latex = match block {
+            Block::Text(slices) => {
+                let text = render_text_slices(slices);
+                if text.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", text)
+                }
+            }
+            Block::Title { text, head_level } => format!(
+                "\\{}{{{}}}\n\n",
+                section_command(head_level),
+                escape(text).replace(LINE_BREAK, " ")
+            ),
+            Block::List { list_type, items } => self.list_items(*list_type, items)?,
+            Block::Image {
+                cached_path,
+                caption,
+                width,
+                align,
+                ..
+            } => self.image(cached_path, *width, *align, caption.as_deref())?,
+            Block::Code { language, code } => {
+                let options = listings_language(language)
+                    .map(|l| format!("[language={{{}}}]", l))
+                    .unwrap_or_default();
+                format!(
+                    "\\begin{{lstlisting}}{}\n{}\n\\end{{lstlisting}}\n\n",
+                    options,
+                    code.trim_end_matches('\n')
+                )
+            }
+            Block::Sheet { cells } => self.table(cells, nested),
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut latex = self.image(cached_path, None, None, None)?;
+                if let Some(root) = outline {
+                    latex.push_str(&format!(
+                        "\\begin{{itemize}}\n{}\\end{{itemize}}\n\n",
+                        render_outline(root)
+                    ));
+                }
+                latex
+            }
+            Block::Callout { emoji, children } => {
+                let mut latex = String::from(
+                    "\\begin{tcolorbox}[colback=yellow!8,colframe=orange!60,boxrule=0.5pt,arc=2pt]\n",
+                );
+                if let Some(emoji) = emoji {
+                    latex.push_str(&format!("{}\n\n", escape(emoji)));
+                }
+                for child in children {
+                    latex.push_str(&self.block(child, true)?);
+                }
+                latex.push_str("\\end{tcolorbox}\n\n");
+                latex
+            }
+        };
+        Ok(latex)
+    }
+}
+
+fn preamble(doc: &Document, has_cjk: bool, uses_svg: bool) -> String {
+    let mut latex = String::from(
+        "% compile with xelatex (or lualatex), twice for the outline\n\\documentclass[11pt,a4paper]{article}\n",
+    );
+    if has_cjk {
+        // fandol fonts ship with every tex distribution
+        latex.push_str("\\usepackage[UTF8,scheme=plain,fontset=fandol]{ctex}\n");
+    } else {
+        latex.push_str("\\usepackage{fontspec}\n");
+    }
+    latex.push_str(
+        "\\usepackage[margin=2.5cm]{geometry}
+\\usepackage{graphicx}
+\\usepackage{caption}
+\\usepackage{longtable}
+\\usepackage{array}
+\\usepackage{amssymb}
+\\usepackage{enumitem}
+\\usepackage{listings}
+\\usepackage[normalem]{ulem}
+\\usepackage{xcolor}
+\\usepackage{tcolorbox}
+",
+    );
+    if uses_svg {
+        // converts through inkscape, run xelatex with --shell-escape
+        latex.push_str("\\usepackage{svg}\n");
+    }
+    latex.push_str(
+        "\\usepackage[hidelinks]{hyperref}
+% feishu lists nest deeper than the four levels latex allows
+\\setlistdepth{9}
+\\renewlist{itemize}{itemize}{9}
+\\setlist[itemize]{label=\\textbullet}
+\\renewlist{enumerate}{enumerate}{9}
+\\setlist[enumerate]{label=\\arabic*.}
+\\lstset{basicstyle=\\ttfamily\\small,breaklines=true,columns=fullflexible,frame=single,backgroundcolor=\\color{black!4}}
+% headings carry their own numbers in feishu
+\\setcounter{secnumdepth}{0}
+",
+    );
+    if let Some(title) = &doc.meta.title {
+        latex.push_str(&format!("\\title{{{}}}\n", escape(title)));
+        latex.push_str(&format!(
+            "\\author{{{}}}\n",
+            doc.meta.author.as_deref().map(escape).unwrap_or_default()
+        ));
+        latex.push_str(&format!(
+            "\\date{{{}}}\n",
+            doc.meta
+                .modified
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        ));
+    }
+    latex
+}
+
+/// Write the document as a latex source, images go to `<stem>.rsc` next to it
+pub fn export_document_to_latex(
+    doc: &Document,
+    tex_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = tex_path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = tex_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let mut writer = LatexWriter {
+        rsc_dir_name,
+        rsc_path,
+        uses_svg: false,
+    };
+    let mut body = String::new();
+    for block in &doc.blocks {
+        body.push_str(&writer.block(block, false)?);
+    }
+
+    let has_cjk = body
+        .chars()
+        .chain(doc.meta.title.iter().flat_map(|t| t.chars()))
+        .any(is_cjk);
+    let mut latex = preamble(doc, has_cjk, writer.uses_svg);
+    latex.push_str("\n\\begin{document}\n");
+    if doc.meta.title.is_some() {
+        latex.push_str("\\maketitle\n");
+    }
+    latex.push('\n');
+    latex.push_str(&body);
+    latex.push_str("\\end{document}\n");
+    fs::write(tex_path, latex)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::temp_dir;
+
+    fn slice(text: &str, is_bold: bool) -> TextSlice {
+        TextSlice {
+            text: text.to_string(),
+            is_bold,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        let slices = [
+            slice("\n", false),
+            slice("\nfirst\n", true),
+            slice("[1] second\n", false),
+            slice("\n", false),
+        ];
+        assert_eq!(
+            render_text_slices(&slices),
+            "\\textbf{first\\\\{}\n}[1] second"
+        );
+        assert_eq!(render_text_slices(&[slice("\n\n", false)]), "");
+
+        let mut writer = LatexWriter {
+            rsc_dir_name: String::new(),
+            rsc_path: PathBuf::new(),
+            uses_svg: false,
+        };
+        let items = [ListOne {
+            done: None,
+            headline: vec![slice("[WIP] draft", false)],
+            following: vec![],
+        }];
+        assert_eq!(
+            writer.list_items(ListType::Unordered, &items).unwrap(),
+            "\\begin{itemize}\n\\item{} [WIP] draft\n\\end{itemize}\n\n"
+        );
+    }
+
+    /// unbalanced groups or environments, a break with nothing before it
+    /// and a `[` taken as an argument, no tex engine is run
+    fn assert_balanced(tex: &str) {
+        let mut depth = 0i32;
+        let mut environments = vec![];
+        let mut chars = tex.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    let rest = &tex[index + 1..];
+                    if let Some(name) = rest.strip_prefix("begin{") {
+                        environments.push(&name[..name.find('}').unwrap()]);
+                    } else if let Some(name) = rest.strip_prefix("end{") {
+                        assert_eq!(environments.pop(), Some(&name[..name.find('}').unwrap()]));
+                    }
+                    // the escaped character is not markup
+                    if rest.starts_with(['{', '}', '\\']) {
+                        chars.next();
+                    }
+                }
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    assert!(depth >= 0, "unbalanced `}}` at {}", index);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(depth, 0);
+        assert!(environments.is_empty(), "{:?} not closed", environments);
+        for paragraph in tex.split("\n\n") {
+            assert!(!paragraph.trim_start().starts_with("\\\\"), "{}", paragraph);
+        }
+        for argument in ["\\\\\n[", "\\\\\n*", "\\item ["] {
+            assert!(
+                !tex.contains(argument),
+                "a break taking {:?} as argument",
+                argument
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_is_balanced() {
+        let doc: Document = serde_json::from_value(serde_json::json!({
+            "meta": { "title": "Breaks {and} 100%", "wiki_path": [] },
+            "blocks": [
+                { "text": [{ "text": "\nlead" }, { "text": "\n[2] next\n", "is_bold": true }] },
+                { "title": { "text": "two\nlines", "head_level": "h2" } },
+                { "list": { "list_type": "task", "items": [
+                    { "done": true, "headline": [{ "text": "done\n" }] },
+                ] } },
+                { "list": { "list_type": "unordered", "items": [
+                    { "headline": [{ "text": "[WIP] draft" }] },
+                ] } },
+                { "sheet": { "cells": [["a\nb", "c"], ["[x]\ny", "$_#"]] } },
+                { "callout": { "emoji": "💡", "children": [{ "text": [{ "text": "x\n*y" }] }] } },
+            ],
+            "comments": [],
+        }))
+        .unwrap();
+        let tex_path = temp_dir("latex").join("doc.tex");
+        export_document_to_latex(&doc, &tex_path).unwrap();
+        assert_balanced(&fs::read_to_string(&tex_path).unwrap());
+    }
+}
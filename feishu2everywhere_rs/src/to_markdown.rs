@@ -1,37 +1,248 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf}; // Added ErrorKind for more specific error handling
 
 // Import Block and related types from crate::block
-use crate::block::{Block, HeadLevel, ListOne, ListType, TextSlice};
+use crate::block::{Block, HeadLevel, ImageAlign, ListOne, ListType, TextSlice};
+use crate::comments::{Comment, CommentStyle, CommentThread};
+use crate::document::Document;
+use crate::front_matter::{self, FrontMatterFormat, FrontMatterKeys, MetaField};
+use crate::markdown_dialect::{
+    HeadingOverflow, ListBullet, MarkdownDialect, code_fence, code_span, escape_text,
+    link_destination,
+};
+use crate::{assets, feishu_api, obsidian, outline, sheet, xml};
+
+/// markdown conventions on top of the common syntax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MarkdownFlavour {
+    #[default]
+    Standard,
+    /// `[[links]]` between exported pages, `> [!note]` callouts, a shared attachments folder
+    /// and tags in the front matter
+    Obsidian,
+}
+
+/// Settings of the markdown output that are not part of the document
+#[derive(Debug, Clone)]
+pub struct MarkdownOptions {
+    pub dialect: MarkdownDialect,
+    /// spaces nested list content is indented by, see `RenderContext::nested_indent`
+    pub list_indent: usize,
+    pub list_bullet: ListBullet,
+    pub heading_overflow: HeadingOverflow,
+    pub front_matter: FrontMatterFormat,
+    pub front_matter_keys: FrontMatterKeys,
+    pub comments: CommentStyle,
+    pub flavour: MarkdownFlavour,
+    /// obsidian: folder for images and other resources, relative to the markdown file,
+    /// instead of `<stem>.rsc/`
+    pub attachments_dir: Option<PathBuf>,
+    /// obsidian: document token -> note name of the pages in the vault, links to them become `[[links]]`
+    pub page_links: HashMap<String, String>,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        Self {
+            dialect: MarkdownDialect::default(),
+            list_indent: 4,
+            list_bullet: ListBullet::default(),
+            heading_overflow: HeadingOverflow::default(),
+            front_matter: FrontMatterFormat::default(),
+            front_matter_keys: FrontMatterKeys::default(),
+            comments: CommentStyle::default(),
+            flavour: MarkdownFlavour::default(),
+            attachments_dir: None,
+            page_links: HashMap::new(),
+        }
+    }
+}
+
+/// Where the resources of one markdown file go and how they are linked
+struct RenderContext<'a> {
+    options: &'a MarkdownOptions,
+    /// link prefix of resources, relative to the markdown file
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+    /// Shared counter for generated resource file names
+    rsc_counter: u32,
+    /// put before generated resource names when the folder is shared by several pages
+    rsc_prefix: String,
+}
+
+impl RenderContext<'_> {
+    fn is_obsidian(&self) -> bool {
+        self.options.flavour == MarkdownFlavour::Obsidian
+    }
+
+    fn dialect(&self) -> MarkdownDialect {
+        self.options.dialect
+    }
+
+    /// columns content under a list marker `marker_width` wide is indented by.
+    /// commonmark reads less than the marker width as outside the item and
+    /// 4 or more past it as an indented code block, so list_indent is kept in between
+    fn nested_indent(&self, marker_width: usize) -> usize {
+        self.options
+            .list_indent
+            .clamp(marker_width, marker_width + 3)
+    }
+
+    /// file name of the next generated resource, eg. `3_sheet.csv`
+    fn next_resource_name(&mut self, suffix: &str) -> String {
+        self.rsc_counter += 1;
+        format!("{}{}_{}", self.rsc_prefix, self.rsc_counter, suffix)
+    }
+
+    /// link to a resource file, obsidian finds it by name anywhere in the vault
+    fn resource_link(&self, text: &str, file_name: &str) -> String {
+        if self.is_obsidian() {
+            return format!("[[{}|{}]]", file_name, text);
+        }
+        let relative_path = Path::new(&self.rsc_dir_name).join(file_name);
+        format!(
+            "[{}]({})",
+            text,
+            link_destination(&relative_path.to_string_lossy().replace("\\", "/"))
+        )
+    }
+
+    /// note name of an exported page the link points to
+    fn linked_page(&self, link: &str) -> Option<&String> {
+        if !self.is_obsidian() {
+            return None;
+        }
+        let (_, token) = feishu_api::parse_doc_url(link)?;
+        self.options.page_links.get(&token)
+    }
+}
 
 // Helper function to convert TextSlice vector to a Markdown string
-fn format_text_slices_to_markdown(slices: &[TextSlice]) -> String {
+fn format_text_slices_to_markdown(slices: &[TextSlice], ctx: &RenderContext) -> String {
     let mut result = String::new();
     for slice in slices {
-        let mut current_text = slice.text.clone();
+        let mut current_text = if slice.is_code {
+            code_span(&slice.text)
+        } else {
+            let at_line_start = result.is_empty() || result.ends_with('\n');
+            escape_text(&slice.text, ctx.dialect(), at_line_start)
+        };
         // Order of application might matter: link should probably wrap styled text.
         // For simplicity now: style, then link.
-        if slice.is_code {
-            current_text = format!("`{}`", current_text);
-        }
         if slice.is_bold {
             // If already code, bold might not render as expected in markdown, but let's keep it simple
             current_text = format!("**{}**", current_text);
         }
-        // Markdown doesn't have standard underline. Could use HTML <u> or omit.
-        // if slice.is_underline { current_text = format!("<u>{}</u>", current_text); }
+        // Markdown doesn't have standard underline, pandoc has a span class for it
+        if slice.is_underline && ctx.dialect() == MarkdownDialect::Pandoc {
+            current_text = format!("[{}]{{.underline}}", current_text);
+        }
 
-        if let Some(link_url) = &slice.link {
-            current_text = format!("[{}]({})", current_text, link_url);
+        if let Some(page) = slice.link.as_deref().and_then(|l| ctx.linked_page(l)) {
+            current_text = if slice.text.trim() == page.as_str() {
+                format!("[[{}]]", page)
+            } else {
+                format!("[[{}|{}]]", page, current_text)
+            };
+        } else if let Some(link_url) = &slice.link {
+            current_text = format!("[{}]({})", current_text, link_destination(link_url));
         }
         result.push_str(&current_text);
     }
     result
 }
 
+// Helper function to render sheet cells as a markdown table, first row is the header
+fn format_cells_to_markdown_table(
+    cells: &[Vec<String>],
+    indent: &str,
+    dialect: MarkdownDialect,
+) -> String {
+    let col_count = cells.iter().map(|row| row.len()).max().unwrap_or(0);
+    if col_count == 0 {
+        return String::new();
+    }
+    if dialect == MarkdownDialect::CommonMark {
+        return format_cells_to_html_table(cells, indent);
+    }
+    // pandoc pipe tables take no html, the lines are joined instead
+    let line_break = match dialect {
+        MarkdownDialect::Pandoc => " ",
+        dialect => dialect.html_break(),
+    };
+    // `|` would split the cell and a raw newline would end the table row
+    let escape_cell = |cell: &str| {
+        escape_text(cell, dialect, false)
+            .replace('|', "\\|")
+            .replace('\n', line_break)
+    };
+    let format_row = |row: &[String]| {
+        let mut line = format!("{}|", indent);
+        for col in 0..col_count {
+            let cell = row.get(col).map(|c| escape_cell(c)).unwrap_or_default();
+            line.push_str(&format!(" {} |", cell));
+        }
+        line.push('\n');
+        line
+    };
+
+    let mut table = format_row(&cells[0]);
+    table.push_str(&format!("{}|{}\n", indent, " --- |".repeat(col_count)));
+    for row in &cells[1..] {
+        table.push_str(&format_row(row));
+    }
+    table
+}
+
+// commonmark has no tables, raw html is part of the spec
+fn format_cells_to_html_table(cells: &[Vec<String>], indent: &str) -> String {
+    let mut table = format!("{}<table>\n", indent);
+    for (index, row) in cells.iter().enumerate() {
+        let tag = if index == 0 { "th" } else { "td" };
+        table.push_str(&format!("{}<tr>", indent));
+        for cell in row {
+            table.push_str(&format!(
+                "<{tag}>{}</{tag}>",
+                xml::escape(cell).replace('\n', "<br>"),
+                tag = tag
+            ));
+        }
+        table.push_str("</tr>\n");
+    }
+    table.push_str(&format!("{}</table>\n", indent));
+    table
+}
+
+// Helper function to put a stored image into the resource dir, returns the markdown relative path
+// Store file names are content hashes, so an image used N times is placed once
+pub fn copy_image_to_rsc(
+    source_image_path: &Path,
+    rsc_dir_name: &str,
+    rsc_path: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let image_file_name = source_image_path.file_name().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid image cached_path: {:?}", source_image_path),
+        )
+    })?;
+
+    if !source_image_path.exists() {
+        return Err(Box::new(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Source image not found: {:?}", source_image_path),
+        )));
+    }
+    assets::link_or_copy(source_image_path, &rsc_path.join(image_file_name))?;
+
+    let relative_image_path = Path::new(rsc_dir_name).join(image_file_name);
+    Ok(relative_image_path.to_string_lossy().replace("\\", "/"))
+}
+
 // Helper function to convert HeadLevel to a numeric level (usize)
-fn head_level_to_usize(level: &HeadLevel) -> usize {
+pub fn head_level_to_usize(level: &HeadLevel) -> usize {
     match level {
         HeadLevel::H1 => 1,
         HeadLevel::H2 => 2,
@@ -50,17 +261,20 @@ fn head_level_to_usize(level: &HeadLevel) -> usize {
 fn format_list_items_to_markdown(
     items: &[ListOne],
     list_type: &ListType,
-    indent_level: usize,
-    output_md_path: &Path,   // For image paths if lists contain images
-    rsc_dir_name: &str,      // For image paths
-    image_counter: &mut u32, // Shared image counter
-    rsc_path: &Path,         // For copying images
+    indent_columns: usize,
+    ctx: &mut RenderContext, // For image paths and generated resources
 ) -> Result<String, Box<dyn std::error::Error>> {
     let mut list_content = String::new();
-    let indent = "    ".repeat(indent_level); // 4 spaces for indentation
+    let indent = " ".repeat(indent_columns);
+    let bullet = ctx.options.list_bullet.char();
 
     for (index, item) in items.iter().enumerate() {
-        let headline_md = format_text_slices_to_markdown(&item.headline);
+        let headline_md = format_text_slices_to_markdown(&item.headline, ctx);
+        // `1. ` or `- `, the checkbox of a task belongs to the content
+        let marker_width = match list_type {
+            ListType::Ordered => format!("{}. ", index + 1).len(),
+            _ => 2,
+        };
         match list_type {
             ListType::Ordered => {
                 list_content.push_str(&format!(
@@ -73,9 +287,9 @@ fn format_list_items_to_markdown(
             }
             ListType::Unordered => {
                 list_content.push_str(&format!(
-                    "{}- {}
+                    "{}{} {}
 ",
-                    indent, headline_md
+                    indent, bullet, headline_md
                 ));
             }
             ListType::Task => {
@@ -85,9 +299,9 @@ fn format_list_items_to_markdown(
                     "[ ]"
                 };
                 list_content.push_str(&format!(
-                    "{}- {} {}
+                    "{}{} {} {}
 ",
-                    indent, checkbox, headline_md
+                    indent, bullet, checkbox, headline_md
                 ));
             }
         }
@@ -96,14 +310,10 @@ fn format_list_items_to_markdown(
         if !item.following.is_empty() {
             let mut nested_block_content = String::new();
             for sub_block in &item.following {
-                // Pass image_counter by mutable reference
                 nested_block_content.push_str(&process_block_to_markdown(
                     sub_block,
-                    output_md_path,
-                    rsc_dir_name,
-                    image_counter, // Pass as mutable
-                    rsc_path,
-                    indent_level + 1, // Increase indent for nested blocks
+                    ctx,
+                    indent_columns + ctx.nested_indent(marker_width),
                 )?);
             }
             list_content.push_str(&nested_block_content);
@@ -115,18 +325,15 @@ fn format_list_items_to_markdown(
 // Main processing function for a single block (can be called recursively by lists)
 fn process_block_to_markdown(
     block: &Block,
-    output_md_path: &Path,   // For context if needed by sub-processors
-    rsc_dir_name: &str,      // For image relative paths
-    image_counter: &mut u32, // Needs to be mutable
-    rsc_path: &Path,         // For copying images
-    indent_level: usize,     // For lists
+    ctx: &mut RenderContext,
+    indent_columns: usize, // For lists
 ) -> Result<String, Box<dyn std::error::Error>> {
     let mut block_md = String::new();
-    let current_indent = "    ".repeat(indent_level);
+    let current_indent = " ".repeat(indent_columns);
 
     match block {
         Block::Text(text_slices) => {
-            let formatted_text = format_text_slices_to_markdown(text_slices);
+            let formatted_text = format_text_slices_to_markdown(text_slices, ctx);
             if !formatted_text.is_empty() {
                 // Avoid extra newlines for empty text blocks
                 block_md.push_str(&current_indent);
@@ -139,102 +346,334 @@ fn process_block_to_markdown(
             }
         }
         Block::Title { text, head_level } => {
-            let level = head_level_to_usize(head_level).min(6); // Cap at H6 for common markdown
+            let level = head_level_to_usize(head_level);
+            let escaped = escape_text(text, ctx.dialect(), false);
             block_md.push_str(&current_indent);
-            block_md.push_str(&format!(
-                "{} {}
-
-",
-                "#".repeat(level),
-                text
-            ));
-        }
-        Block::Image { cached_path } => {
-            // Use cached_path as the source, derive alt_text from filename
-            let source_image_path = cached_path;
-            let image_file_name = source_image_path.file_name().ok_or_else(|| {
-                io::Error::new(
-                    ErrorKind::InvalidInput,
-                    format!("Invalid image cached_path: {:?}", source_image_path),
-                )
-            })?;
-
-            let alt_text = image_file_name.to_string_lossy().into_owned();
-
-            *image_counter += 1;
-            let new_image_file_name =
-                format!("{}_{}", *image_counter, image_file_name.to_string_lossy());
-            let dest_image_path = rsc_path.join(&new_image_file_name);
-
-            if !source_image_path.exists() {
-                return Err(Box::new(io::Error::new(
-                    ErrorKind::NotFound,
-                    format!("Source image not found: {:?}", source_image_path),
-                )));
+            match ctx.options.heading_overflow {
+                // markdown stops at H6
+                HeadingOverflow::Bold if level > 6 => {
+                    block_md.push_str(&format!("**{}**\n\n", escaped));
+                }
+                HeadingOverflow::Paragraph if level > 6 => {
+                    // a paragraph starts the line, block markers need escaping as well
+                    let escaped = escape_text(text, ctx.dialect(), true);
+                    block_md.push_str(&format!("{}\n\n", escaped));
+                }
+                _ => {
+                    block_md.push_str(&format!("{} {}\n\n", "#".repeat(level.min(6)), escaped));
+                }
             }
-            fs::copy(source_image_path, &dest_image_path)?;
-
-            let relative_image_path = Path::new(rsc_dir_name).join(new_image_file_name);
-            block_md.push_str(&current_indent);
-            block_md.push_str(&format!(
-                "![{}]({})
-
-",
-                alt_text, // Use derived alt_text
-                relative_image_path.to_string_lossy().replace("\\", "/")
-            ));
         }
-        Block::Code { language, code } => {
-            block_md.push_str(&current_indent);
-            if language.is_empty() {
-                block_md.push_str(&format!(
-                    "```
-{}
-```
+        Block::Image {
+            cached_path,
+            alt,
+            caption,
+            width,
+            align,
+        } => {
+            let relative_image_path =
+                copy_image_to_rsc(cached_path, &ctx.rsc_dir_name, &ctx.rsc_path)?;
+            // a caption describes the image well enough when there is no explicit alt
+            let alt_text = alt.as_deref().or(caption.as_deref()).unwrap_or_default();
+            let md_caption = caption
+                .as_deref()
+                .map(|c| escape_text(c, ctx.dialect(), false));
 
-",
-                    code
+            let keeps_layout =
+                width.is_some() || matches!(align, Some(ImageAlign::Center | ImageAlign::Right));
+            if ctx.is_obsidian() {
+                // obsidian embeds by file name, a width goes after the pipe, alignment is lost
+                let file_name = relative_image_path.rsplit('/').next().unwrap_or_default();
+                let size = width.map(|w| format!("|{}", w)).unwrap_or_default();
+                block_md.push_str(&format!("{}![[{}{}]]\n\n", current_indent, file_name, size));
+                if let Some(caption) = md_caption {
+                    block_md.push_str(&format!("{}*{}*\n\n", current_indent, caption));
+                }
+            } else if keeps_layout {
+                // markdown images have no size or alignment, fall back to html
+                let align_attr = match align {
+                    Some(ImageAlign::Center) => " align=\"center\"",
+                    Some(ImageAlign::Right) => " align=\"right\"",
+                    _ => "",
+                };
+                let width_attr = width
+                    .map(|w| format!(" width=\"{}\"", w))
+                    .unwrap_or_default();
+                block_md.push_str(&format!("{}<figure{}>\n", current_indent, align_attr));
+                // jsx wants void elements closed
+                let img_end = if ctx.dialect() == MarkdownDialect::Mdx {
+                    " />"
+                } else {
+                    ">"
+                };
+                block_md.push_str(&format!(
+                    "{}<img src=\"{}\" alt=\"{}\"{}{}\n",
+                    current_indent,
+                    xml::escape(&relative_image_path),
+                    xml::escape(alt_text),
+                    width_attr,
+                    img_end
                 ));
+                if let Some(caption) = caption {
+                    let mut caption = xml::escape(caption);
+                    if ctx.dialect() == MarkdownDialect::Mdx {
+                        // braces in jsx text open an expression
+                        caption = caption.replace('{', "&#123;").replace('}', "&#125;");
+                    }
+                    block_md.push_str(&format!(
+                        "{}<figcaption>{}</figcaption>\n",
+                        current_indent, caption
+                    ));
+                }
+                block_md.push_str(&format!("{}</figure>\n\n", current_indent));
             } else {
+                block_md.push_str(&current_indent);
                 block_md.push_str(&format!(
-                    "```{}
-{}
-```
-
-",
-                    language, code
+                    "![{}]({})\n\n",
+                    escape_text(alt_text, ctx.dialect(), false),
+                    link_destination(&relative_image_path)
                 ));
+                if let Some(caption) = md_caption {
+                    block_md.push_str(&format!("{}*{}*\n\n", current_indent, caption));
+                }
             }
         }
+        Block::Code { language, code } => {
+            // the fence outgrows any backtick run in the code
+            let fence = code_fence(code);
+            block_md.push_str(&format!("{}{}{}\n", current_indent, fence, language));
+            for line in code.lines() {
+                if !line.is_empty() {
+                    block_md.push_str(&current_indent);
+                }
+                block_md.push_str(line);
+                block_md.push('\n');
+            }
+            block_md.push_str(&format!("{}{}\n\n", current_indent, fence));
+        }
         Block::List { list_type, items } => {
-            // initial call for a list block, indent_level passed to format_list_items_to_markdown
+            // initial call for a list block, indent_columns passed to format_list_items_to_markdown
             // should ensure the list content itself is not double-indented if process_block_to_markdown adds one.
             // The format_list_items_to_markdown handles indentation for its items.
             let list_md = format_list_items_to_markdown(
                 items,
                 list_type,
-                indent_level, // Pass current indent level for items
-                output_md_path,
-                rsc_dir_name,
-                image_counter, // Pass mutable reference
-                rsc_path,
+                indent_columns, // Pass current indent for items
+                ctx,
             )?;
             block_md.push_str(&list_md); // format_list_items_to_markdown already adds its own newlines as needed.
 
             // Add an extra newline after the whole list if it's a top-level block and the list itself is not empty.
-            if indent_level == 0 && !list_md.is_empty() {
+            if indent_columns == 0 && !list_md.is_empty() {
                 // only add extra \n\n if it's not already nested
                 block_md.push_str("\n");
             }
         }
+        Block::Sheet { cells } => {
+            if cells.is_empty() {
+                return Ok(block_md);
+            }
+            // full data goes to a sidecar csv, the table is for reading inline
+            let csv_file_name = ctx.next_resource_name("sheet.csv");
+            fs::write(ctx.rsc_path.join(&csv_file_name), sheet::rows_to_csv(cells))?;
+
+            block_md.push_str(&format_cells_to_markdown_table(
+                cells,
+                &current_indent,
+                ctx.dialect(),
+            ));
+            block_md.push('\n');
+            block_md.push_str(&current_indent);
+            block_md.push_str(&format!("{}\n\n", ctx.resource_link("csv", &csv_file_name)));
+        }
+        Block::Diagram {
+            kind,
+            cached_path,
+            outline,
+        } => {
+            let relative_image_path =
+                copy_image_to_rsc(cached_path, &ctx.rsc_dir_name, &ctx.rsc_path)?;
+            block_md.push_str(&current_indent);
+            if ctx.is_obsidian() {
+                let file_name = relative_image_path.rsplit('/').next().unwrap_or_default();
+                block_md.push_str(&format!("![[{}]]\n\n", file_name));
+            } else {
+                block_md.push_str(&format!(
+                    "![{}]({})\n\n",
+                    kind.name(),
+                    link_destination(&relative_image_path)
+                ));
+            }
+
+            // mind map source: opml sidecar for outliners, nested list for readers
+            if let Some(outline) = outline {
+                let opml_file_name = ctx.next_resource_name(&format!("{}.opml", kind.name()));
+                fs::write(
+                    ctx.rsc_path.join(&opml_file_name),
+                    outline::to_opml(outline),
+                )?;
+                block_md.push_str(&current_indent);
+                block_md.push_str(&format!(
+                    "{}\n\n",
+                    ctx.resource_link("opml", &opml_file_name)
+                ));
+                block_md.push_str(&process_block_to_markdown(
+                    &outline.to_list_block(),
+                    ctx,
+                    indent_columns,
+                )?);
+            }
+        }
+        Block::Callout { emoji, children } => {
+            let mut inner = String::new();
+            for child in children {
+                inner.push_str(&process_block_to_markdown(child, ctx, 0)?);
+            }
+            let mut lines = inner
+                .trim_end()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            if ctx.is_obsidian() {
+                let mut head = format!("[!{}]", obsidian::callout_type(emoji.as_deref()));
+                if let Some(emoji) = emoji {
+                    head.push_str(&format!(" {}", emoji));
+                }
+                lines.insert(0, head);
+            } else if let Some(emoji) = emoji {
+                match lines.first_mut() {
+                    Some(first) => first.insert_str(0, &format!("{} ", emoji)),
+                    None => lines.push(emoji.clone()),
+                }
+            }
+            // every line of the content is quoted, blank lines included to keep one box
+            for line in lines {
+                block_md.push_str(&current_indent);
+                if line.is_empty() {
+                    block_md.push_str(">\n");
+                } else {
+                    block_md.push_str(&format!("> {}\n", line));
+                }
+            }
+            block_md.push('\n');
+        }
     }
     Ok(block_md)
 }
 
+/// export with the document meta rendered as front matter according to `options`
+pub fn export_document_to_markdown(
+    doc: &Document,
+    output_md_path_str: &str,
+    options: &MarkdownOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let front_matter = match options.flavour {
+        MarkdownFlavour::Standard => {
+            front_matter::render(&doc.meta, options.front_matter, &options.front_matter_keys)
+        }
+        // obsidian only reads yaml and wants the tags
+        MarkdownFlavour::Obsidian => front_matter::render(
+            &doc.meta,
+            FrontMatterFormat::Yaml,
+            &options
+                .front_matter_keys
+                .clone()
+                .with_field(MetaField::Tags),
+        ),
+    };
+    write_markdown(
+        &doc.blocks,
+        output_md_path_str,
+        front_matter,
+        &doc.comments,
+        options,
+    )
+}
+
 pub fn export_blocks_to_markdown(
     blocks: &[Block],
     output_md_path_str: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    write_markdown(
+        blocks,
+        output_md_path_str,
+        String::new(),
+        &[],
+        &MarkdownOptions::default(),
+    )
+}
+
+fn format_comment(comment: &Comment, dialect: MarkdownDialect) -> String {
+    let mut line = format!("**{}**", escape_text(&comment.author, dialect, false));
+    if let Some(time) = &comment.time {
+        line.push_str(&format!(" ({})", time));
+    }
+    // keep each comment on one line so it can sit in a list or footnote
+    line.push_str(&format!(
+        ": {}",
+        escape_text(comment.text.trim(), dialect, false).replace('\n', dialect.html_break())
+    ));
+    line
+}
+
+/// paragraphs of a thread: quoted anchor, opening comment, replies as a list, state
+fn format_comment_thread(thread: &CommentThread, dialect: MarkdownDialect) -> Vec<String> {
+    let mut paragraphs = vec![];
+    if !thread.anchor.is_empty() {
+        paragraphs.push(format!(
+            "> {}",
+            escape_text(&thread.anchor.replace('\n', " "), dialect, true)
+        ));
+    }
+    let mut comments = thread.comments.iter();
+    if let Some(first) = comments.next() {
+        paragraphs.push(format_comment(first, dialect));
+    }
+    let replies = comments
+        .map(|c| format!("- {}", format_comment(c, dialect)))
+        .collect::<Vec<_>>();
+    if !replies.is_empty() {
+        paragraphs.push(replies.join("\n"));
+    }
+    if thread.resolved {
+        paragraphs.push("*resolved*".to_string());
+    }
+    paragraphs
+}
+
+/// footnote definition, paragraphs after the first are indented to stay inside it
+fn format_comment_footnote(
+    label: &str,
+    thread: &CommentThread,
+    dialect: MarkdownDialect,
+) -> String {
+    let body = format_comment_thread(thread, dialect)
+        .join("\n\n")
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("    {}", line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("[^{}]: {}\n\n", label, body.trim_start())
+}
+
+fn write_markdown(
+    blocks: &[Block],
+    output_md_path_str: &str,
+    front_matter: String,
+    comments: &[CommentThread],
+    options: &MarkdownOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // commonmark has no footnotes, its threads go to the discussion section
+    let comment_style = match (options.comments, options.dialect) {
+        (CommentStyle::Footnotes, MarkdownDialect::CommonMark) => CommentStyle::Discussion,
+        (style, _) => style,
+    };
     // 1. Validate output path and get PathBuf
     if !output_md_path_str.ends_with(".md") {
         return Err(Box::new(io::Error::new(
@@ -251,29 +690,83 @@ pub fn export_blocks_to_markdown(
         .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Output path has no file stem"))?
         .to_string_lossy();
 
-    let rsc_dir_name = format!("{}.rsc", file_stem);
+    // obsidian vaults keep every page's resources in one attachments folder
+    let (rsc_dir_name, rsc_prefix) = match (&options.attachments_dir, options.flavour) {
+        (Some(dir), MarkdownFlavour::Obsidian) => (
+            dir.to_string_lossy().replace("\\", "/"),
+            format!("{}_", file_stem),
+        ),
+        _ => (format!("{}.rsc", file_stem), String::new()),
+    };
     let rsc_path = parent_dir.join(&rsc_dir_name);
 
     fs::create_dir_all(&rsc_path)?;
 
     // 3. Process blocks and build markdown content
-    let mut markdown_content = String::new();
-    let mut image_counter: u32 = 0; // Ensure type is explicit if it matters for the helper
+    let mut markdown_content = front_matter;
+    let mut ctx = RenderContext {
+        options,
+        rsc_dir_name,
+        rsc_path,
+        rsc_counter: 0,
+        rsc_prefix,
+    };
 
-    for block in blocks {
+    // footnote label of each thread attached to a block
+    let footnote_labels = comments
+        .iter()
+        .enumerate()
+        .map(|(i, thread)| {
+            (comment_style == CommentStyle::Footnotes && thread.block_index.is_some())
+                .then(|| format!("c{}", i + 1))
+        })
+        .collect::<Vec<_>>();
+
+    for (index, block) in blocks.iter().enumerate() {
         // Use the helper function to process each block
-        // The initial indent_level for top-level blocks is 0.
-        let block_md = process_block_to_markdown(
-            block,
-            &output_md_path,    // Pass as reference
-            &rsc_dir_name,      // Pass as reference
-            &mut image_counter, // Pass as mutable reference
-            &rsc_path,          // Pass as reference
-            0,                  // Initial indent level for top-level blocks
+        // The initial indent_columns for top-level blocks is 0.
+        let mut block_md = process_block_to_markdown(
+            block, &mut ctx, 0, // Initial indent level for top-level blocks
         )?;
+        let refs = comments
+            .iter()
+            .zip(&footnote_labels)
+            .filter(|(thread, _)| thread.block_index == Some(index))
+            .filter_map(|(_, label)| label.as_ref())
+            .map(|label| format!("[^{}]", label))
+            .collect::<String>();
+        if !refs.is_empty() {
+            if matches!(block, Block::Text(_) | Block::Title { .. }) {
+                // reference right after the text, before the trailing blank line
+                let body_len = block_md.trim_end_matches('\n').len();
+                block_md.insert_str(body_len, &refs);
+            } else {
+                block_md.push_str(&format!("{}\n\n", refs));
+            }
+        }
         markdown_content.push_str(&block_md);
     }
 
+    if comment_style != CommentStyle::None {
+        for (thread, label) in comments.iter().zip(&footnote_labels) {
+            if let Some(label) = label {
+                markdown_content.push_str(&format_comment_footnote(label, thread, options.dialect));
+            }
+        }
+        // threads without a footnote: discussion style, or anchors not found in any block
+        let discussion = comments
+            .iter()
+            .zip(&footnote_labels)
+            .filter(|(_, label)| label.is_none())
+            .map(|(thread, _)| format_comment_thread(thread, options.dialect).join("\n\n"))
+            .collect::<Vec<_>>();
+        if !discussion.is_empty() {
+            markdown_content.push_str("## Discussion\n\n");
+            markdown_content.push_str(&discussion.join("\n\n---\n\n"));
+            markdown_content.push('\n');
+        }
+    }
+
     // 4. Write the markdown content to the output file
     fs::write(&output_md_path, markdown_content)?;
 
@@ -634,3 +1127,83 @@ pub fn export_blocks_to_markdown(
 //         Ok(())
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_http::temp_dir;
+
+    fn render(block: &Block, options: &MarkdownOptions, rsc_path: PathBuf) -> String {
+        fs::create_dir_all(&rsc_path).unwrap();
+        let mut ctx = RenderContext {
+            options,
+            rsc_dir_name: "doc.rsc".to_string(),
+            rsc_path,
+            rsc_counter: 0,
+            rsc_prefix: String::new(),
+        };
+        process_block_to_markdown(block, &mut ctx, 0).unwrap()
+    }
+
+    fn item(text: &str, following: Vec<Block>) -> ListOne {
+        ListOne::new(
+            vec![TextSlice {
+                text: text.to_string(),
+                ..Default::default()
+            }],
+            None,
+            following,
+        )
+    }
+
+    fn list(list_type: ListType, items: Vec<ListOne>) -> Block {
+        Block::List { list_type, items }
+    }
+
+    #[test]
+    fn test_list_indent_stays_inside_the_parent_item() {
+        let nested = list(
+            ListType::Ordered,
+            vec![item(
+                "one",
+                vec![list(ListType::Unordered, vec![item("inner", vec![])])],
+            )],
+        );
+        let outer = list(ListType::Unordered, vec![item("top", vec![nested])]);
+        let rendered = |list_indent| {
+            let options = MarkdownOptions {
+                list_indent,
+                ..Default::default()
+            };
+            render(&outer, &options, temp_dir("list-indent"))
+        };
+        // `- ` is 2 wide, `1. ` is 3 wide
+        assert_eq!(rendered(2), "- top\n  1. one\n     - inner\n\n");
+        assert_eq!(rendered(4), "- top\n    1. one\n        - inner\n\n");
+        assert_eq!(rendered(8), "- top\n     1. one\n           - inner\n\n");
+    }
+
+    #[test]
+    fn test_mdx_figcaption_escapes_braces() {
+        let dir = temp_dir("mdx-figcaption");
+        let image_path = dir.join("a.png");
+        fs::write(&image_path, b"png").unwrap();
+        let image = Block::Image {
+            cached_path: image_path,
+            alt: None,
+            caption: Some("f(x) = {x}".to_string()),
+            width: Some(200),
+            align: None,
+        };
+        let options = MarkdownOptions {
+            dialect: MarkdownDialect::Mdx,
+            ..Default::default()
+        };
+        let md = render(&image, &options, dir.join("doc.rsc"));
+        assert!(
+            md.contains("<figcaption>f(x) = &#123;x&#125;</figcaption>"),
+            "{}",
+            md
+        );
+    }
+}
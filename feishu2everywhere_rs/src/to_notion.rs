@@ -0,0 +1,477 @@
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::block::{Block, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+
+/// notion rejects longer text contents
+const MAX_TEXT_LENGTH: usize = 2000;
+/// and rich text arrays with more items
+const MAX_RICH_TEXT_ITEMS: usize = 100;
+
+/// languages the code block accepts, anything else is plain text
+const CODE_LANGUAGES: [&str; 88] = [
+    "abap",
+    "agda",
+    "arduino",
+    "ascii art",
+    "assembly",
+    "bash",
+    "basic",
+    "bnf",
+    "c",
+    "c#",
+    "c++",
+    "clojure",
+    "coffeescript",
+    "coq",
+    "css",
+    "dart",
+    "dhall",
+    "diff",
+    "docker",
+    "ebnf",
+    "elixir",
+    "elm",
+    "erlang",
+    "f#",
+    "flow",
+    "fortran",
+    "gherkin",
+    "glsl",
+    "go",
+    "graphql",
+    "groovy",
+    "haskell",
+    "hcl",
+    "html",
+    "idris",
+    "java",
+    "javascript",
+    "json",
+    "julia",
+    "kotlin",
+    "latex",
+    "less",
+    "lisp",
+    "livescript",
+    "llvm ir",
+    "lua",
+    "makefile",
+    "markdown",
+    "markup",
+    "matlab",
+    "mathematica",
+    "mermaid",
+    "nix",
+    "notion formula",
+    "objective-c",
+    "ocaml",
+    "pascal",
+    "perl",
+    "php",
+    "plain text",
+    "powershell",
+    "prolog",
+    "protobuf",
+    "purescript",
+    "python",
+    "r",
+    "racket",
+    "reason",
+    "ruby",
+    "rust",
+    "sass",
+    "scala",
+    "scheme",
+    "scss",
+    "shell",
+    "smalltalk",
+    "solidity",
+    "sql",
+    "swift",
+    "toml",
+    "typescript",
+    "vb.net",
+    "verilog",
+    "vhdl",
+    "visual basic",
+    "webassembly",
+    "xml",
+    "yaml",
+];
+
+/// gives the file object of an image: an external url, or a file upload when publishing.
+/// Images without one are left as their file name.
+pub type ImageSource<'a> = dyn FnMut(&Path) -> Option<Value> + 'a;
+
+fn code_language(language: &str) -> &'static str {
+    let language = match language.to_lowercase().as_str() {
+        "cpp" => "c++".to_string(),
+        "csharp" => "c#".to_string(),
+        "golang" => "go".to_string(),
+        "js" => "javascript".to_string(),
+        "ts" => "typescript".to_string(),
+        "sh" | "zsh" => "shell".to_string(),
+        "yml" => "yaml".to_string(),
+        "dockerfile" => "docker".to_string(),
+        "objc" | "objectivec" => "objective-c".to_string(),
+        "plaintext" => "plain text".to_string(),
+        other => other.to_string(),
+    };
+    CODE_LANGUAGES
+        .iter()
+        .find(|l| **l == language)
+        .copied()
+        .unwrap_or("plain text")
+}
+
+fn text_item(content: &str, link: Option<&str>, bold: bool, underline: bool, code: bool) -> Value {
+    json!({
+        "type": "text",
+        "text": {
+            "content": content,
+            "link": link.map(|url| json!({ "url": url })),
+        },
+        "annotations": {
+            "bold": bold,
+            "italic": false,
+            "strikethrough": false,
+            "underline": underline,
+            "code": code,
+            "color": "default",
+        },
+    })
+}
+
+/// one text item per slice, slices longer than notion allows are split
+fn rich_text(slices: &[TextSlice]) -> Vec<Value> {
+    let mut items = vec![];
+    for slice in slices {
+        let chars = slice.text.chars().collect::<Vec<_>>();
+        for chunk in chars.chunks(MAX_TEXT_LENGTH) {
+            items.push(text_item(
+                &chunk.iter().collect::<String>(),
+                slice.link.as_deref(),
+                slice.is_bold,
+                slice.is_underline,
+                slice.is_code,
+            ));
+        }
+    }
+    items
+}
+
+fn plain_rich_text(text: &str) -> Vec<Value> {
+    rich_text(&[TextSlice {
+        text: text.to_string(),
+        ..Default::default()
+    }])
+}
+
+fn block(kind: &str, content: Value) -> Value {
+    json!({ "object": "block", "type": kind, kind: content })
+}
+
+/// rich text of a block, capped at the item count notion takes
+fn capped(mut items: Vec<Value>) -> Vec<Value> {
+    items.truncate(MAX_RICH_TEXT_ITEMS);
+    items
+}
+
+fn outline_items(node: &OutlineNode) -> Value {
+    let mut content = json!({ "rich_text": plain_rich_text(&node.text) });
+    if !node.children.is_empty() {
+        content["children"] = node.children.iter().map(outline_items).collect();
+    }
+    block("bulleted_list_item", content)
+}
+
+fn list_items(
+    list_type: ListType,
+    items: &[ListOne],
+    image_source: &mut ImageSource,
+) -> Vec<Value> {
+    items
+        .iter()
+        .map(|item| {
+            let mut content = json!({ "rich_text": capped(rich_text(&item.headline)) });
+            let kind = match list_type {
+                ListType::Ordered => "numbered_list_item",
+                ListType::Unordered => "bulleted_list_item",
+                ListType::Task => {
+                    content["checked"] = json!(item.done == Some(true));
+                    "to_do"
+                }
+            };
+            let children = item
+                .following
+                .iter()
+                .flat_map(|b| blocks(b, image_source))
+                .collect::<Vec<_>>();
+            if !children.is_empty() {
+                content["children"] = json!(children);
+            }
+            block(kind, content)
+        })
+        .collect()
+}
+
+fn image_block(path: &Path, caption: Option<&str>, image_source: &mut ImageSource) -> Value {
+    let Some(mut content) = image_source(path) else {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        return block("paragraph", json!({ "rich_text": plain_rich_text(&name) }));
+    };
+    content["caption"] = json!(caption.map(plain_rich_text).unwrap_or_default());
+    block("image", content)
+}
+
+/// notion blocks of a feishu block, lists and long paragraphs become several
+pub fn blocks(block_: &Block, image_source: &mut ImageSource) -> Vec<Value> {
+    match block_ {
+        Block::Text(slices) => {
+            let items = rich_text(slices);
+            if items.is_empty() {
+                return vec![block("paragraph", json!({ "rich_text": [] }))];
+            }
+            items
+                .chunks(MAX_RICH_TEXT_ITEMS)
+                .map(|chunk| block("paragraph", json!({ "rich_text": chunk })))
+                .collect()
+        }
+        Block::Title { text, head_level } => match head_level_to_usize(head_level) {
+            level @ 1..=3 => vec![block(
+                &format!("heading_{}", level),
+                json!({ "rich_text": plain_rich_text(text) }),
+            )],
+            // notion stops at three levels, deeper headings are bold paragraphs
+            _ => vec![block(
+                "paragraph",
+                json!({ "rich_text": [text_item(text, None, true, false, false)] }),
+            )],
+        },
+        Block::List { list_type, items } => list_items(*list_type, items, image_source),
+        Block::Image {
+            cached_path,
+            caption,
+            ..
+        } => vec![image_block(cached_path, caption.as_deref(), image_source)],
+        Block::Code { language, code } => vec![block(
+            "code",
+            json!({
+                "rich_text": capped(plain_rich_text(code.trim_end_matches('\n'))),
+                "language": code_language(language),
+            }),
+        )],
+        Block::Sheet { cells } => {
+            let width = cells.iter().map(Vec::len).max().unwrap_or(0);
+            if width == 0 {
+                return vec![];
+            }
+            let rows = cells
+                .iter()
+                .map(|row| {
+                    let cells = (0..width)
+                        .map(|c| {
+                            json!(plain_rich_text(
+                                row.get(c).map(String::as_str).unwrap_or_default()
+                            ))
+                        })
+                        .collect::<Vec<_>>();
+                    block("table_row", json!({ "cells": cells }))
+                })
+                .collect::<Vec<_>>();
+            vec![block(
+                "table",
+                json!({
+                    "table_width": width,
+                    "has_column_header": true,
+                    "has_row_header": false,
+                    "children": rows,
+                }),
+            )]
+        }
+        Block::Diagram {
+            cached_path,
+            outline,
+            ..
+        } => {
+            let mut result = vec![image_block(cached_path, None, image_source)];
+            if let Some(root) = outline {
+                result.push(outline_items(root));
+            }
+            result
+        }
+        Block::Callout { emoji, children } => {
+            let mut content = json!({
+                "rich_text": [],
+                "color": "default",
+                "children": children
+                    .iter()
+                    .flat_map(|b| blocks(b, image_source))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(emoji) = emoji {
+                content["icon"] = json!({ "type": "emoji", "emoji": emoji });
+            }
+            vec![block("callout", content)]
+        }
+    }
+}
+
+/// every image of the blocks, the ones to upload before publishing
+pub fn image_paths(blocks: &[Block]) -> Vec<&Path> {
+    let mut paths = vec![];
+    for block in blocks {
+        match block {
+            Block::Image { cached_path, .. } | Block::Diagram { cached_path, .. } => {
+                paths.push(cached_path.as_path())
+            }
+            Block::List { items, .. } => {
+                for item in items {
+                    paths.extend(image_paths(&item.following));
+                }
+            }
+            Block::Callout { children, .. } => paths.extend(image_paths(children)),
+            _ => {}
+        }
+    }
+    paths
+}
+
+/// Write the blocks of `doc` as the body of an append block children request,
+/// images point to their copies in `<stem>.rsc` and have to be hosted or uploaded
+pub fn export_document_to_notion(
+    doc: &Document,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = path
+        .file_name()
+        .ok_or("output path has no file name")?
+        .to_string_lossy();
+    let stem = file_name
+        .strip_suffix(".notion.json")
+        .or_else(|| file_name.strip_suffix(".json"))
+        .unwrap_or(&file_name);
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let mut copy_error = None;
+    let mut image_source = |cached_path: &Path| {
+        let url = copy_image_to_rsc(cached_path, &rsc_dir_name, &rsc_path).unwrap_or_else(|e| {
+            copy_error = Some(e.to_string());
+            String::new()
+        });
+        Some(json!({ "type": "external", "external": { "url": url } }))
+    };
+    let children = doc
+        .blocks
+        .iter()
+        .flat_map(|b| blocks(b, &mut image_source))
+        .collect::<Vec<_>>();
+    if let Some(error) = copy_error {
+        return Err(error.into());
+    }
+    fs::write(
+        path,
+        serde_json::to_string_pretty(&json!({ "children": children }))?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice(text: &str) -> TextSlice {
+        TextSlice {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn contents(items: &[Value]) -> Vec<&str> {
+        items
+            .iter()
+            .map(|i| i["text"]["content"].as_str().unwrap())
+            .collect()
+    }
+
+    fn no_images(_: &Path) -> Option<Value> {
+        None
+    }
+
+    #[test]
+    fn test_rich_text_splits_long_slices() {
+        // counted in characters, not bytes
+        let text = "文".repeat(MAX_TEXT_LENGTH * 2 + 5);
+        let items = rich_text(&[
+            TextSlice {
+                link: Some("https://example.com".to_string()),
+                is_bold: true,
+                ..slice(&text)
+            },
+            slice("tail"),
+        ]);
+        let lengths = contents(&items)
+            .iter()
+            .map(|c| c.chars().count())
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, [2000, 2000, 5, 4]);
+        // every piece keeps the formatting of its slice
+        for item in &items[..3] {
+            assert_eq!(item["annotations"]["bold"], true);
+            assert_eq!(item["text"]["link"]["url"], "https://example.com");
+        }
+        assert_eq!(items[3]["annotations"]["bold"], false);
+    }
+
+    #[test]
+    fn test_paragraphs_and_headlines_respect_item_cap() {
+        let slices = (0..250).map(|i| slice(&i.to_string())).collect::<Vec<_>>();
+        let paragraphs = blocks(&Block::Text(slices.clone()), &mut no_images);
+        let counts = paragraphs
+            .iter()
+            .map(|p| p["paragraph"]["rich_text"].as_array().unwrap().len())
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [100, 100, 50]);
+        assert_eq!(
+            paragraphs[2]["paragraph"]["rich_text"][0]["text"]["content"],
+            "200"
+        );
+
+        let list = Block::List {
+            list_type: ListType::Task,
+            items: vec![ListOne::new(slices, Some(true), vec![])],
+        };
+        let items = blocks(&list, &mut no_images);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["to_do"]["checked"], true);
+        assert_eq!(
+            items[0]["to_do"]["rich_text"].as_array().unwrap().len(),
+            MAX_RICH_TEXT_ITEMS
+        );
+    }
+
+    #[test]
+    fn test_long_code_is_split_into_items() {
+        let code = Block::Code {
+            language: "Rust".to_string(),
+            code: format!("{}\n", "x".repeat(4500)),
+        };
+        let block = &blocks(&code, &mut no_images)[0];
+        assert_eq!(block["code"]["language"], "rust");
+        let lengths = contents(block["code"]["rich_text"].as_array().unwrap())
+            .iter()
+            .map(|c| c.len())
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, [2000, 2000, 500]);
+    }
+}
@@ -0,0 +1,423 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::obsidian::callout_type;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+use crate::to_typst::indent_lines;
+
+/// renders nothing, keeps org from reading the characters around it as markup
+const ZERO_WIDTH_SPACE: char = '\u{200b}';
+
+/// how task lists are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OrgTasks {
+    /// `- [X]` checkbox items
+    #[default]
+    Checkbox,
+    /// TODO / DONE headings one level below the current section, for the agenda.
+    /// Content after the list until the next heading ends up under the last task.
+    Todo,
+}
+
+/// characters allowed right before an emphasis marker
+fn emphasis_pre(c: Option<char>) -> bool {
+    c.is_none_or(|c| c.is_whitespace() || "-('\"{".contains(c))
+}
+
+/// and right after the closing one
+fn emphasis_post(c: Option<char>) -> bool {
+    c.is_none_or(|c| c.is_whitespace() || "-.,;:!?')}[\"\\".contains(c))
+}
+
+/// a heading, list, table, keyword or drawer when at the start of a line
+fn starts_line_markup(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        return matches!(line[digits..].chars().next(), Some('.' | ')'));
+    }
+    matches!(line.chars().next(), Some('*' | '#' | '|' | '-' | '+' | ':'))
+}
+
+/// Escape text for a paragraph, `at_line_start` tells whether it begins a line
+pub fn escape(text: &str, at_line_start: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        if (index > 0 || at_line_start) && starts_line_markup(line) {
+            out.push(ZERO_WIDTH_SPACE);
+        }
+        let mut previous = None;
+        for c in line.chars() {
+            // a marker that could open emphasis, or a second `[` opening a link
+            if ("*/_=~+".contains(c) && emphasis_pre(previous))
+                || (c == '[' && previous == Some('['))
+            {
+                out.push(ZERO_WIDTH_SPACE);
+            }
+            if !c.is_control() || c == '\t' {
+                out.push(c);
+            }
+            previous = Some(c);
+        }
+    }
+    out
+}
+
+/// `*`, `_`, `~`... around the text, which cannot start or end with a space
+fn emphasize(text: &str, marker: char) -> String {
+    format!("{}{}{}", marker, text, marker)
+}
+
+fn render_text_slices(slices: &[TextSlice], at_line_start: bool) -> String {
+    let mut org = String::new();
+    let mut after_markup = false;
+    for slice in slices {
+        if slice.text.is_empty() {
+            continue;
+        }
+        if after_markup && !emphasis_post(slice.text.chars().next()) {
+            org.push(ZERO_WIDTH_SPACE);
+        }
+        after_markup = false;
+        let line_start = if org.is_empty() {
+            at_line_start
+        } else {
+            org.ends_with('\n')
+        };
+        let core = slice.text.trim();
+        let marked = slice.is_bold || slice.is_underline || slice.is_code || slice.link.is_some();
+        if core.is_empty() || !marked {
+            org.push_str(&escape(&slice.text, line_start));
+            continue;
+        }
+        let lead = &slice.text[..slice.text.len() - slice.text.trim_start().len()];
+        let trail = &slice.text[slice.text.trim_end().len()..];
+        let core = core.replace('\n', " ");
+        let mut current = if slice.is_code {
+            // verbatim when the code holds a tilde
+            emphasize(&core, if core.contains('~') { '=' } else { '~' })
+        } else {
+            escape(&core, false)
+        };
+        if slice.is_underline {
+            current = emphasize(&current, '_');
+        }
+        if slice.is_bold {
+            current = emphasize(&current, '*');
+        }
+        if let Some(link) = &slice.link {
+            current = format!("[[{}][{}]]", link, current.replace("]]", "] ]"));
+        }
+        org.push_str(&escape(lead, line_start));
+        if !emphasis_pre(org.chars().last()) {
+            org.push(ZERO_WIDTH_SPACE);
+        }
+        org.push_str(&current);
+        org.push_str(trail);
+        after_markup = trail.is_empty();
+    }
+    // `\\` ends a line inside a paragraph
+    org.trim_end().replace('\n', "\\\\\n")
+}
+
+fn render_outline(node: &OutlineNode) -> String {
+    let mut org = format!("- {}\n", escape(&node.text, false));
+    for child in &node.children {
+        org.push_str(&indent_lines(&render_outline(child), "  "));
+        org.push('\n');
+    }
+    org
+}
+
+/// code lines org would read as a heading or a keyword get a leading comma
+fn escape_code(code: &str) -> String {
+    code.lines()
+        .map(|line| {
+            let content = line.trim_start().trim_start_matches(',');
+            if content.starts_with('*') || content.starts_with("#+") {
+                format!(",{}", line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct OrgWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+    tasks: OrgTasks,
+    /// level of the last heading, tasks written as headings go one below
+    section_level: usize,
+}
+
+impl OrgWriter {
+    fn image(
+        &self,
+        cached_path: &Path,
+        alt: Option<&str>,
+        caption: Option<&str>,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let path = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let mut org = String::new();
+        if let Some(caption) = caption {
+            org.push_str(&format!("#+caption: {}\n", caption.replace('\n', " ")));
+        }
+        let mut attributes = vec![];
+        if let Some(alt) = alt.or(caption) {
+            attributes.push(format!(":alt {}", alt.replace('\n', " ")));
+        }
+        if let Some(width) = width {
+            attributes.push(format!(":width {}px", width));
+        }
+        match align {
+            Some(ImageAlign::Left) => attributes.push(":align left".to_string()),
+            Some(ImageAlign::Center) => attributes.push(":align center".to_string()),
+            Some(ImageAlign::Right) => attributes.push(":align right".to_string()),
+            None => {}
+        }
+        if !attributes.is_empty() {
+            org.push_str(&format!("#+attr_html: {}\n", attributes.join(" ")));
+        }
+        org.push_str(&format!("[[file:{}]]\n\n", path));
+        Ok(org)
+    }
+
+    /// task items as TODO / DONE headings, their content below them
+    fn task_headings(&mut self, items: &[ListOne]) -> Result<String, Box<dyn std::error::Error>> {
+        let stars = "*".repeat(self.section_level + 1);
+        let mut org = String::new();
+        for item in items {
+            let keyword = if item.done == Some(true) {
+                "DONE"
+            } else {
+                "TODO"
+            };
+            org.push_str(&format!(
+                "{} {} {}\n",
+                stars,
+                keyword,
+                render_text_slices(&item.headline, false).replace("\\\\\n", " ")
+            ));
+            for following in &item.following {
+                org.push_str(&self.block(following, true)?);
+            }
+        }
+        Ok(org)
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+        nested: bool,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if list_type == ListType::Task && self.tasks == OrgTasks::Todo && !nested {
+            return self.task_headings(items);
+        }
+        let mut org = String::new();
+        for (index, item) in items.iter().enumerate() {
+            let marker = match (list_type, item.done) {
+                (ListType::Ordered, _) => format!("{}. ", index + 1),
+                (ListType::Unordered, _) => "- ".to_string(),
+                (ListType::Task, Some(true)) => "- [X] ".to_string(),
+                (ListType::Task, _) => "- [ ] ".to_string(),
+            };
+            // content lines up with the text after the bullet, without the checkbox
+            let indent = " ".repeat(if list_type == ListType::Ordered {
+                marker.len()
+            } else {
+                2
+            });
+            let headline = render_text_slices(&item.headline, false);
+            org.push_str(&format!(
+                "{}{}\n",
+                marker,
+                indent_lines(&headline, &indent).trim_start()
+            ));
+            for following in &item.following {
+                let block = self.block(following, true)?;
+                if block.trim().is_empty() {
+                    continue;
+                }
+                // a paragraph of its own rather than the rest of the item text
+                if !matches!(following, Block::List { .. }) {
+                    org.push('\n');
+                }
+                org.push_str(&indent_lines(block.trim_end(), &indent));
+                org.push('\n');
+            }
+        }
+        if !nested {
+            org.push('\n');
+        }
+        Ok(org)
+    }
+
+    /// `nested` is set for blocks inside list items
+    fn block(&mut self, block: &Block, nested: bool) -> Result<String, Box<dyn std::error::Error>> {
+        let org = match block {
+            Block::Text(slices) => {
+                let text = render_text_slices(slices, true);
+                if text.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", text)
+                }
+            }
+            Block::Title { text, head_level } => {
+                self.section_level = head_level_to_usize(head_level);
+                format!(
+                    "{} {}\n\n",
+                    "*".repeat(self.section_level),
+                    escape(&text.replace('\n', " "), false)
+                )
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items, nested)?,
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => self.image(
+                cached_path,
+                alt.as_deref(),
+                caption.as_deref(),
+                *width,
+                *align,
+            )?,
+            Block::Code { language, code } => {
+                let code = escape_code(code.trim_end_matches('\n'));
+                if language.is_empty() {
+                    format!("#+begin_example\n{}\n#+end_example\n\n", code)
+                } else {
+                    format!(
+                        "#+begin_src {}\n{}\n#+end_src\n\n",
+                        language.to_lowercase().replace(' ', "-"),
+                        code
+                    )
+                }
+            }
+            Block::Sheet { cells } => {
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                if columns == 0 {
+                    return Ok(String::new());
+                }
+                let mut org = String::new();
+                for (index, row) in cells.iter().enumerate() {
+                    let cells = (0..columns)
+                        .map(|c| {
+                            let text = row.get(c).map(String::as_str).unwrap_or_default();
+                            escape(&text.replace('\n', " "), false).replace('|', "\\vert{}")
+                        })
+                        .collect::<Vec<_>>();
+                    org.push_str(&format!("| {} |\n", cells.join(" | ")));
+                    if index == 0 {
+                        // the rule makes the first row the header
+                        org.push_str(&format!("|{}|\n", vec!["---"; columns].join("+")));
+                    }
+                }
+                org.push('\n');
+                org
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut org = self.image(cached_path, None, None, None, None)?;
+                if let Some(root) = outline {
+                    org.push_str(&render_outline(root));
+                    org.push('\n');
+                }
+                org
+            }
+            Block::Callout { children, emoji } => {
+                // a special block, exported as a div or an environment of that name
+                let name = callout_type(emoji.as_deref());
+                let mut content = String::new();
+                for child in children {
+                    content.push_str(&self.block(child, true)?);
+                }
+                format!(
+                    "#+begin_{}\n{}\n#+end_{}\n\n",
+                    name,
+                    content.trim_end(),
+                    name
+                )
+            }
+        };
+        Ok(org)
+    }
+}
+
+/// Write the document as an org file, images go to `<stem>.rsc` next to it
+pub fn export_document_to_org(
+    doc: &Document,
+    org_path: &Path,
+    tasks: OrgTasks,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = org_path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = org_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let mut writer = OrgWriter {
+        rsc_dir_name,
+        rsc_path,
+        tasks,
+        section_level: 0,
+    };
+
+    let mut org = String::new();
+    if let Some(title) = &doc.meta.title {
+        org.push_str(&format!("#+title: {}\n", title.replace('\n', " ")));
+    }
+    if let Some(author) = &doc.meta.author {
+        org.push_str(&format!("#+author: {}\n", author));
+    }
+    if let Some(date) = doc.meta.modified.or(doc.meta.created) {
+        org.push_str(&format!("#+date: {}\n", date.format("%Y-%m-%d")));
+    }
+    // `a_b` stays as it is, subscripts need braces
+    org.push_str("#+options: ^:{}\n\n");
+    for block in &doc.blocks {
+        org.push_str(&writer.block(block, false)?);
+    }
+    fs::write(org_path, org.trim_end().to_string() + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_export_golden;
+
+    #[test]
+    fn test_export_golden() {
+        assert_export_golden("document.org", |doc, path| {
+            export_document_to_org(doc, path, OrgTasks::Checkbox)
+        });
+    }
+
+    #[test]
+    fn test_export_golden_todo_headings() {
+        assert_export_golden("document.todo.org", |doc, path| {
+            export_document_to_org(doc, path, OrgTasks::Todo)
+        });
+    }
+}
@@ -0,0 +1,377 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Value, json};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::outline::OutlineNode;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+
+/// pandoc-types version of the ast, read by pandoc 3.1.4 and later
+const PANDOC_API_VERSION: [u32; 3] = [1, 23, 1];
+
+/// a node of the ast, `{"t": tag, "c": content}`
+fn node(tag: &str, content: Value) -> Value {
+    json!({ "t": tag, "c": content })
+}
+
+/// a node without content
+fn tag(tag: &str) -> Value {
+    json!({ "t": tag })
+}
+
+/// identifier, classes and key value pairs
+fn attr(classes: &[&str], pairs: &[(&str, String)]) -> Value {
+    json!(["", classes, pairs])
+}
+
+fn no_attr() -> Value {
+    attr(&[], &[])
+}
+
+/// words as `Str`, runs of blanks as `Space` and newlines as `LineBreak`
+fn text_inlines(text: &str) -> Vec<Value> {
+    let mut inlines = vec![];
+    let mut word = String::new();
+    for c in text.chars() {
+        if c == ' ' || c == '\t' || c == '\n' {
+            if !word.is_empty() {
+                inlines.push(node("Str", json!(word)));
+                word.clear();
+            }
+            if c == '\n' {
+                inlines.push(tag("LineBreak"));
+            } else if inlines.last() != Some(&tag("Space")) {
+                inlines.push(tag("Space"));
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        inlines.push(node("Str", json!(word)));
+    }
+    inlines
+}
+
+fn slice_inlines(slices: &[TextSlice]) -> Vec<Value> {
+    let mut inlines = vec![];
+    for slice in slices {
+        let mut current = if slice.is_code {
+            vec![node("Code", json!([no_attr(), slice.text]))]
+        } else {
+            text_inlines(&slice.text)
+        };
+        if current.is_empty() {
+            continue;
+        }
+        if slice.is_bold {
+            current = vec![node("Strong", json!(current))];
+        }
+        if slice.is_underline {
+            current = vec![node("Underline", json!(current))];
+        }
+        if let Some(link) = &slice.link {
+            current = vec![node("Link", json!([no_attr(), current, [link, ""]]))];
+        }
+        inlines.extend(current);
+    }
+    // a paragraph ending in a line break renders an empty line
+    while inlines.last() == Some(&tag("LineBreak")) {
+        inlines.pop();
+    }
+    inlines
+}
+
+/// blocks of a mind map node as a list item, its children as a nested list
+fn outline_item(outline: &OutlineNode) -> Vec<Value> {
+    let mut item = vec![node("Plain", json!(text_inlines(&outline.text)))];
+    if !outline.children.is_empty() {
+        item.push(node(
+            "BulletList",
+            json!(
+                outline
+                    .children
+                    .iter()
+                    .map(outline_item)
+                    .collect::<Vec<_>>()
+            ),
+        ));
+    }
+    item
+}
+
+/// a table cell holding plain text
+fn cell(text: &str) -> Value {
+    json!([
+        no_attr(),
+        tag("AlignDefault"),
+        1,
+        1,
+        [node("Plain", json!(text_inlines(text)))]
+    ])
+}
+
+fn row(cells: &[String], width: usize) -> Value {
+    json!([
+        no_attr(),
+        (0..width)
+            .map(|c| cell(cells.get(c).map(String::as_str).unwrap_or_default()))
+            .collect::<Vec<_>>()
+    ])
+}
+
+struct PandocWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+}
+
+impl PandocWriter {
+    fn image(
+        &self,
+        cached_path: &Path,
+        alt: Option<&str>,
+        caption: Option<&str>,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let url = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let mut pairs = vec![];
+        if let Some(width) = width {
+            pairs.push(("width", format!("{}px", width)));
+        }
+        let image = node(
+            "Image",
+            json!([
+                attr(&[], &pairs),
+                text_inlines(alt.unwrap_or_default()),
+                [url, ""]
+            ]),
+        );
+        let classes = match align {
+            Some(ImageAlign::Left) => vec!["align-left"],
+            Some(ImageAlign::Center) => vec!["align-center"],
+            Some(ImageAlign::Right) => vec!["align-right"],
+            None => vec![],
+        };
+        Ok(match caption {
+            // caption is a short caption (none here) and the long one as blocks
+            Some(caption) => node(
+                "Figure",
+                json!([
+                    attr(&classes, &[]),
+                    [null, [node("Plain", json!(text_inlines(caption)))]],
+                    [node("Plain", json!([image]))]
+                ]),
+            ),
+            None => node("Para", json!([image])),
+        })
+    }
+
+    fn list_items(
+        &self,
+        list_type: ListType,
+        items: &[ListOne],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut entries = vec![];
+        for item in items {
+            let mut headline = slice_inlines(&item.headline);
+            // pandoc reads task items as list items led by a ballot box
+            if list_type == ListType::Task {
+                let box_ = if item.done == Some(true) {
+                    "☒"
+                } else {
+                    "☐"
+                };
+                headline.splice(0..0, [node("Str", json!(box_)), tag("Space")]);
+            }
+            let mut blocks = vec![node("Plain", json!(headline))];
+            for following in &item.following {
+                blocks.extend(self.block(following)?);
+            }
+            entries.push(blocks);
+        }
+        Ok(match list_type {
+            ListType::Ordered => node(
+                "OrderedList",
+                json!([[1, tag("Decimal"), tag("Period")], entries]),
+            ),
+            _ => node("BulletList", json!(entries)),
+        })
+    }
+
+    fn block(&self, block: &Block) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let blocks = match block {
+            Block::Text(slices) => {
+                let inlines = slice_inlines(slices);
+                if inlines.is_empty() {
+                    vec![]
+                } else {
+                    vec![node("Para", json!(inlines))]
+                }
+            }
+            Block::Title { text, head_level } => vec![node(
+                "Header",
+                json!([
+                    // deeper levels are not written by most formats
+                    head_level_to_usize(head_level).min(6),
+                    no_attr(),
+                    text_inlines(&text.replace('\n', " "))
+                ]),
+            )],
+            Block::List { list_type, items } => vec![self.list_items(*list_type, items)?],
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => vec![self.image(
+                cached_path,
+                alt.as_deref(),
+                caption.as_deref(),
+                *width,
+                *align,
+            )?],
+            Block::Code { language, code } => {
+                let classes = if language.is_empty() {
+                    vec![]
+                } else {
+                    vec![language.to_lowercase()]
+                };
+                vec![node(
+                    "CodeBlock",
+                    json!([["", classes, []], code.trim_end_matches('\n')]),
+                )]
+            }
+            Block::Sheet { cells } => {
+                let width = cells.iter().map(Vec::len).max().unwrap_or(0);
+                if width == 0 {
+                    return Ok(vec![]);
+                }
+                let mut rows = cells.iter();
+                let head = rows
+                    .next()
+                    .map(|r| row(r, width))
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                let body = rows.map(|r| row(r, width)).collect::<Vec<_>>();
+                vec![node(
+                    "Table",
+                    json!([
+                        no_attr(),
+                        [null, []],
+                        (0..width)
+                            .map(|_| json!([tag("AlignDefault"), tag("ColWidthDefault")]))
+                            .collect::<Vec<_>>(),
+                        [no_attr(), head],
+                        [[no_attr(), 0, [], body]],
+                        [no_attr(), []]
+                    ]),
+                )]
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut blocks = vec![self.image(cached_path, None, None, None, None)?];
+                if let Some(root) = outline {
+                    blocks.push(node("BulletList", json!([outline_item(root)])));
+                }
+                blocks
+            }
+            Block::Callout { emoji, children } => {
+                let mut pairs = vec![];
+                if let Some(emoji) = emoji {
+                    pairs.push(("emoji", emoji.clone()));
+                }
+                let mut content = vec![];
+                for child in children {
+                    content.extend(self.block(child)?);
+                }
+                vec![node("Div", json!([attr(&["callout"], &pairs), content]))]
+            }
+        };
+        Ok(blocks)
+    }
+}
+
+fn meta_inlines(text: &str) -> Value {
+    node("MetaInlines", json!(text_inlines(text)))
+}
+
+/// Write the document as pandoc's json ast, for `pandoc -f json`.
+/// Images go to `<stem>.rsc` next to it.
+pub fn export_document_to_pandoc(
+    doc: &Document,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file_name = path
+        .file_name()
+        .ok_or("output path has no file name")?
+        .to_string_lossy();
+    let stem = file_name
+        .strip_suffix(".pandoc.json")
+        .or_else(|| file_name.strip_suffix(".json"))
+        .unwrap_or(&file_name);
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let writer = PandocWriter {
+        rsc_dir_name,
+        rsc_path,
+    };
+    let mut blocks = vec![];
+    for block in &doc.blocks {
+        blocks.extend(writer.block(block)?);
+    }
+
+    let mut meta = serde_json::Map::new();
+    if let Some(title) = &doc.meta.title {
+        meta.insert("title".to_string(), meta_inlines(title));
+    }
+    if let Some(author) = &doc.meta.author {
+        meta.insert(
+            "author".to_string(),
+            node("MetaList", json!([meta_inlines(author)])),
+        );
+    }
+    if let Some(date) = doc.meta.modified.or(doc.meta.created) {
+        meta.insert(
+            "date".to_string(),
+            node("MetaString", json!(date.format("%Y-%m-%d").to_string())),
+        );
+    }
+    if let Some(url) = &doc.meta.source_url {
+        meta.insert("source-url".to_string(), node("MetaString", json!(url)));
+    }
+    let ast = json!({
+        "pandoc-api-version": PANDOC_API_VERSION,
+        "meta": meta,
+        "blocks": blocks,
+    });
+    fs::write(path, serde_json::to_string(&ast)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_export_golden;
+
+    #[test]
+    fn test_export_golden() {
+        // pretty printed so the golden file diffs line by line
+        assert_export_golden("document.pandoc.json", |doc, path| {
+            export_document_to_pandoc(doc, path)?;
+            let ast: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+            fs::write(path, serde_json::to_string_pretty(&ast)? + "\n")?;
+            Ok(())
+        });
+    }
+}
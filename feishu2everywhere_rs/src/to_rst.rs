@@ -0,0 +1,429 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::obsidian::callout_type;
+use crate::outline::OutlineNode;
+use crate::to_latex::is_cjk;
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+use crate::to_typst::indent_lines;
+
+/// underline characters of the section depths, in order
+const ADORNMENTS: [char; 10] = ['=', '-', '~', '^', '"', '\'', '`', '#', '*', '+'];
+
+/// Section depths without gaps, a heading goes one below the closest shallower one.
+/// Docutils and asciidoctor refuse a section that skips a level.
+#[derive(Default)]
+pub struct SectionDepth {
+    open: Vec<usize>,
+}
+
+impl SectionDepth {
+    /// depth, from 1, of a heading of `level`
+    pub fn enter(&mut self, level: usize) -> usize {
+        while self.open.last().is_some_and(|l| *l >= level) {
+            self.open.pop();
+        }
+        self.open.push(level);
+        self.open.len()
+    }
+}
+
+/// columns the text takes in a monospace font, cjk characters take two
+pub fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if is_cjk(c) { 2 } else { 1 }).sum()
+}
+
+/// a list marker or directive at the start of the text, `1.`, `- `, `#.`, `..`
+fn starts_block_markup(line: &str) -> bool {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 {
+        return matches!(line[digits..].chars().next(), Some('.' | ')'));
+    }
+    matches!(
+        line.chars().next(),
+        Some('-' | '+' | '*' | '#' | ':' | '.' | '>' | '|')
+    )
+}
+
+/// Escape text for a paragraph, `at_line_start` tells whether it begins a line
+pub fn escape(text: &str, at_line_start: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        // the characters escaped below already are
+        if (index > 0 || at_line_start)
+            && starts_block_markup(line)
+            && !line.starts_with(['*', '|'])
+        {
+            out.push('\\');
+        }
+        for c in line.chars() {
+            match c {
+                '\\' | '*' | '`' | '|' | '_' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                c if c.is_control() && c != '\t' => {}
+                c => out.push(c),
+            }
+        }
+    }
+    out
+}
+
+/// Inline markup must start after a space and end before one,
+/// `\ ` is an escaped space that renders as nothing
+fn push_markup(rst: &mut String, markup: &str) {
+    if !rst.is_empty() && !rst.ends_with(char::is_whitespace) {
+        rst.push_str("\\ ");
+    }
+    rst.push_str(markup);
+}
+
+fn render_text_slices(slices: &[TextSlice]) -> String {
+    let mut rst = String::new();
+    let mut after_markup = false;
+    for slice in slices {
+        if slice.text.is_empty() {
+            continue;
+        }
+        if after_markup && !slice.text.starts_with(char::is_whitespace) {
+            rst.push_str("\\ ");
+        }
+        after_markup = false;
+        // markup cannot begin or end with a space, nor span lines of a line block
+        let core = slice.text.trim();
+        let lead = &slice.text[..slice.text.len() - slice.text.trim_start().len()];
+        let trail = &slice.text[slice.text.trim_end().len()..];
+        let markup = if core.is_empty() {
+            None
+        } else if let Some(link) = &slice.link {
+            Some(format!(
+                "`{} <{}>`__",
+                escape(&core.replace('\n', " "), false).replace('<', "\\<"),
+                link
+            ))
+        } else if slice.is_code {
+            Some(format!("``{}``", core.replace('\n', " ")))
+        } else if slice.is_bold {
+            Some(format!("**{}**", escape(&core.replace('\n', " "), false)))
+        } else {
+            None
+        };
+        match markup {
+            Some(markup) => {
+                rst.push_str(&escape(lead, rst.is_empty() || rst.ends_with('\n')));
+                push_markup(&mut rst, &markup);
+                rst.push_str(&escape(trail, false));
+                after_markup = trail.is_empty();
+            }
+            None => {
+                rst.push_str(&escape(&slice.text, rst.is_empty() || rst.ends_with('\n')));
+            }
+        }
+    }
+    rst.trim_end().to_string()
+}
+
+fn render_outline(node: &OutlineNode) -> String {
+    let mut rst = format!("- {}\n", escape(&node.text, false));
+    if !node.children.is_empty() {
+        rst.push('\n');
+        for child in &node.children {
+            rst.push_str(&indent_lines(&render_outline(child), "  "));
+            rst.push('\n');
+        }
+        rst.push('\n');
+    }
+    rst
+}
+
+/// admonition directive for the emoji leading a feishu callout
+fn admonition(emoji: Option<&str>) -> &'static str {
+    match callout_type(emoji) {
+        "tip" | "success" => "tip",
+        "warning" | "bug" => "warning",
+        "danger" => "danger",
+        "important" => "important",
+        _ => "note",
+    }
+}
+
+/// a code block language pygments knows, `text` for none
+fn pygments_language(language: &str) -> String {
+    let language = language.to_lowercase();
+    if language.is_empty() || language.contains(char::is_whitespace) {
+        "text".to_string()
+    } else {
+        language
+    }
+}
+
+struct RstWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+    sections: SectionDepth,
+}
+
+impl RstWriter {
+    fn image(
+        &self,
+        cached_path: &Path,
+        alt: Option<&str>,
+        caption: Option<&str>,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let path = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let directive = if caption.is_some() { "figure" } else { "image" };
+        let mut rst = format!(".. {}:: {}\n", directive, path);
+        if let Some(alt) = alt.or(caption) {
+            rst.push_str(&format!("   :alt: {}\n", alt.replace('\n', " ")));
+        }
+        if let Some(width) = width {
+            rst.push_str(&format!("   :width: {}px\n", width));
+        }
+        match align {
+            Some(ImageAlign::Left) => rst.push_str("   :align: left\n"),
+            Some(ImageAlign::Center) => rst.push_str("   :align: center\n"),
+            Some(ImageAlign::Right) => rst.push_str("   :align: right\n"),
+            None => {}
+        }
+        if let Some(caption) = caption {
+            rst.push_str(&format!(
+                "\n{}\n",
+                indent_lines(&escape(caption, true), "   ")
+            ));
+        }
+        rst.push('\n');
+        Ok(rst)
+    }
+
+    fn list_items(
+        &mut self,
+        list_type: ListType,
+        items: &[ListOne],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut rst = String::new();
+        for item in items {
+            let marker = match (list_type, item.done) {
+                (ListType::Ordered, _) => "#. ",
+                (ListType::Unordered, _) => "- ",
+                (ListType::Task, Some(true)) => "- ☒ ",
+                (ListType::Task, _) => "- ☐ ",
+            };
+            // content lines up with the text after the bullet
+            let indent = if list_type == ListType::Ordered {
+                "   "
+            } else {
+                "  "
+            };
+            let headline = render_text_slices(&item.headline);
+            let headline = if headline.contains('\n') {
+                let lines = headline
+                    .lines()
+                    .map(|l| format!("| {}", l))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                indent_lines(&lines, indent).trim_start().to_string()
+            } else {
+                headline
+            };
+            rst.push_str(&format!("{}{}\n", marker, headline));
+            for following in &item.following {
+                let block = self.block(following)?;
+                if block.trim().is_empty() {
+                    continue;
+                }
+                rst.push('\n');
+                rst.push_str(&indent_lines(block.trim_end(), indent));
+                rst.push('\n');
+            }
+            if !item.following.is_empty() {
+                rst.push('\n');
+            }
+        }
+        if !rst.ends_with("\n\n") {
+            rst.push('\n');
+        }
+        Ok(rst)
+    }
+
+    fn block(&mut self, block: &Block) -> Result<String, Box<dyn std::error::Error>> {
+        let rst = match block {
+            Block::Text(slices) => {
+                let text = render_text_slices(slices);
+                if text.trim().is_empty() {
+                    String::new()
+                } else if text.contains('\n') {
+                    // a line block keeps the line breaks
+                    let lines = text
+                        .lines()
+                        .map(|l| {
+                            if l.is_empty() {
+                                "|".to_string()
+                            } else {
+                                format!("| {}", l)
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    format!("{}\n\n", lines.join("\n"))
+                } else {
+                    format!("{}\n\n", text)
+                }
+            }
+            Block::Title { text, head_level } => {
+                let depth = self.sections.enter(head_level_to_usize(head_level));
+                let title = escape(&text.replace('\n', " "), false);
+                let adornment = ADORNMENTS[(depth - 1).min(ADORNMENTS.len() - 1)];
+                format!(
+                    "{}\n{}\n\n",
+                    title,
+                    adornment.to_string().repeat(display_width(&title).max(1))
+                )
+            }
+            Block::List { list_type, items } => self.list_items(*list_type, items)?,
+            Block::Image {
+                cached_path,
+                alt,
+                caption,
+                width,
+                align,
+            } => self.image(
+                cached_path,
+                alt.as_deref(),
+                caption.as_deref(),
+                *width,
+                *align,
+            )?,
+            Block::Code { language, code } => format!(
+                ".. code-block:: {}\n\n{}\n\n",
+                pygments_language(language),
+                indent_lines(code.trim_end_matches('\n'), "   ")
+            ),
+            Block::Sheet { cells } => {
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                if columns == 0 {
+                    return Ok(String::new());
+                }
+                let mut rst = String::from(".. list-table::\n   :header-rows: 1\n\n");
+                for row in cells {
+                    for c in 0..columns {
+                        let text = row.get(c).map(String::as_str).unwrap_or_default();
+                        let text = escape(&text.replace('\n', " "), true);
+                        let bullet = if c == 0 { "   * -" } else { "     -" };
+                        if text.trim().is_empty() {
+                            rst.push_str(&format!("{}\n", bullet));
+                        } else {
+                            rst.push_str(&format!("{} {}\n", bullet, text.trim()));
+                        }
+                    }
+                }
+                rst.push('\n');
+                rst
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut rst = self.image(cached_path, None, None, None, None)?;
+                if let Some(root) = outline {
+                    rst.push_str(&render_outline(root));
+                    rst.push('\n');
+                }
+                rst
+            }
+            Block::Callout { emoji, children } => {
+                let mut content = String::new();
+                for child in children {
+                    content.push_str(&self.block(child)?);
+                }
+                format!(
+                    ".. {}::\n\n{}\n\n",
+                    admonition(emoji.as_deref()),
+                    indent_lines(content.trim_end(), "   ")
+                )
+            }
+        };
+        Ok(rst)
+    }
+}
+
+/// Write the document as restructuredtext for sphinx or docutils,
+/// images go to `<stem>.rsc` next to it
+pub fn export_document_to_rst(
+    doc: &Document,
+    rst_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = rst_path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = rst_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let mut writer = RstWriter {
+        rsc_dir_name,
+        rsc_path,
+        sections: SectionDepth::default(),
+    };
+
+    let mut rst = String::new();
+    if let Some(title) = &doc.meta.title {
+        // over and underlined, a style no section uses
+        let title = escape(&title.replace('\n', " "), false);
+        let line = "#".repeat(display_width(&title).max(1));
+        rst.push_str(&format!("{}\n{}\n{}\n\n", line, title, line));
+        // a field list right after the title is the docinfo
+        if let Some(author) = &doc.meta.author {
+            rst.push_str(&format!(":Author: {}\n", escape(author, false)));
+        }
+        if let Some(date) = doc.meta.modified.or(doc.meta.created) {
+            rst.push_str(&format!(":Date: {}\n", date.format("%Y-%m-%d")));
+        }
+        if doc.meta.author.is_some() || doc.meta.modified.or(doc.meta.created).is_some() {
+            rst.push('\n');
+        }
+    }
+    for block in &doc.blocks {
+        rst.push_str(&writer.block(block)?);
+    }
+    fs::write(rst_path, rst.trim_end().to_string() + "\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golden::assert_export_golden;
+
+    #[test]
+    fn test_section_depth_closes_gaps() {
+        let mut sections = SectionDepth::default();
+        let depths = [1, 3, 3, 2, 5, 4, 1, 2]
+            .iter()
+            .map(|level| sections.enter(*level))
+            .collect::<Vec<_>>();
+        assert_eq!(depths, [1, 2, 2, 2, 3, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_display_width_counts_cjk_twice() {
+        assert_eq!(display_width("Overview"), 8);
+        assert_eq!(display_width("概要 Overview"), 13);
+        assert_eq!(display_width("代码"), 4);
+    }
+
+    #[test]
+    fn test_export_golden() {
+        assert_export_golden("document.rst", export_document_to_rst);
+    }
+}
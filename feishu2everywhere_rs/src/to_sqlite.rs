@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use rusqlite::{Connection, params_from_iter, types::Value as SqlValue};
+use serde_json::Value;
+
+use crate::bitable::{BitableTable, ValueKind};
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `name`, or `name_2`, `name_3`... when it is taken, sqlite compares names case-insensitively.
+/// names of sqlite's own tables get a leading underscore
+fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base = if name.is_empty() {
+        "unnamed".to_string()
+    } else if name.to_lowercase().starts_with("sqlite_") {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    };
+    let mut candidate = base.clone();
+    let mut suffix = 2;
+    while !used.insert(candidate.to_lowercase()) {
+        candidate = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+fn column_type(kind: ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Number => "REAL",
+        ValueKind::Bool => "INTEGER",
+        // iso 8601 text sorts and works with sqlite date functions
+        ValueKind::DateTime | ValueKind::Text => "TEXT",
+        // json array, query it with json_each()
+        ValueKind::List => "TEXT",
+    }
+}
+
+fn to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => n.as_f64().map(SqlValue::Real).unwrap_or(SqlValue::Null),
+        Value::String(s) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+/// Write every table into a fresh sqlite database, one sql table per bitable table
+/// keyed by _record_id, and the field schema into `_fields`.
+/// Tables and columns clashing with those or with each other get a numeric suffix,
+/// `_fields` maps them back to the bitable names
+pub fn export_tables_to_sqlite(
+    tables: &[BitableTable],
+    sqlite_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if sqlite_path.exists() {
+        std::fs::remove_file(sqlite_path)?;
+    }
+    let mut conn = Connection::open(sqlite_path)?;
+    let tx = conn.transaction()?;
+
+    tx.execute_batch(
+        "CREATE TABLE _fields (
+            table_name TEXT NOT NULL,
+            bitable_table_name TEXT NOT NULL,
+            field_id TEXT NOT NULL,
+            field_name TEXT NOT NULL,
+            column_name TEXT NOT NULL,
+            field_type INTEGER NOT NULL,
+            type_name TEXT NOT NULL,
+            options TEXT NOT NULL,
+            property TEXT NOT NULL
+        );",
+    )?;
+
+    let mut table_names = HashSet::from(["_fields".to_string()]);
+    for table in tables {
+        let table_name = unique_name(&table.name, &mut table_names);
+        if table_name != table.name {
+            println!("table {:?} written as {:?}", table.name, table_name);
+        }
+        let mut used_columns = HashSet::from(["_record_id".to_string()]);
+        let column_names = table
+            .fields
+            .iter()
+            .map(|f| unique_name(&f.name, &mut used_columns))
+            .collect::<Vec<_>>();
+        let columns = table
+            .fields
+            .iter()
+            .zip(&column_names)
+            .map(|(f, name)| format!("{} {}", quote_ident(name), column_type(f.value_kind())))
+            .collect::<Vec<_>>();
+        tx.execute_batch(&format!(
+            "CREATE TABLE {} (_record_id TEXT PRIMARY KEY{}{});",
+            quote_ident(&table_name),
+            if columns.is_empty() { "" } else { ", " },
+            columns.join(", ")
+        ))?;
+
+        for (field, column_name) in table.fields.iter().zip(&column_names) {
+            tx.execute(
+                "INSERT INTO _fields VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                rusqlite::params![
+                    table_name,
+                    table.name,
+                    field.field_id,
+                    field.name,
+                    column_name,
+                    field.field_type,
+                    field.type_name(),
+                    serde_json::to_string(&field.options())?,
+                    field.property.to_string(),
+                ],
+            )?;
+        }
+
+        let placeholders = (1..=table.fields.len() + 1)
+            .map(|i| format!("?{}", i))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO {} VALUES ({})",
+            quote_ident(&table_name),
+            placeholders
+        ))?;
+        for record in &table.records {
+            let row = std::iter::once(SqlValue::Text(record.record_id.clone()))
+                .chain(record.values.iter().map(to_sql));
+            insert.execute(params_from_iter(row))?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_name() {
+        let mut used = HashSet::from(["_fields".to_string()]);
+        assert_eq!(unique_name("Tasks", &mut used), "Tasks");
+        assert_eq!(unique_name("tasks", &mut used), "tasks_2");
+        assert_eq!(unique_name("TASKS", &mut used), "TASKS_3");
+        assert_eq!(unique_name("_Fields", &mut used), "_Fields_2");
+        assert_eq!(unique_name("sqlite_master", &mut used), "_sqlite_master");
+        assert_eq!(unique_name("", &mut used), "unnamed");
+
+        let mut used = HashSet::from(["_record_id".to_string()]);
+        assert_eq!(unique_name("_record_id", &mut used), "_record_id_2");
+    }
+}
@@ -0,0 +1,398 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::block::{Block, ImageAlign, ListOne, ListType, TextSlice};
+use crate::document::Document;
+use crate::markdown_dialect::code_fence;
+use crate::outline::OutlineNode;
+use crate::to_latex::{image_width_pt, is_cjk};
+use crate::to_markdown::{copy_image_to_rsc, head_level_to_usize};
+
+/// Escape text for typst markup. `at_line_start` tells whether the text begins a line,
+/// where `1.` would start a numbered list.
+pub fn escape(text: &str, at_line_start: bool) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(text.len());
+    let mut line_start = at_line_start;
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '\\' | '*' | '_' | '`' | '#' | '$' | '<' | '>' | '@' | '[' | ']' | '~' | '/' | '='
+            | '-' | '+' | '"' | '\'' => {
+                out.push('\\');
+                out.push(*c);
+            }
+            '.' if line_start_digits(&chars[..i], line_start) => out.push_str("\\."),
+            // a backslash before the newline is a line break
+            '\n' => out.push_str("\\\n"),
+            c if c.is_control() && *c != '\t' => {}
+            c => out.push(*c),
+        }
+        if *c == '\n' {
+            line_start = true;
+        }
+    }
+    out
+}
+
+/// only digits since the start of the line, a `.` now makes it a list item
+fn line_start_digits(before: &[char], at_line_start: bool) -> bool {
+    let digits = before
+        .iter()
+        .rev()
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    if digits == 0 {
+        return false;
+    }
+    match before.len().checked_sub(digits + 1) {
+        Some(index) => before[index] == '\n',
+        None => at_line_start,
+    }
+}
+
+/// a typst string literal
+fn string_literal(text: &str) -> String {
+    format!(
+        "\"{}\"",
+        text.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+struct TypstWriter {
+    rsc_dir_name: String,
+    rsc_path: PathBuf,
+}
+
+fn render_text_slices(slices: &[TextSlice], at_line_start: bool) -> String {
+    let mut typst = String::new();
+    let mut line_start = at_line_start;
+    for slice in slices {
+        let mut current = if slice.is_code {
+            format!("#raw({})", string_literal(&slice.text))
+        } else {
+            escape(&slice.text, line_start)
+        };
+        if slice.is_bold {
+            current = format!("#strong[{}]", current);
+        }
+        if slice.is_underline {
+            current = format!("#underline[{}]", current);
+        }
+        if let Some(link) = &slice.link {
+            current = format!("#link({})[{}]", string_literal(link), current);
+        }
+        // right after a function call `.` would be read as a field access
+        // and `(` or `[` as more arguments
+        if (typst.ends_with(']') || typst.ends_with(')')) && current.starts_with(['.', '(', '[']) {
+            current = format!("\\{}", current);
+        }
+        typst.push_str(&current);
+        line_start = slice.text.ends_with('\n');
+    }
+    typst.trim_end_matches("\\\n").to_string()
+}
+
+fn render_outline(node: &OutlineNode, indent: &str) -> String {
+    let mut typst = format!("{}- {}\n", indent, escape(&node.text, false));
+    for child in &node.children {
+        typst.push_str(&render_outline(child, &format!("{}  ", indent)));
+    }
+    typst
+}
+
+/// every line of a block shifted under a list item
+pub fn indent_lines(text: &str, indent: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl TypstWriter {
+    /// copy the image next to the source, formats typst cannot read become png
+    fn place_image(&self, cached_path: &Path) -> Result<String, Box<dyn std::error::Error>> {
+        let relative = copy_image_to_rsc(cached_path, &self.rsc_dir_name, &self.rsc_path)?;
+        let extension = cached_path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if matches!(
+            extension.as_str(),
+            "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp"
+        ) {
+            return Ok(relative);
+        }
+        let decoded = image::load_from_memory(&fs::read(cached_path)?)?;
+        let png_name = Path::new(&relative).with_extension("png");
+        let mut png = Cursor::new(vec![]);
+        decoded.write_to(&mut png, image::ImageFormat::Png)?;
+        fs::write(
+            self.rsc_path.join(png_name.file_name().unwrap_or_default()),
+            png.into_inner(),
+        )?;
+        Ok(png_name.to_string_lossy().replace('\\', "/"))
+    }
+
+    fn image(
+        &self,
+        cached_path: &Path,
+        width: Option<u32>,
+        align: Option<ImageAlign>,
+        caption: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let path = self.place_image(cached_path)?;
+        // small images keep their size, large ones fit the text width
+        let width = width.or_else(|| image::image_dimensions(cached_path).ok().map(|(w, _)| w));
+        let width = match image_width_pt(width) {
+            Some(pt) => format!("{:.0}pt", pt),
+            None => "100%".to_string(),
+        };
+        let image = format!("image({}, width: {})", string_literal(&path), width);
+        let content = match caption {
+            Some(caption) => format!("figure({}, caption: [{}])", image, escape(caption, false)),
+            None => image,
+        };
+        let alignment = match align {
+            Some(ImageAlign::Left) => "left",
+            Some(ImageAlign::Right) => "right",
+            _ => "center",
+        };
+        Ok(format!("#align({}, {})\n\n", alignment, content))
+    }
+
+    fn list_items(
+        &self,
+        list_type: ListType,
+        items: &[ListOne],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut typst = String::new();
+        for item in items {
+            let marker = match (list_type, item.done) {
+                (ListType::Ordered, _) => "+",
+                (ListType::Unordered, _) => "-",
+                (ListType::Task, Some(true)) => "- ☒",
+                (ListType::Task, _) => "- ☐",
+            };
+            typst.push_str(&format!(
+                "{} {}\n",
+                marker,
+                render_text_slices(&item.headline, false).replace("\\\n", "\\\n  ")
+            ));
+            // content indented past the marker belongs to the item
+            for following in &item.following {
+                let block = self.block(following)?;
+                typst.push('\n');
+                typst.push_str(&indent_lines(block.trim_end(), "  "));
+                typst.push('\n');
+            }
+        }
+        typst.push('\n');
+        Ok(typst)
+    }
+
+    fn block(&self, block: &Block) -> Result<String, Box<dyn std::error::Error>> {
+        let typst = match block {
+            Block::Text(slices) => {
+                let text = render_text_slices(slices, true);
+                if text.trim().is_empty() {
+                    String::new()
+                } else {
+                    format!("{}\n\n", text)
+                }
+            }
+            Block::Title { text, head_level } => format!(
+                "{} {}\n\n",
+                "=".repeat(head_level_to_usize(head_level)),
+                escape(&text.replace('\n', " "), false)
+            ),
+            Block::List { list_type, items } => self.list_items(*list_type, items)?,
+            Block::Image {
+                cached_path,
+                caption,
+                width,
+                align,
+                ..
+            } => self.image(cached_path, *width, *align, caption.as_deref())?,
+            Block::Code { language, code } => {
+                let fence = code_fence(code);
+                // the tag ends at the first character that cannot be in an identifier
+                let language = language
+                    .to_lowercase()
+                    .replace("++", "pp")
+                    .replace('#', "sharp")
+                    .replace(|c: char| !c.is_alphanumeric(), "");
+                format!(
+                    "{}{}\n{}\n{}\n\n",
+                    fence,
+                    language,
+                    code.trim_end_matches('\n'),
+                    fence
+                )
+            }
+            Block::Sheet { cells } => {
+                let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+                if columns == 0 {
+                    return Ok(String::new());
+                }
+                let row = |row: &Vec<String>, bold: bool| {
+                    (0..columns)
+                        .map(|c| {
+                            let text =
+                                escape(row.get(c).map(String::as_str).unwrap_or_default(), true);
+                            if bold {
+                                format!("strong[{}]", text)
+                            } else {
+                                format!("[{}]", text)
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                let mut typst = format!("#table(\n  columns: {},\n", columns);
+                let mut rows = cells.iter();
+                if let Some(header) = rows.next() {
+                    // repeated on every page the table spans
+                    typst.push_str(&format!("  table.header({}),\n", row(header, true)));
+                }
+                for cells in rows {
+                    typst.push_str(&format!("  {},\n", row(cells, false)));
+                }
+                typst.push_str(")\n\n");
+                typst
+            }
+            Block::Diagram {
+                cached_path,
+                outline,
+                ..
+            } => {
+                let mut typst = self.image(cached_path, None, None, None)?;
+                if let Some(root) = outline {
+                    typst.push_str(&render_outline(root, ""));
+                    typst.push('\n');
+                }
+                typst
+            }
+            Block::Callout { emoji, children } => {
+                let mut content = String::new();
+                if let Some(emoji) = emoji {
+                    content.push_str(&format!("{}\n\n", escape(emoji, true)));
+                }
+                for child in children {
+                    content.push_str(&self.block(child)?);
+                }
+                format!(
+                    "#block(fill: rgb(\"#fff5d6\"), stroke: (left: 3pt + rgb(\"#f0b400\")), inset: 8pt, radius: 2pt, width: 100%)[\n{}\n]\n\n",
+                    content.trim_end()
+                )
+            }
+        };
+        Ok(typst)
+    }
+}
+
+/// Write the document as a typst source, images go to `<stem>.rsc` next to it
+pub fn export_document_to_typst(
+    doc: &Document,
+    typ_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stem = typ_path
+        .file_stem()
+        .ok_or("output path has no file stem")?
+        .to_string_lossy();
+    let rsc_dir_name = format!("{}.rsc", stem);
+    let rsc_path = typ_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(&rsc_dir_name);
+    fs::create_dir_all(&rsc_path)?;
+    let writer = TypstWriter {
+        rsc_dir_name,
+        rsc_path,
+    };
+    let mut body = String::new();
+    for block in &doc.blocks {
+        body.push_str(&writer.block(block)?);
+    }
+
+    let mut typst = String::from("// compile with `typst compile`\n");
+    let mut document_args = vec![];
+    if let Some(title) = &doc.meta.title {
+        document_args.push(format!("title: {}", string_literal(title)));
+    }
+    if let Some(author) = &doc.meta.author {
+        document_args.push(format!("author: {}", string_literal(author)));
+    }
+    typst.push_str(&format!("#set document({})\n", document_args.join(", ")));
+    typst.push_str("#set page(paper: \"a4\", numbering: \"1\")\n");
+    let has_cjk = body
+        .chars()
+        .chain(doc.meta.title.iter().flat_map(|t| t.chars()))
+        .any(is_cjk);
+    if has_cjk {
+        // typst bundles no cjk font, the first installed one is used
+        typst.push_str(
+            "#set text(lang: \"zh\", font: (\"Libertinus Serif\", \"Noto Serif CJK SC\", \"Source Han Serif SC\", \"Songti SC\", \"SimSun\"))\n",
+        );
+    }
+    typst.push_str("#set heading(numbering: none)\n\n");
+    if let Some(title) = &doc.meta.title {
+        typst.push_str(&format!(
+            "#align(center, text(size: 20pt, weight: \"bold\")[{}])\n\n",
+            escape(title, false)
+        ));
+    }
+    typst.push_str(&body);
+    fs::write(typ_path, typst)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slice(text: &str) -> TextSlice {
+        TextSlice {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_text_after_call() {
+        let code = TextSlice {
+            is_code: true,
+            ..slice("x")
+        };
+        let bold = TextSlice {
+            is_bold: true,
+            ..slice("b")
+        };
+        let link = TextSlice {
+            link: Some("https://example.com".to_string()),
+            ..slice("site")
+        };
+        assert_eq!(
+            render_text_slices(&[code, slice("(see above)")], false),
+            "#raw(\"x\")\\(see above)"
+        );
+        assert_eq!(
+            render_text_slices(&[bold, slice(".5 [1]")], false),
+            "#strong[b]\\.5 \\[1\\]"
+        );
+        assert_eq!(
+            render_text_slices(&[link, slice("(2)")], false),
+            "#link(\"https://example.com\")[site]\\(2)"
+        );
+        // a plain `(` is not a call argument
+        assert_eq!(render_text_slices(&[slice("a (b)")], false), "a (b)");
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use rust_xlsxwriter::{Format, Workbook};
+
+use crate::spreadsheet::SheetTab;
+
+/// excel limits sheet names to 31 chars and forbids []:*?/\
+fn worksheet_name(name: &str, index: usize, used: &mut HashSet<String>) -> String {
+    let mut cleaned = name
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_matches('\'')
+        .chars()
+        .take(31)
+        .collect::<String>();
+    // names are case insensitive and must be unique
+    if cleaned.is_empty() || used.contains(&cleaned.to_lowercase()) {
+        cleaned = format!("Sheet{}", index + 1);
+    }
+    used.insert(cleaned.to_lowercase());
+    cleaned
+}
+
+/// `value` as a number when it reads back exactly as written,
+/// so "007", "1e5" and ids too long for a float stay text
+fn exact_number(value: &str) -> Option<f64> {
+    let number = value.parse::<f64>().ok()?;
+    (number.is_finite() && number.to_string() == value).then_some(number)
+}
+
+/// Write every tab into one workbook, keeping cell values, merged ranges and bold cells
+pub fn export_tabs_to_xlsx(
+    tabs: &[SheetTab],
+    xlsx_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let plain = Format::new();
+    let mut used_names = HashSet::new();
+
+    for (index, tab) in tabs.iter().enumerate() {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(worksheet_name(&tab.name, index, &mut used_names))?;
+
+        let grid = &tab.grid;
+        for (r, row) in grid.rows.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                if value.is_empty() && !grid.bold.contains(&(r, c)) {
+                    continue;
+                }
+                let format = if grid.bold.contains(&(r, c)) {
+                    &bold
+                } else {
+                    &plain
+                };
+                // keep numbers numeric so formulas and sorting work in excel
+                match exact_number(value) {
+                    Some(number) => {
+                        worksheet.write_number_with_format(r as u32, c as u16, number, format)?
+                    }
+                    None => {
+                        worksheet.write_string_with_format(r as u32, c as u16, value, format)?
+                    }
+                };
+            }
+        }
+
+        let max_row = grid.rows.len().saturating_sub(1);
+        let max_col = grid
+            .rows
+            .first()
+            .map(|r| r.len())
+            .unwrap_or(0)
+            .saturating_sub(1);
+        for &(first_row, first_col, last_row, last_col) in &grid.merges {
+            // a partly scraped merge can reach past the grid
+            let (last_row, last_col) = (last_row.min(max_row), last_col.min(max_col));
+            if (first_row, first_col) == (last_row, last_col) {
+                continue;
+            }
+            let value = &grid.rows[first_row][first_col];
+            let format = if grid.bold.contains(&(first_row, first_col)) {
+                &bold
+            } else {
+                &plain
+            };
+            if let Err(err) = worksheet.merge_range(
+                first_row as u32,
+                first_col as u16,
+                last_row as u32,
+                last_col as u16,
+                value,
+                format,
+            ) {
+                println!("skip merged range: {:?}", err);
+            }
+        }
+    }
+
+    workbook.save(xlsx_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_number() {
+        assert_eq!(exact_number("42"), Some(42.0));
+        assert_eq!(exact_number("-3.25"), Some(-3.25));
+        assert_eq!(exact_number("007"), None);
+        assert_eq!(exact_number("1e5"), None);
+        assert_eq!(exact_number("1.50"), None);
+        assert_eq!(exact_number("12345678901234567890"), None);
+        assert_eq!(exact_number("NaN"), None);
+        assert_eq!(exact_number("abc"), None);
+    }
+}